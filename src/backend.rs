@@ -0,0 +1,180 @@
+use std::{borrow::Cow, cell::OnceCell, error::Error, mem::size_of};
+
+use glium::{
+    backend::{glutin::headless::Headless, Facade},
+    glutin::{self, dpi::PhysicalSize, event_loop::EventLoop},
+    program::ComputeShader,
+    texture::{ClientFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    uniform,
+    uniforms::{ImageUnitAccess, ImageUnitFormat, UniformBuffer},
+    Texture2d,
+};
+
+use crate::{
+    cpu::{CpuBoard, CpuFun, CpuProgram},
+    glsl,
+    program::{
+        program_from_spec,
+        shader::{self, WORK_GROUP_SIZE},
+        Program, ProgramError, ProgramSpec,
+    },
+    stats::BoardStats,
+};
+
+/// Something that can compile a `ProgramSpec` and hold boards for it.
+///
+/// Boards cross between backends as `CpuBoard`s, which is also how they are
+/// painted on, saved or inspected.
+pub trait Backend {
+    type Board;
+
+    fn program(&self, spec: &ProgramSpec) -> Result<Box<dyn Program<Self::Board>>, ProgramError>;
+    fn upload(&self, board: &CpuBoard) -> Self::Board;
+    fn download(&self, board: &Self::Board) -> CpuBoard;
+
+    /// Statistics of a board, by default computed from a downloaded copy.
+    fn stats(&self, board: &Self::Board) -> BoardStats {
+        BoardStats::of(&self.download(board))
+    }
+}
+
+/// Runs programs as compute shaders on any glium facade, either the window's
+/// `Display` or an offscreen context from [`GlBackend::headless`].
+pub struct GlBackend<F> {
+    facade: F,
+    // Compiled on first use.
+    stats_shader: OnceCell<ComputeShader>,
+    // Keeps the connection an offscreen context was created on alive.
+    _event_loop: Option<EventLoop<()>>,
+}
+
+impl<F: Facade> GlBackend<F> {
+    pub fn new(facade: F) -> Self {
+        Self {
+            facade,
+            stats_shader: OnceCell::new(),
+            _event_loop: None,
+        }
+    }
+
+    pub fn facade(&self) -> &F {
+        &self.facade
+    }
+}
+
+impl GlBackend<Headless> {
+    /// Creates an OpenGL context without a window. This still needs a
+    /// windowing system to talk to (an X server, Wayland compositor or
+    /// Xvfb), use `CpuBackend` where there is none.
+    pub fn headless() -> Result<Self, Box<dyn Error>> {
+        let event_loop = EventLoop::new();
+        let context = glutin::ContextBuilder::new().build_headless(&event_loop, PhysicalSize::new(1, 1))?;
+        let facade = Headless::new(unsafe { context.treat_as_current() })?;
+        Ok(Self {
+            facade,
+            stats_shader: OnceCell::new(),
+            _event_loop: Some(event_loop),
+        })
+    }
+}
+
+impl<F: Facade> Backend for GlBackend<F> {
+    type Board = Texture2d;
+
+    fn program(&self, spec: &ProgramSpec) -> Result<Box<dyn Program<Texture2d>>, ProgramError> {
+        program_from_spec(spec.clone(), &self.facade)
+    }
+
+    fn upload(&self, board: &CpuBoard) -> Texture2d {
+        let image = RawImage2d {
+            data: Cow::Borrowed(&board.data[..]),
+            width: board.width,
+            height: board.height,
+            format: ClientFormat::F32F32F32F32,
+        };
+        Texture2d::with_format(
+            &self.facade,
+            image,
+            UncompressedFloatFormat::F32F32F32F32,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap()
+    }
+
+    fn download(&self, board: &Texture2d) -> CpuBoard {
+        let rows: Vec<Vec<(f32, f32, f32, f32)>> = unsafe { board.unchecked_read() };
+        CpuBoard {
+            width: board.width(),
+            height: board.height(),
+            data: rows
+                .into_iter()
+                .flatten()
+                .flat_map(|(r, g, b, a)| [r, g, b, a])
+                .collect(),
+        }
+    }
+
+    /// Reduces the board on the GPU, reading back four `vec4`s per work group
+    /// instead of the whole board.
+    fn stats(&self, board: &Texture2d) -> BoardStats {
+        let shader = self
+            .stats_shader
+            .get_or_init(|| ComputeShader::from_source(&self.facade, &shader::stats_shader_src()).unwrap());
+        let (width, height) = (board.width(), board.height());
+        let groups = (width.div_ceil(WORK_GROUP_SIZE.0), height.div_ceil(WORK_GROUP_SIZE.1));
+        let len = (groups.0 * groups.1 * 4) as usize;
+        let partials: UniformBuffer<[[f32; 4]]> =
+            UniformBuffer::empty_unsized(&self.facade, len * size_of::<[f32; 4]>()).unwrap();
+        let image_unit = board
+            .image_unit(ImageUnitFormat::RGBA32F)
+            .unwrap()
+            .set_access(ImageUnitAccess::Read);
+        shader.execute(
+            uniform! { uWidth: width, uHeight: height, uTexture: image_unit, uPartials: &partials },
+            groups.0,
+            groups.1,
+            1,
+        );
+        BoardStats::from_partials(&partials.read().unwrap())
+    }
+}
+
+/// Function turning a spec's `fun` into Rust code for `CpuProgram`.
+pub type FunCompiler = dyn Fn(&ProgramSpec) -> Result<CpuFun, ProgramError>;
+
+/// Runs programs with the CPU reference implementation, no GPU or display needed.
+pub struct CpuBackend {
+    compile: Box<FunCompiler>,
+}
+
+impl CpuBackend {
+    pub fn new(compile: impl Fn(&ProgramSpec) -> Result<CpuFun, ProgramError> + 'static) -> Self {
+        Self {
+            compile: Box::new(compile),
+        }
+    }
+}
+
+impl Default for CpuBackend {
+    /// Evaluates `fun` with the GLSL interpreter in `glsl`.
+    fn default() -> Self {
+        Self::new(glsl::compile_fun)
+    }
+}
+
+impl Backend for CpuBackend {
+    type Board = CpuBoard;
+
+    fn program(&self, spec: &ProgramSpec) -> Result<Box<dyn Program<CpuBoard>>, ProgramError> {
+        let fun = (self.compile)(spec)?;
+        Ok(Box::new(CpuProgram::new(spec.clone(), fun)?))
+    }
+
+    fn upload(&self, board: &CpuBoard) -> CpuBoard {
+        board.clone()
+    }
+
+    fn download(&self, board: &CpuBoard) -> CpuBoard {
+        board.clone()
+    }
+}
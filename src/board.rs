@@ -1,4 +1,14 @@
-pub(crate) fn empty_board(width: u32, height: u32) -> Vec<Vec<f32>> {
+use std::{io, path::PathBuf, str::FromStr};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    cpu::CpuBoard,
+    init::{self, ImageSource},
+    program::ProgramSpec,
+};
+
+pub fn empty_board(width: u32, height: u32) -> Vec<Vec<f32>> {
     let mut board: Vec<Vec<f32>> = Vec::with_capacity(height.try_into().unwrap());
     for _y in 0..height {
         let mut row = Vec::with_capacity(width.try_into().unwrap());
@@ -10,22 +20,99 @@ pub(crate) fn empty_board(width: u32, height: u32) -> Vec<Vec<f32>> {
     board
 }
 
-pub(crate) fn random_board(width: u32, height: u32) -> Vec<Vec<f32>> {
+/// Same `seed` and size always give the same board.
+pub fn random_board(width: u32, height: u32, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
     let mut board: Vec<Vec<f32>> = empty_board(width, height);
     for row in board.iter_mut() {
         for cell in row {
-            *cell = rand::random();
+            *cell = rng.gen();
         }
     }
     board
 }
 
-pub(crate) fn random_board_binary(width: u32, height: u32) -> Vec<Vec<f32>> {
+pub fn random_board_binary(width: u32, height: u32, seed: u64) -> Vec<Vec<f32>> {
+    let mut rng = StdRng::seed_from_u64(seed);
     let mut board: Vec<Vec<f32>> = empty_board(width, height);
     for row in board.iter_mut() {
         for cell in row {
-            *cell = if rand::random::<f32>() > 0.5 { 1. } else { 0. };
+            *cell = if rng.gen::<f32>() > 0.5 { 1. } else { 0. };
         }
     }
     board
 }
+
+/// A fresh seed for when none was given, print it so the run can be repeated.
+pub fn random_seed() -> u64 {
+    // Kept below 2^53 so it survives a round trip through YAML or JSON numbers.
+    rand::random::<u64>() >> 11
+}
+
+/// How a board starts out, as given on the command line: `program` (the
+/// `init:` section, empty without one), `empty`, `random`, `random-binary` or
+/// the path of an image or a board saved with `formats::save`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InitialBoard {
+    Program,
+    Empty,
+    Random,
+    RandomBinary,
+    File(ImageSource),
+}
+
+impl FromStr for InitialBoard {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "program" => InitialBoard::Program,
+            "empty" => InitialBoard::Empty,
+            "random" => InitialBoard::Random,
+            "random-binary" => InitialBoard::RandomBinary,
+            path => InitialBoard::File(ImageSource::new(PathBuf::from(path))),
+        })
+    }
+}
+
+impl InitialBoard {
+    /// `seed` is only used by random fills.
+    pub fn board(&self, spec: &ProgramSpec, seed: u64) -> io::Result<CpuBoard> {
+        let (width, height) = (spec.width, spec.height);
+        Ok(match self {
+            InitialBoard::Program => init::initial_board(&spec.init, width, height, seed)?,
+            InitialBoard::Empty => CpuBoard::new(width, height),
+            InitialBoard::Random => CpuBoard::from_values(&random_board(width, height, seed)),
+            InitialBoard::RandomBinary => CpuBoard::from_values(&random_board_binary(width, height, seed)),
+            InitialBoard::File(source) => init::image_board(source, width, height)?,
+        })
+    }
+
+    /// Whether `seed` makes a difference, i.e. whether it's worth printing.
+    pub fn uses_seed(&self, spec: &ProgramSpec) -> bool {
+        match self {
+            InitialBoard::Program => init::uses_seed(&spec.init),
+            InitialBoard::Random | InitialBoard::RandomBinary => true,
+            InitialBoard::Empty | InitialBoard::File(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_seed_decides_random_boards() {
+        for random in [random_board, random_board_binary] {
+            let board = random(8, 6, 42);
+            assert_eq!((board.len(), board[0].len()), (6, 8));
+            assert_eq!(board, random(8, 6, 42));
+            assert_ne!(board, random(8, 6, 43));
+        }
+        let binary = random_board_binary(8, 6, 42);
+        assert!(binary.iter().flatten().all(|&cell| cell == 0. || cell == 1.));
+        let board = random_board(8, 6, 42);
+        assert!(board.iter().flatten().all(|cell| (0. ..1.).contains(cell)));
+    }
+}
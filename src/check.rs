@@ -0,0 +1,61 @@
+//! `check` subcommand: parses and validates program files without running
+//! them, e.g. a whole directory before committing it.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const USAGE: &str = "usage: gpu_convolution check <program.yaml | directory>...";
+
+/// Runs the subcommand with the arguments following `check`, returning the
+/// exit code.
+pub fn main(args: &[String]) -> i32 {
+    if args.is_empty() {
+        eprintln!("{}", USAGE);
+        return 2;
+    }
+
+    let mut files = Vec::new();
+    for arg in args {
+        let path = PathBuf::from(arg);
+        if path.is_dir() {
+            match yaml_files(&path) {
+                Ok(found) => files.extend(found),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    return 1;
+                }
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    let mut failed = 0;
+    for path in &files {
+        if let Err(e) = crate::load_spec(&path.to_string_lossy()) {
+            println!("{}: {}", path.display(), e);
+            failed += 1;
+        }
+    }
+    println!("{} programs checked, {} with errors", files.len(), failed);
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Program files in `dir`, sorted by name.
+pub(crate) fn yaml_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "yaml" || ext == "yml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,356 @@
+use crate::program::{kind_mismatch, EdgeSolution, Params, Program, ProgramError, ProgramKind, ProgramSpec};
+
+/// RGBA board kept in memory, rows stored bottom to top like the rows of the
+/// board texture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuBoard {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl CpuBoard {
+    /// A black board with alpha set to 1, same as the board `main` starts with.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: [0., 0., 0., 1.].repeat((width * height) as usize),
+        }
+    }
+
+    /// Builds a board from single values (as returned by the functions in
+    /// `board`), which end up in the red channel.
+    pub fn from_values(values: &[Vec<f32>]) -> Self {
+        let height = values.len() as u32;
+        let width = values.first().map_or(0, |row| row.len() as u32);
+        let mut board = Self::new(width, height);
+        for (y, row) in values.iter().enumerate() {
+            for (x, val) in row.iter().enumerate() {
+                board.data[(y * width as usize + x) * 4] = *val;
+            }
+        }
+        board
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [f32; 4] {
+        let i = self.offset(x, y);
+        [self.data[i], self.data[i + 1], self.data[i + 2], self.data[i + 3]]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, pixel: [f32; 4]) {
+        let i = self.offset(x, y);
+        self.data[i..i + 4].copy_from_slice(&pixel);
+    }
+
+    /// The board centered on a black board of another size, cut off where it
+    /// doesn't fit, like an image with `--fit crop`.
+    pub fn resized(&self, width: u32, height: u32) -> Self {
+        let mut board = Self::new(width, height);
+        let dx = (width as i64 - self.width as i64).div_euclid(2);
+        let dy = (height as i64 - self.height as i64).div_euclid(2);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x as i64 - dx, y as i64 - dy);
+                if (0..self.width as i64).contains(&sx) && (0..self.height as i64).contains(&sy) {
+                    board.set(x, y, self.get(sx as u32, sy as u32));
+                }
+            }
+        }
+        board
+    }
+
+    /// The board scaled to another size, each cell taking the nearest old one
+    /// so values stay as they were, e.g. 0 or 1 on a binary board.
+    pub fn resampled(&self, width: u32, height: u32) -> Self {
+        let mut board = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let sx = ((x as f64 + 0.5) * self.width as f64 / width as f64) as u32;
+                let sy = ((y as f64 + 0.5) * self.height as f64 / height as f64) as u32;
+                board.set(x, y, self.get(sx.min(self.width - 1), sy.min(self.height - 1)));
+            }
+        }
+        board
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+}
+
+/// `float fun(float x, float prev)`, the last argument holds the values of
+/// the program's `params`.
+pub type ValFun = dyn Fn(f32, f32, &[f32]) -> f32 + Send + Sync;
+/// `vec3 fun(vec3 v, vec3 prev)`, the last argument holds the values of the
+/// program's `params`.
+pub type RgbFun = dyn Fn([f32; 3], [f32; 3], &[f32]) -> [f32; 3] + Send + Sync;
+
+/// Rust implementation of a program's `fun`, with the signature of the GLSL
+/// function for the program type.
+pub enum CpuFun {
+    Val(Box<ValFun>),
+    Rgb(Box<RgbFun>),
+}
+
+#[derive(Clone, Copy)]
+enum Edge {
+    Clamp,
+    Wrap,
+    Value([f32; 4]),
+}
+
+impl From<&EdgeSolution<f32>> for Edge {
+    fn from(edge: &EdgeSolution<f32>) -> Self {
+        match edge {
+            EdgeSolution::Clamp => Edge::Clamp,
+            EdgeSolution::Wrap => Edge::Wrap,
+            EdgeSolution::Value(v) => Edge::Value([*v, 0., 0., 1.]),
+        }
+    }
+}
+
+impl From<&EdgeSolution<(f32, f32, f32)>> for Edge {
+    fn from(edge: &EdgeSolution<(f32, f32, f32)>) -> Self {
+        match edge {
+            EdgeSolution::Clamp => Edge::Clamp,
+            EdgeSolution::Wrap => Edge::Wrap,
+            EdgeSolution::Value((r, g, b)) => Edge::Value([*r, *g, *b, 1.]),
+        }
+    }
+}
+
+/// Reference implementation of the compute shaders in `program`, producing
+/// the same board as the GL programs for a given `ProgramSpec`.
+pub struct CpuProgram {
+    spec: ProgramSpec,
+    edge: Edge,
+    fun: CpuFun,
+}
+
+impl CpuProgram {
+    pub fn new(spec: ProgramSpec, fun: CpuFun) -> Result<Self, ProgramError> {
+        let edge = match (&spec.kind, &fun) {
+            (ProgramKind::Val { edge, .. }, CpuFun::Val(_)) => Edge::from(edge),
+            (ProgramKind::Rgb { edge, .. } | ProgramKind::Sym { edge, .. }, CpuFun::Rgb(_)) => {
+                Edge::from(edge)
+            }
+            (ProgramKind::Val { .. }, _) => return Err(kind_mismatch("`rgb` or `sym`")),
+            _ => return Err(kind_mismatch("`val`")),
+        };
+        Ok(Self { spec, edge, fun })
+    }
+
+    pub fn spec(&self) -> &ProgramSpec {
+        &self.spec
+    }
+
+    /// The val shader stores `fun` in green and then swaps red and green, so
+    /// after a step red holds the new value and green the previous one.
+    fn step_val(&self, board: &mut CpuBoard, kernel: &[Vec<f32>], fun: &ValFun) {
+        let (ax, ay) = (self.spec.anchor.0 as i64, self.spec.anchor.1 as i64);
+        let mut next = Vec::with_capacity((board.width * board.height) as usize);
+        for y in 0..board.height {
+            for x in 0..board.width {
+                let mut sum = 0.;
+                for (ky, row) in kernel.iter().enumerate() {
+                    for (kx, k) in row.iter().enumerate() {
+                        let sx = x as i64 + kx as i64 - ax;
+                        let sy = y as i64 + ky as i64 - ay;
+                        sum += sample(board, self.edge, sx, sy)[0] * k;
+                    }
+                }
+                next.push(fun(sum, board.get(x, y)[0], self.spec.params.values()));
+            }
+        }
+        for (i, val) in next.into_iter().enumerate() {
+            let pixel = &mut board.data[i * 4..i * 4 + 2];
+            pixel[1] = pixel[0];
+            pixel[0] = val;
+        }
+    }
+
+    fn step_rgb(&self, board: &mut CpuBoard, kernel: &[Vec<f32>], fun: &RgbFun) {
+        let (ax, ay) = (self.spec.anchor.0 as i64, self.spec.anchor.1 as i64);
+        let buffer = board.clone();
+        for y in 0..board.height {
+            for x in 0..board.width {
+                let mut sum = [0.; 3];
+                for (ky, row) in kernel.iter().enumerate() {
+                    for (kx, k) in row.iter().enumerate() {
+                        let sx = x as i64 + kx as i64 - ax;
+                        let sy = y as i64 + ky as i64 - ay;
+                        let s = sample(&buffer, self.edge, sx, sy);
+                        for c in 0..3 {
+                            sum[c] += s[c] * k;
+                        }
+                    }
+                }
+                let prev = buffer.get(x, y);
+                let [r, g, b] = fun(sum, [prev[0], prev[1], prev[2]], self.spec.params.values());
+                board.set(x, y, [r, g, b, prev[3]]);
+            }
+        }
+    }
+
+    /// One pass of the separable sym shader along `dir`, `fun` is applied
+    /// after each pass.
+    fn step_sym(
+        &self,
+        board: &mut CpuBoard,
+        kernel: &[f32],
+        dir: (i64, i64),
+        anchor: u32,
+        fun: &RgbFun,
+    ) {
+        let buffer = board.clone();
+        for y in 0..board.height {
+            for x in 0..board.width {
+                let mut sum = [0.; 3];
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as i64 - anchor as i64;
+                    let s = sample(&buffer, self.edge, x as i64 + offset * dir.0, y as i64 + offset * dir.1);
+                    for c in 0..3 {
+                        sum[c] += s[c] * weight;
+                    }
+                }
+                let prev = buffer.get(x, y);
+                let [r, g, b] = fun(sum, [prev[0], prev[1], prev[2]], self.spec.params.values());
+                board.set(x, y, [r, g, b, prev[3]]);
+            }
+        }
+    }
+}
+
+impl Program<CpuBoard> for CpuProgram {
+    fn step(&self, board: &mut CpuBoard) {
+        match (&self.spec.kind, &self.fun) {
+            (ProgramKind::Val { kernel, .. }, CpuFun::Val(fun)) => self.step_val(board, kernel, fun),
+            (ProgramKind::Rgb { kernel, .. }, CpuFun::Rgb(fun)) => self.step_rgb(board, kernel, fun),
+            (ProgramKind::Sym { kernel_hor, kernel_ver, .. }, CpuFun::Rgb(fun)) => {
+                let (ax, ay) = self.spec.anchor;
+                self.step_sym(board, kernel_hor, (1, 0), ax, fun);
+                self.step_sym(board, kernel_ver, (0, 1), ay, fun);
+            }
+            _ => unreachable!("checked in CpuProgram::new"),
+        }
+    }
+
+    fn get_dimensions(&self) -> (u32, u32) {
+        (self.spec.width, self.spec.height)
+    }
+
+    fn params(&self) -> &Params {
+        &self.spec.params
+    }
+
+    fn params_mut(&mut self) -> &mut Params {
+        &mut self.spec.params
+    }
+}
+
+/// CPU counterpart of the `csample` function generated by `EdgeSolution::csample_src`.
+fn sample(board: &CpuBoard, edge: Edge, x: i64, y: i64) -> [f32; 4] {
+    let (w, h) = (board.width as i64, board.height as i64);
+    let (x, y) = match edge {
+        Edge::Clamp => (x.clamp(0, w - 1), y.clamp(0, h - 1)),
+        Edge::Wrap => (x.rem_euclid(w), y.rem_euclid(h)),
+        Edge::Value(val) => {
+            if x < 0 || y < 0 || x >= w || y >= h {
+                return val;
+            }
+            (x, y)
+        }
+    };
+    board.get(x as u32, y as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{Backend, CpuBackend};
+
+    /// A 4x2 board whose cells hold `x + 4 * y`.
+    fn numbered() -> CpuBoard {
+        CpuBoard::from_values(&[vec![0., 1., 2., 3.], vec![4., 5., 6., 7.]])
+    }
+
+    fn red(board: &CpuBoard) -> Vec<Vec<f32>> {
+        (0..board.height).map(|y| (0..board.width).map(|x| board.get(x, y)[0]).collect()).collect()
+    }
+
+    #[test]
+    fn resized_crops_and_pads() {
+        let board = numbered();
+        // Cut off on both sides, an odd difference losing one more cell on
+        // the left and bottom.
+        assert_eq!(red(&board.resized(2, 2)), [[1., 2.], [5., 6.]]);
+        assert_eq!(red(&board.resized(3, 1)), [[5., 6., 7.]]);
+        // Centered on black, the odd cell going to the right and top.
+        let padded = board.resized(6, 4);
+        assert_eq!(
+            red(&padded),
+            [
+                [0., 0., 0., 0., 0., 0.],
+                [0., 0., 1., 2., 3., 0.],
+                [0., 4., 5., 6., 7., 0.],
+                [0., 0., 0., 0., 0., 0.],
+            ]
+        );
+        assert_eq!(padded.get(0, 0), [0., 0., 0., 1.]);
+        assert_eq!(padded.get(1, 1), board.get(0, 0));
+        assert_eq!(red(&board.resized(5, 3)), [[0., 1., 2., 3., 0.], [4., 5., 6., 7., 0.], [0.; 5]]);
+        assert_eq!(board.resized(4, 2).data, board.data);
+    }
+
+    #[test]
+    fn resampled_takes_the_nearest_cell() {
+        let board = numbered();
+        assert_eq!(red(&board.resampled(2, 1)), [[5., 7.]]);
+        assert_eq!(red(&board.resampled(3, 2)), [[0., 2., 3.], [4., 6., 7.]]);
+        assert_eq!(
+            red(&board.resampled(8, 4)),
+            [
+                [0., 0., 1., 1., 2., 2., 3., 3.],
+                [0., 0., 1., 1., 2., 2., 3., 3.],
+                [4., 4., 5., 5., 6., 6., 7., 7.],
+                [4., 4., 5., 5., 6., 6., 7., 7.],
+            ]
+        );
+        assert_eq!(board.resampled(4, 2).data, board.data);
+    }
+
+    /// One step of a val program whose 2x2 kernel only weighs its first cell,
+    /// on a wrapping 4x3 board with a different value in every cell.
+    fn step_with_anchor(anchor: [u32; 2]) -> (CpuBoard, CpuBoard) {
+        let src = format!(
+            "screen: [4, 3]\ntype: val\nedge: wrap\nkernel: [[1, 0], [0, 0]]\nanchor: {:?}\nfun: return x;\n",
+            anchor
+        );
+        let spec: ProgramSpec = src.parse().unwrap();
+        let values: Vec<Vec<f32>> = (0..3).map(|y| (0..4).map(|x| (x + 4 * y) as f32).collect()).collect();
+        let start = CpuBoard::from_values(&values);
+        let backend = CpuBackend::default();
+        let mut board = backend.upload(&start);
+        backend.program(&spec).unwrap().step(&mut board);
+        (start, backend.download(&board))
+    }
+
+    #[test]
+    fn anchor_shifts_the_kernel() {
+        // Anchored on the weighted cell, every cell keeps its value.
+        let red = |board: &CpuBoard| board.data.chunks(4).map(|p| p[0]).collect::<Vec<_>>();
+        let (start, board) = step_with_anchor([0, 0]);
+        assert_eq!(red(&board), red(&start));
+        // Anchored one column right of it, each cell takes its left neighbour.
+        let (start, board) = step_with_anchor([1, 0]);
+        for (x, y) in (0..4).flat_map(|x| (0..3).map(move |y| (x, y))) {
+            assert_eq!(board.get(x, y)[0], start.get((x + 3) % 4, y)[0]);
+        }
+        // And one row further, the neighbour one row down as well.
+        let (start, board) = step_with_anchor([1, 1]);
+        for (x, y) in (0..4).flat_map(|x| (0..3).map(move |y| (x, y))) {
+            assert_eq!(board.get(x, y)[0], start.get((x + 3) % 4, (y + 2) % 3)[0]);
+        }
+    }
+}
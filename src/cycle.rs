@@ -0,0 +1,210 @@
+//! Detecting boards that stopped changing or repeat.
+//!
+//! `CycleDetector` hashes the whole board (all four channels) every few steps
+//! and remembers the step each hash was first seen at. When a hash comes back
+//! the simulation is in a cycle, since programs are deterministic. Checking
+//! every N steps only sees periods that are multiples of N.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use crate::cpu::CpuBoard;
+
+/// Number of hashes kept, older ones are forgotten so long runs don't grow
+/// without bound.
+const HISTORY: usize = 1 << 16;
+
+/// A repeat found by `CycleDetector`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cycle {
+    /// Steps between repeats, 1 for a board that doesn't change.
+    pub period: u64,
+    /// First checked step that is part of the cycle.
+    pub since: u64,
+}
+
+impl Cycle {
+    pub fn is_static(&self) -> bool {
+        self.period == 1
+    }
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_static() {
+            write!(f, "static since step {}", self.since)
+        } else {
+            write!(f, "repeats every {} steps since step {}", self.period, self.since)
+        }
+    }
+}
+
+/// What to do once a cycle is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CycleAction {
+    Print,
+    Pause,
+    /// Start over from a new random board.
+    Reseed,
+}
+
+impl FromStr for CycleAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "print" => Ok(CycleAction::Print),
+            "pause" => Ok(CycleAction::Pause),
+            "reseed" => Ok(CycleAction::Reseed),
+            _ => Err(format!("unknown cycle action `{}`, expected `print`, `pause` or `reseed`", s)),
+        }
+    }
+}
+
+pub struct CycleDetector {
+    every: u32,
+    seen: HashMap<u64, u64>,
+    order: VecDeque<u64>,
+    /// Reported cycles are not reported again until `reset`.
+    found: bool,
+}
+
+impl CycleDetector {
+    pub fn new(every: u32) -> Self {
+        Self {
+            every,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+            found: false,
+        }
+    }
+
+    /// Whether `check` wants the board after `step`.
+    pub fn due(&self, step: u64) -> bool {
+        !self.found && step.is_multiple_of(self.every.into())
+    }
+
+    /// Records the board after `step`, returning the cycle the first time one
+    /// is found.
+    pub fn check(&mut self, step: u64, board: &CpuBoard) -> Option<Cycle> {
+        let mut hasher = DefaultHasher::new();
+        (board.width, board.height).hash(&mut hasher);
+        for c in &board.data {
+            c.to_bits().hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+
+        if let Some(&since) = self.seen.get(&hash) {
+            self.found = true;
+            return Some(Cycle {
+                period: step - since,
+                since,
+            });
+        }
+        self.seen.insert(hash, step);
+        self.order.push_back(hash);
+        if self.order.len() > HISTORY {
+            let oldest = self.order.pop_front().unwrap();
+            self.seen.remove(&oldest);
+        }
+        None
+    }
+
+    /// Forgets all boards, for when the board was replaced or painted on.
+    pub fn reset(&mut self) {
+        self.seen.clear();
+        self.order.clear();
+        self.found = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(value: f32) -> CpuBoard {
+        CpuBoard::from_values(&[vec![value, 0.], vec![0., 1.]])
+    }
+
+    #[test]
+    fn static_board() {
+        let mut cycles = CycleDetector::new(1);
+        assert_eq!(cycles.check(0, &board(0.)), None);
+        assert_eq!(cycles.check(1, &board(1.)), None);
+        let cycle = cycles.check(2, &board(1.)).unwrap();
+        assert_eq!(cycle, Cycle { period: 1, since: 1 });
+        assert!(cycle.is_static());
+        assert_eq!(cycle.to_string(), "static since step 1");
+    }
+
+    #[test]
+    fn period() {
+        let mut cycles = CycleDetector::new(1);
+        for (step, value) in [0., 1., 2., 3., 1.].into_iter().enumerate() {
+            let found = cycles.check(step as u64, &board(value));
+            assert_eq!(found, (step == 4).then_some(Cycle { period: 3, since: 1 }));
+        }
+        assert_eq!(Cycle { period: 3, since: 1 }.to_string(), "repeats every 3 steps since step 1");
+    }
+
+    #[test]
+    fn every_few_steps() {
+        let mut cycles = CycleDetector::new(3);
+        assert!(cycles.due(0) && !cycles.due(1) && !cycles.due(2) && cycles.due(3));
+        // A period 2 board looks like period 6 when every third step is checked.
+        let value = |step: u64| (step % 2) as f32;
+        let mut found = Vec::new();
+        for step in 0..20 {
+            if cycles.due(step) {
+                found.extend(cycles.check(step, &board(value(step))));
+            }
+        }
+        assert_eq!(found, [Cycle { period: 6, since: 0 }]);
+    }
+
+    #[test]
+    fn reported_once_until_reset() {
+        let mut cycles = CycleDetector::new(1);
+        cycles.check(0, &board(0.));
+        assert!(cycles.check(1, &board(0.)).is_some());
+        assert!(!cycles.due(2));
+        cycles.reset();
+        assert!(cycles.due(2));
+        assert_eq!(cycles.check(2, &board(0.)), None);
+        assert_eq!(cycles.check(3, &board(0.)), Some(Cycle { period: 1, since: 2 }));
+    }
+
+    #[test]
+    fn any_channel_and_size_counts() {
+        let mut cycles = CycleDetector::new(1);
+        let mut blue = board(0.);
+        blue.set(1, 1, [0., 0., 0.5, 1.]);
+        cycles.check(0, &board(0.));
+        assert_eq!(cycles.check(1, &blue), None);
+        assert_eq!(cycles.check(2, &CpuBoard::new(4, 1)), None);
+        assert_eq!(cycles.check(3, &CpuBoard::new(1, 4)), None);
+    }
+
+    #[test]
+    fn old_boards_are_forgotten() {
+        let mut cycles = CycleDetector::new(1);
+        for step in 0..=HISTORY as u64 {
+            assert_eq!(cycles.check(step, &board(step as f32)), None);
+        }
+        // Step 0 fell out of the history, step 1 is still there.
+        assert_eq!(cycles.check(HISTORY as u64 + 1, &board(0.)), None);
+        assert!(cycles.check(HISTORY as u64 + 2, &board(2.)).is_some());
+    }
+
+    #[test]
+    fn actions() {
+        assert_eq!("reseed".parse(), Ok(CycleAction::Reseed));
+        assert_eq!("pause".parse(), Ok(CycleAction::Pause));
+        assert_eq!("print".parse(), Ok(CycleAction::Print));
+        assert!("stop".parse::<CycleAction>().is_err());
+    }
+}
@@ -0,0 +1,527 @@
+//! Reading and writing boards as image files.
+//!
+//! Boards are stored as PFM (portable float map), which keeps the exact `f32`
+//! values of the red, green and blue channels. PFM rows run bottom to top,
+//! same as `CpuBoard`, so rows are copied as they are. Alpha is not stored and
+//! reads back as 1.
+//!
+//! PNG and PPM/PGM images can be loaded too, with values scaled to 0..1, gray
+//! copied to red, green and blue, and rows flipped to run bottom to top.
+//!
+//! Boards can also be saved as 8-bit PNG, with values clamped to 0..1, and as
+//! NumPy `.npy` arrays of all four channels, shaped `(height, width, 4)` with
+//! rows top to bottom like the PNG.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::cpu::CpuBoard;
+
+/// Largest board images are read into, 16384 x 16384 cells.
+const MAX_CELLS: usize = 1 << 28;
+
+/// A format boards can be saved in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Pfm,
+    Png,
+    Npy,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Pfm => "pfm",
+            Format::Png => "png",
+            Format::Npy => "npy",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_string_lossy().to_lowercase().parse().ok()
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pfm" => Ok(Format::Pfm),
+            "png" => Ok(Format::Png),
+            "npy" => Ok(Format::Npy),
+            _ => Err(format!("unknown format `{}`, expected `pfm`, `png` or `npy`", s)),
+        }
+    }
+}
+
+/// Saves a board as PFM, PNG or NPY, picked by extension.
+pub fn save(path: &Path, board: &CpuBoard) -> io::Result<()> {
+    let format = Format::from_path(path).ok_or_else(|| invalid("unknown format, expected .pfm, .png or .npy"))?;
+    let mut file = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Pfm => write_pfm(&mut file, board)?,
+        Format::Png => write_png(&mut file, board)?,
+        Format::Npy => write_npy(&mut file, board)?,
+    }
+    file.flush()
+}
+
+/// Loads a PFM, PNG or PPM/PGM file, picked by extension.
+pub fn load(path: &Path) -> io::Result<CpuBoard> {
+    let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    let mut file = BufReader::new(File::open(path)?);
+    match ext.as_deref() {
+        Some("pfm") => read_pfm(&mut file),
+        Some("png") => read_png(file),
+        Some("ppm" | "pgm" | "pnm") => read_pnm(&mut file),
+        _ => Err(invalid("unknown image format, expected .pfm, .png, .ppm or .pgm")),
+    }
+}
+
+pub fn write_pfm(w: &mut impl Write, board: &CpuBoard) -> io::Result<()> {
+    // A negative scale marks little-endian data.
+    write!(w, "PF\n{} {}\n-1.0\n", board.width, board.height)?;
+    for pixel in board.data.chunks_exact(4) {
+        for c in &pixel[..3] {
+            w.write_all(&c.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// 8-bit RGB, values clamped to 0..1.
+pub fn write_png(w: &mut impl Write, board: &CpuBoard) -> io::Result<()> {
+    write_rgb8_png(w, board.width, board.height, &rgb8(board, 1))
+}
+
+/// Writes bytes as returned by `rgb8` as a PNG.
+pub fn write_rgb8_png(w: &mut impl Write, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::from)?;
+    writer.write_image_data(data).map_err(io::Error::from)
+}
+
+/// Red, green and blue as bytes, clamped to 0..1, rows top to bottom as in
+/// image files and the window. Each byte pixel averages a `scale` x `scale`
+/// block of cells, leftover cells at the right and top edges are dropped.
+pub fn rgb8(board: &CpuBoard, scale: u32) -> Vec<u8> {
+    let (width, height) = scaled_size(board, scale);
+    let mut data = Vec::with_capacity((width * height * 3) as usize);
+    let area = (scale * scale) as f32;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let mut sum = [0.; 3];
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let pixel = board.get(x * scale + dx, y * scale + dy);
+                    for c in 0..3 {
+                        sum[c] += pixel[c].clamp(0., 1.);
+                    }
+                }
+            }
+            data.extend(sum.iter().map(|c| (c / area * 255.).round() as u8));
+        }
+    }
+    data
+}
+
+/// Size of the images `rgb8` returns.
+pub fn scaled_size(board: &CpuBoard, scale: u32) -> (u32, u32) {
+    (board.width / scale, board.height / scale)
+}
+
+/// Little-endian `f32` array of shape `(height, width, 4)`, in format version
+/// 1.0.
+pub fn write_npy(w: &mut impl Write, board: &CpuBoard) -> io::Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}, 4), }}",
+        board.height, board.width
+    );
+    // Magic, version and length take 10 bytes, the data starts 64-byte aligned
+    // after the newline ending the header.
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+    w.write_all(b"\x93NUMPY\x01\x00")?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for y in (0..board.height).rev() {
+        for x in 0..board.width {
+            for c in board.get(x, y) {
+                w.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn read_pfm(r: &mut impl BufRead) -> io::Result<CpuBoard> {
+    let magic = header_token(r, "PFM")?;
+    let channels = match magic.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let width = header_number(r, "PFM", "size")?;
+    let height = header_number(r, "PFM", "size")?;
+    let scale: f32 = header_token(r, "PFM")?.parse().map_err(|_| invalid("bad PFM scale"))?;
+
+    let bytes = read_data(r, "PFM", width, height, channels * 4)?;
+    let mut values = bytes.chunks_exact(4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if scale < 0. {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        }
+    });
+
+    let mut board = CpuBoard::new(width, height);
+    for pixel in board.data.chunks_exact_mut(4) {
+        for c in pixel.iter_mut().take(channels) {
+            *c = values.next().unwrap();
+        }
+    }
+    Ok(board)
+}
+
+pub fn read_png(r: impl io::Read) -> io::Result<CpuBoard> {
+    let mut decoder = png::Decoder::new(r);
+    // Palettes and low bit depths to 8 bits, transparency chunks to alpha.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| invalid(&e.to_string()))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| invalid(&e.to_string()))?;
+
+    let channels = info.color_type.samples();
+    let (bytes, max) = match info.bit_depth {
+        png::BitDepth::Sixteen => (2, 65535.),
+        _ => (1, 255.),
+    };
+    let samples: Vec<f32> = buf[..info.buffer_size()]
+        .chunks_exact(bytes)
+        .map(|b| if bytes == 2 { u16::from_be_bytes([b[0], b[1]]) as f32 } else { b[0] as f32 } / max)
+        .collect();
+    Ok(from_top_down(info.width, info.height, channels, &samples))
+}
+
+/// Reads binary (`P5`, `P6`) and plain (`P2`, `P3`) PGM and PPM files.
+pub fn read_pnm(r: &mut impl BufRead) -> io::Result<CpuBoard> {
+    let (channels, plain) = match header_token(r, "PGM/PPM")?.as_str() {
+        "P2" => (1, true),
+        "P3" => (3, true),
+        "P5" => (1, false),
+        "P6" => (3, false),
+        _ => return Err(invalid("not a binary or plain PGM/PPM file")),
+    };
+    let width = header_number(r, "PGM/PPM", "size")?;
+    let height = header_number(r, "PGM/PPM", "size")?;
+    let max = header_number(r, "PGM/PPM", "maximum value")?;
+    if max == 0 || max > 65535 {
+        return Err(invalid("bad PGM/PPM maximum value"));
+    }
+
+    let samples: Vec<u32> = if plain {
+        // Collecting doesn't reserve `count` up front, the samples must be there.
+        let count = data_size("PGM/PPM", width, height, channels)?;
+        (0..count).map(|_| header_number(r, "PGM/PPM", "sample")).collect::<io::Result<_>>()?
+    } else {
+        let bytes = if max > 255 { 2 } else { 1 };
+        read_data(r, "PGM/PPM", width, height, channels * bytes)?
+            .chunks_exact(bytes)
+            .map(|b| if bytes == 2 { u16::from_be_bytes([b[0], b[1]]) as u32 } else { b[0] as u32 })
+            .collect()
+    };
+    let samples: Vec<f32> = samples.into_iter().map(|s| s as f32 / max as f32).collect();
+    Ok(from_top_down(width, height, channels, &samples))
+}
+
+/// Board from interleaved samples with rows running top to bottom, as in PNG
+/// and PPM. Gray fills red, green and blue; alpha is 1 when absent.
+fn from_top_down(width: u32, height: u32, channels: usize, samples: &[f32]) -> CpuBoard {
+    let mut board = CpuBoard::new(width, height);
+    for (i, pixel) in samples.chunks_exact(channels).enumerate() {
+        let (x, y) = (i as u32 % width, height - 1 - i as u32 / width);
+        board.set(x, y, match *pixel {
+            [l] => [l, l, l, 1.],
+            [l, a] => [l, l, l, a],
+            [r, g, b] => [r, g, b, 1.],
+            [r, g, b, a, ..] => [r, g, b, a],
+            _ => unreachable!("chunks are not empty"),
+        });
+    }
+    board
+}
+
+/// `width` x `height` times `per_cell`, for a size from a header of `format`
+/// of at most `MAX_CELLS` cells.
+fn data_size(format: &str, width: u32, height: u32, per_cell: usize) -> io::Result<usize> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .filter(|&cells| cells <= MAX_CELLS)
+        .and_then(|cells| cells.checked_mul(per_cell))
+        .ok_or_else(|| invalid(&format!("{} size {}x{} is too large", format, width, height)))
+}
+
+/// Reads the `bytes` per pixel of a `width` x `height` image. Sizes from the
+/// header are only trusted up to `MAX_CELLS` and as far as the data goes, so a
+/// bad header can't make this allocate more than the file holds.
+fn read_data(r: &mut impl BufRead, format: &str, width: u32, height: u32, bytes: usize) -> io::Result<Vec<u8>> {
+    let size = data_size(format, width, height, bytes)?;
+    let mut data = Vec::new();
+    r.take(size as u64).read_to_end(&mut data)?;
+    if data.len() < size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} data ends early", format)));
+    }
+    Ok(data)
+}
+
+/// Reads one whitespace separated header token, consuming the single
+/// whitespace byte after it. `#` comments (used in PGM/PPM) are skipped.
+/// `format` names the file format in errors.
+fn header_token(r: &mut impl BufRead, format: &str) -> io::Result<String> {
+    let mut token = String::new();
+    loop {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        if byte[0] == b'#' && token.is_empty() {
+            let mut comment = Vec::new();
+            r.read_until(b'\n', &mut comment)?;
+            continue;
+        }
+        if byte[0].is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            }
+            return Ok(token);
+        }
+        token.push(byte[0] as char);
+        if token.len() > 32 {
+            return Err(invalid(&format!("bad {} header", format)));
+        }
+    }
+}
+
+/// Reads a header number, `format` and `what` name it in errors, e.g. "bad
+/// PGM/PPM size".
+fn header_number(r: &mut impl BufRead, format: &str, what: &str) -> io::Result<u32> {
+    header_token(r, format)?.parse().map_err(|_| invalid(&format!("bad {} {}", format, what)))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("gpu_convolution_{}_{}", std::process::id(), name))
+    }
+
+    /// Saves `board` to a temporary file named `name` and loads it back.
+    fn round_trip(name: &str, board: &CpuBoard) -> CpuBoard {
+        let path = temp_path(name);
+        save(&path, board).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        loaded
+    }
+
+    /// A 3x2 board whose cells all differ, values multiples of 1/255.
+    fn board() -> CpuBoard {
+        let mut board = CpuBoard::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                let v = (x + 3 * y) as f32 * 40. / 255.;
+                board.set(x, y, [v, 1. - v, v / 2., 1.]);
+            }
+        }
+        board
+    }
+
+    fn error(result: io::Result<CpuBoard>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    /// A 2x2 PFM with the given channel values, rows bottom to top.
+    fn pfm(magic: &str, scale: &str, values: &[f32]) -> Vec<u8> {
+        let mut bytes = format!("{}\n2 2\n{}\n", magic, scale).into_bytes();
+        for v in values {
+            bytes.extend(if scale.starts_with('-') { v.to_le_bytes() } else { v.to_be_bytes() });
+        }
+        bytes
+    }
+
+    #[test]
+    fn pfm_endianness_and_row_order() {
+        let values: Vec<f32> = (0..12).map(|i| i as f32 - 0.5).collect();
+        for scale in ["-1.0", "1.0"] {
+            let board = read_pfm(&mut &pfm("PF", scale, &values)[..]).unwrap();
+            // The first row in the file is the bottom one, y = 0.
+            assert_eq!(board.get(0, 0), [-0.5, 0.5, 1.5, 1.], "{}", scale);
+            assert_eq!(board.get(1, 0), [2.5, 3.5, 4.5, 1.], "{}", scale);
+            assert_eq!(board.get(0, 1), [5.5, 6.5, 7.5, 1.], "{}", scale);
+            assert_eq!(board.get(1, 1), [8.5, 9.5, 10.5, 1.], "{}", scale);
+        }
+        let gray = read_pfm(&mut &pfm("Pf", "-1.0", &[1., 2., 3., 4.])[..]).unwrap();
+        assert_eq!((gray.get(1, 0), gray.get(0, 1)), ([2., 0., 0., 1.], [3., 0., 0., 1.]));
+    }
+
+    #[test]
+    fn pnm() {
+        // Plain and binary files of the same image, rows top to bottom.
+        let plain = "P3\n# a comment\n2 2\n4\n0 1 2  3 4 0\n4 4 4  0 0 0\n";
+        let mut binary = b"P6 2 2 4\n".to_vec();
+        binary.extend([0, 1, 2, 3, 4, 0, 4, 4, 4, 0, 0, 0]);
+        for file in [plain.as_bytes(), &binary] {
+            let board = read_pnm(&mut &file[..]).unwrap();
+            assert_eq!(board.get(0, 1), [0., 0.25, 0.5, 1.]);
+            assert_eq!(board.get(1, 1), [0.75, 1., 0., 1.]);
+            assert_eq!(board.get(0, 0), [1., 1., 1., 1.]);
+            assert_eq!(board.get(1, 0), [0., 0., 0., 1.]);
+        }
+
+        let mut wide = b"P5 2 1 65535\n".to_vec();
+        wide.extend([0xff, 0xff, 0x80, 0x00]);
+        let board = read_pnm(&mut &wide[..]).unwrap();
+        assert_eq!(board.get(0, 0), [1., 1., 1., 1.]);
+        let half = 32768. / 65535.;
+        assert_eq!(board.get(1, 0), [half, half, half, 1.]);
+        let gray = read_pnm(&mut &b"P2 1 2 2\n2\n1\n"[..]).unwrap();
+        assert_eq!((gray.get(0, 1), gray.get(0, 0)), ([1., 1., 1., 1.], [0.5, 0.5, 0.5, 1.]));
+    }
+
+    #[test]
+    fn pfm_sizes_are_not_trusted() {
+        // Would overflow when multiplied out.
+        let overflowing = error(read_pfm(&mut &b"PF\n4000000000 4000000000\n-1\n"[..]));
+        assert_eq!(overflowing, "PFM size 4000000000x4000000000 is too large");
+        // Would allocate terabytes.
+        assert_eq!(error(read_pfm(&mut &b"PF\n1000000 1000000\n-1\n"[..])), "PFM size 1000000x1000000 is too large");
+        // Within the limit, but the file doesn't hold the data.
+        assert_eq!(error(read_pfm(&mut &b"Pf\n16384 16384\n-1\n\0\0\0\0"[..])), "PFM data ends early");
+    }
+
+    #[test]
+    fn pnm_sizes_are_not_trusted() {
+        for magic in ["P2", "P3", "P5", "P6"] {
+            let overflowing = format!("{} 4294967295 4294967295 65535\n", magic);
+            assert_eq!(error(read_pnm(&mut overflowing.as_bytes())), "PGM/PPM size 4294967295x4294967295 is too large");
+            let huge = format!("{} 1000000 1000000 255\n", magic);
+            assert_eq!(error(read_pnm(&mut huge.as_bytes())), "PGM/PPM size 1000000x1000000 is too large");
+        }
+        assert_eq!(error(read_pnm(&mut &b"P6 16384 16384 65535\n\0\0"[..])), "PGM/PPM data ends early");
+        let truncated = read_pnm(&mut &b"P3 16384 16384 255\n0 0 0\n"[..]).unwrap_err();
+        assert_eq!(truncated.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn errors_name_the_format() {
+        assert_eq!(error(read_pfm(&mut &b"PF\n2 x\n-1.0\n"[..])), "bad PFM size");
+        assert_eq!(error(read_pfm(&mut &b"PF\n2 2\nbig\n"[..])), "bad PFM scale");
+        assert_eq!(error(read_pfm(&mut &b"P6\n2 2\n-1.0\n"[..])), "not a PFM file");
+        assert_eq!(error(read_pnm(&mut &b"P6 -2 2 255\n"[..])), "bad PGM/PPM size");
+        assert_eq!(error(read_pnm(&mut &b"P6 2 2 0\n"[..])), "bad PGM/PPM maximum value");
+        assert_eq!(error(read_pnm(&mut &b"P2 2 1 9\n1 a\n"[..])), "bad PGM/PPM sample");
+        assert_eq!(error(read_pnm(&mut &b"P3 4444444444444444444444444444444444 1 9\n"[..])), "bad PGM/PPM header");
+        assert_eq!(error(read_pnm(&mut &b"PF\n"[..])), "not a binary or plain PGM/PPM file");
+        assert_eq!(error(read_pfm(&mut &pfm("PF", "-1.0", &[0.; 11])[..])), "PFM data ends early");
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let mut board = board();
+        board.set(2, 1, [-3.5, 1e30, f32::MIN_POSITIVE, 0.25]);
+        let loaded = round_trip("board.pfm", &board);
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                // Alpha isn't stored.
+                let [r, g, b, _] = board.get(x, y);
+                assert_eq!(loaded.get(x, y), [r, g, b, 1.]);
+            }
+        }
+
+        // Little-endian, bottom row first.
+        let mut bytes = Vec::new();
+        write_pfm(&mut bytes, &board).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let first: Vec<u8> = board.get(0, 0)[..3].iter().flat_map(|c| c.to_le_bytes()).collect();
+        assert_eq!(&bytes[header.len()..header.len() + 12], &first[..]);
+        assert_eq!(bytes.len(), header.len() + 3 * 2 * 3 * 4);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut board = board();
+        let loaded = round_trip("board.png", &board);
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        for (a, b) in loaded.data.iter().zip(&board.data) {
+            assert!((a - b).abs() < 1e-6, "{} {}", a, b);
+        }
+        // Values are clamped to 0..1.
+        board.set(1, 0, [-1., 2., 0.5, 1.]);
+        assert_eq!(round_trip("clamped.png", &board).get(1, 0), [0., 1., 128. / 255., 1.]);
+    }
+
+    #[test]
+    fn pnm_round_trip() {
+        // Loaded PPM and PGM files saved as PFM come back unchanged.
+        let files: [(&str, &[u8]); 2] =
+            [("board.ppm", b"P3 2 1 4 0 1 2 3 4 0\n"), ("board.pgm", b"P5 2 1 4\n\x01\x03")];
+        for (name, file) in files {
+            let path = temp_path(name);
+            fs::write(&path, file).unwrap();
+            let board = load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(round_trip("pnm.pfm", &board).data, board.data, "{}", name);
+        }
+    }
+
+    #[test]
+    fn npy() {
+        let board = board();
+        let path = temp_path("board.npy");
+        save(&path, &board).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert_eq!((10 + header_len) % 64, 0);
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3, 4), }"));
+        assert!(header.ends_with('\n'));
+
+        // Rows top to bottom, all four channels.
+        let values: Vec<f32> = bytes[10 + header_len..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values.len(), 2 * 3 * 4);
+        for (i, pixel) in values.chunks_exact(4).enumerate() {
+            let (x, y) = (i as u32 % 3, 1 - i as u32 / 3);
+            assert_eq!(pixel, board.get(x, y));
+        }
+    }
+
+    #[test]
+    fn formats_by_extension() {
+        assert_eq!(Format::from_path(Path::new("a/b.PNG")), Some(Format::Png));
+        assert_eq!(Format::from_path(Path::new("b.ppm")), None);
+        assert!(save(&temp_path("board.ppm"), &board()).is_err());
+        assert!(load(Path::new("board.jpg")).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use super::{
+    parser::{BinOp, Builtin, Expr, Function, Stmt, UnOp},
+    Type, Value,
+};
+
+impl Function {
+    /// Runs the function. Arguments must match the parameter types it was
+    /// compiled with; a body that ends without `return` yields zero, which is
+    /// one of the values a GPU may produce for undefined results.
+    pub fn call(&self, args: &[Value]) -> Value {
+        self.call_with_floats(args, &[])
+    }
+
+    /// Like `call`, with `float` arguments following `args`, such as the
+    /// values of a program's `params`.
+    pub fn call_with_floats(&self, args: &[Value], floats: &[f32]) -> Value {
+        let mut locals: Vec<Value> = self.slots.iter().map(|t| Value::zero(*t)).collect();
+        locals[..args.len()].copy_from_slice(args);
+        for (local, f) in locals[args.len()..].iter_mut().zip(floats) {
+            *local = Value::Float(*f);
+        }
+        exec(&self.body, &mut locals).unwrap_or(Value::zero(self.ret))
+    }
+}
+
+fn exec(stmts: &[Stmt], locals: &mut [Value]) -> Option<Value> {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Assign { slot, swizzle, value } => {
+                let value = eval(value, locals);
+                match swizzle {
+                    None => locals[*slot] = value,
+                    Some(indices) => {
+                        let (src, _) = value.components();
+                        if let Value::Vec(_, dst) = &mut locals[*slot] {
+                            for (k, i) in indices.iter().enumerate() {
+                                dst[*i] = src[k];
+                            }
+                        }
+                    }
+                }
+            }
+            Stmt::If(cond, then, otherwise) => {
+                let branch = if eval(cond, locals) == Value::Bool(true) { then } else { otherwise };
+                if let Some(ret) = exec(branch, locals) {
+                    return Some(ret);
+                }
+            }
+            Stmt::Return(value) => return Some(eval(value, locals)),
+            Stmt::Expr(value) => {
+                eval(value, locals);
+            }
+        }
+    }
+    None
+}
+
+fn eval(expr: &Expr, locals: &[Value]) -> Value {
+    match expr {
+        Expr::Const(v) => *v,
+        Expr::Local(slot) => locals[*slot],
+        Expr::Swizzle(base, indices) => {
+            let (c, _) = eval(base, locals).components();
+            let mut out = [0.; 4];
+            for (k, i) in indices.iter().enumerate() {
+                out[k] = c[*i];
+            }
+            Value::from_components(Type::of_len(indices.len()), out)
+        }
+        Expr::Unary(UnOp::Not, v) => Value::Bool(eval(v, locals) != Value::Bool(true)),
+        Expr::Unary(UnOp::Neg, v) => match eval(v, locals) {
+            Value::Int(i) => Value::Int(i.wrapping_neg()),
+            Value::Float(f) => Value::Float(-f),
+            Value::Vec(n, c) => Value::Vec(n, c.map(|x| -x)),
+            other => other,
+        },
+        Expr::Binary(BinOp::And, a, b) => {
+            Value::Bool(eval(a, locals) == Value::Bool(true) && eval(b, locals) == Value::Bool(true))
+        }
+        Expr::Binary(BinOp::Or, a, b) => {
+            Value::Bool(eval(a, locals) == Value::Bool(true) || eval(b, locals) == Value::Bool(true))
+        }
+        Expr::Binary(op, a, b) => binary(*op, eval(a, locals), eval(b, locals)),
+        Expr::Ternary(cond, a, b) => {
+            if eval(cond, locals) == Value::Bool(true) {
+                eval(a, locals)
+            } else {
+                eval(b, locals)
+            }
+        }
+        Expr::Call(builtin, typ, args) => {
+            let args: Vec<Value> = args.iter().map(|a| eval(a, locals)).collect();
+            call(*builtin, *typ, &args)
+        }
+        Expr::Construct(typ, args) => {
+            let mut c = Vec::with_capacity(4);
+            for arg in args {
+                let (comps, len) = eval(arg, locals).components();
+                c.extend_from_slice(&comps[..len]);
+            }
+            match typ {
+                Type::Bool => Value::Bool(c[0] != 0.),
+                Type::Int => Value::Int(c[0] as i32),
+                Type::Float => Value::Float(c[0]),
+                Type::Vec(n) => {
+                    let mut out = [0.; 4];
+                    for (i, o) in out.iter_mut().take(*n as usize).enumerate() {
+                        *o = if c.len() == 1 { c[0] } else { c[i] };
+                    }
+                    Value::Vec(*n, out)
+                }
+            }
+        }
+        Expr::ToFloat(v) => match eval(v, locals) {
+            Value::Int(i) => Value::Float(i as f32),
+            other => other,
+        },
+    }
+}
+
+fn binary(op: BinOp, a: Value, b: Value) -> Value {
+    if let (Value::Int(x), Value::Int(y)) = (a, b) {
+        return match op {
+            BinOp::Add => Value::Int(x.wrapping_add(y)),
+            BinOp::Sub => Value::Int(x.wrapping_sub(y)),
+            BinOp::Mul => Value::Int(x.wrapping_mul(y)),
+            BinOp::Div => Value::Int(x.checked_div(y).unwrap_or(0)),
+            BinOp::Rem => Value::Int(x.checked_rem(y).unwrap_or(0)),
+            BinOp::Lt => Value::Bool(x < y),
+            BinOp::Gt => Value::Bool(x > y),
+            BinOp::Le => Value::Bool(x <= y),
+            BinOp::Ge => Value::Bool(x >= y),
+            BinOp::Eq => Value::Bool(x == y),
+            BinOp::Ne => Value::Bool(x != y),
+            BinOp::And | BinOp::Or => unreachable!("short-circuited in eval"),
+        };
+    }
+    match op {
+        BinOp::Eq => return Value::Bool(a == b),
+        BinOp::Ne => return Value::Bool(a != b),
+        _ => (),
+    }
+    let (x, xn) = a.components();
+    let (y, yn) = b.components();
+    match op {
+        BinOp::Lt => Value::Bool(x[0] < y[0]),
+        BinOp::Gt => Value::Bool(x[0] > y[0]),
+        BinOp::Le => Value::Bool(x[0] <= y[0]),
+        BinOp::Ge => Value::Bool(x[0] >= y[0]),
+        _ => {
+            let n = xn.max(yn);
+            let mut out = [0.; 4];
+            for (i, o) in out.iter_mut().take(n).enumerate() {
+                let (p, q) = (x[if xn == 1 { 0 } else { i }], y[if yn == 1 { 0 } else { i }]);
+                *o = match op {
+                    BinOp::Add => p + q,
+                    BinOp::Sub => p - q,
+                    BinOp::Mul => p * q,
+                    BinOp::Div => p / q,
+                    _ => unreachable!("checked by the parser"),
+                };
+            }
+            Value::from_components(Type::of_len(n), out)
+        }
+    }
+}
+
+fn call(builtin: Builtin, typ: Type, args: &[Value]) -> Value {
+    let comps: Vec<([f32; 4], usize)> = args.iter().map(|a| a.components()).collect();
+    let n = comps.iter().map(|(_, len)| *len).max().unwrap_or(1);
+    let arg = |k: usize, i: usize| {
+        let (c, len) = comps[k];
+        c[if len == 1 { 0 } else { i }]
+    };
+
+    match builtin {
+        Builtin::Dot => {
+            return Value::Float((0..n).map(|i| arg(0, i) * arg(1, i)).sum());
+        }
+        Builtin::Length => {
+            return Value::Float((0..n).map(|i| arg(0, i).powi(2)).sum::<f32>().sqrt());
+        }
+        Builtin::Distance => {
+            return Value::Float((0..n).map(|i| (arg(0, i) - arg(1, i)).powi(2)).sum::<f32>().sqrt());
+        }
+        _ => (),
+    }
+
+    let length = (0..n).map(|i| arg(0, i).powi(2)).sum::<f32>().sqrt();
+    let mut out = [0.; 4];
+    for (i, o) in out.iter_mut().take(n).enumerate() {
+        let x = arg(0, i);
+        *o = match builtin {
+            Builtin::Abs => x.abs(),
+            Builtin::Sign => {
+                if x > 0. {
+                    1.
+                } else if x < 0. {
+                    -1.
+                } else {
+                    0.
+                }
+            }
+            Builtin::Floor => x.floor(),
+            Builtin::Ceil => x.ceil(),
+            Builtin::Fract => x - x.floor(),
+            Builtin::Exp => x.exp(),
+            Builtin::Exp2 => x.exp2(),
+            Builtin::Log => x.ln(),
+            Builtin::Log2 => x.log2(),
+            Builtin::Sqrt => x.sqrt(),
+            Builtin::InverseSqrt => 1. / x.sqrt(),
+            Builtin::Sin => x.sin(),
+            Builtin::Cos => x.cos(),
+            Builtin::Tan => x.tan(),
+            Builtin::Asin => x.asin(),
+            Builtin::Acos => x.acos(),
+            Builtin::Atan if args.len() == 2 => x.atan2(arg(1, i)),
+            Builtin::Atan => x.atan(),
+            Builtin::Pow => x.powf(arg(1, i)),
+            Builtin::Mod => {
+                let y = arg(1, i);
+                x - y * (x / y).floor()
+            }
+            Builtin::Min => x.min(arg(1, i)),
+            Builtin::Max => x.max(arg(1, i)),
+            Builtin::Clamp => x.max(arg(1, i)).min(arg(2, i)),
+            Builtin::Mix => {
+                let a = arg(2, i);
+                x * (1. - a) + arg(1, i) * a
+            }
+            Builtin::Step => {
+                if arg(1, i) < x {
+                    0.
+                } else {
+                    1.
+                }
+            }
+            Builtin::Smoothstep => {
+                let (edge0, edge1) = (x, arg(1, i));
+                let t = ((arg(2, i) - edge0) / (edge1 - edge0)).clamp(0., 1.);
+                t * t * (3. - 2. * t)
+            }
+            Builtin::Normalize => x / length,
+            Builtin::Dot | Builtin::Length | Builtin::Distance => unreachable!(),
+        };
+    }
+    Value::from_components(typ, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use crate::glsl::{compile, Type, Value};
+
+    /// Runs `src` as `float fun(float x)`.
+    fn val(src: &str, x: f32) -> f32 {
+        let f = compile(src, &[("x", Type::Float)], Type::Float).unwrap_or_else(|e| panic!("{}: {}", src, e));
+        match f.call(&[Value::Float(x)]) {
+            Value::Float(r) => r,
+            other => panic!("{}: returned {:?}", src, other),
+        }
+    }
+
+    /// Runs `src` as `vec3 fun(vec3 v, vec3 prev)`.
+    fn rgb(src: &str, v: [f32; 3], prev: [f32; 3]) -> [f32; 3] {
+        let params = [("v", Type::Vec(3)), ("prev", Type::Vec(3))];
+        let f = compile(src, &params, Type::Vec(3)).unwrap_or_else(|e| panic!("{}: {}", src, e));
+        let vec3 = |c: [f32; 3]| Value::Vec(3, [c[0], c[1], c[2], 0.]);
+        match f.call(&[vec3(v), vec3(prev)]) {
+            Value::Vec(3, c) => [c[0], c[1], c[2]],
+            other => panic!("{}: returned {:?}", src, other),
+        }
+    }
+
+    #[test]
+    fn ints_convert_to_float() {
+        assert_eq!(val("int i = 3;\nfloat f = i;\nreturn f / 2;", 0.), 1.5);
+        // Integer division truncates before the conversion.
+        assert_eq!(val("return 7 / 2;", 0.), 3.);
+        assert_eq!(val("return x + 1;", 0.5), 1.5);
+        assert_eq!(val("float a = 1;\na += 1;\nreturn a;", 0.), 2.);
+        assert_eq!(val("int i = 5;\ni++;\nreturn i % 4;", 0.), 2.);
+        assert_eq!(val("return x > 0. ? 1 : 0.5;", 1.), 1.);
+        assert_eq!(val("return x > 0. ? 1 : 0.5;", -1.), 0.5);
+        assert_eq!(val("return -2 * x;", 1.5), -3.);
+        assert_eq!(rgb("return v * 2;", [1., 2., 3.], [0.; 3]), [2., 4., 6.]);
+    }
+
+    #[test]
+    fn swizzles() {
+        let (v, prev) = ([1., 2., 3.], [4., 5., 6.]);
+        assert_eq!(rgb("return v.bgr;", v, prev), [3., 2., 1.]);
+        assert_eq!(rgb("return vec3(v.xy, prev.r);", v, prev), [1., 2., 4.]);
+        assert_eq!(rgb("vec4 c = vec4(v, 7.);\nreturn c.wzy;", v, prev), [7., 3., 2.]);
+        assert_eq!(rgb("return v.sst;", v, prev), [1., 1., 2.]);
+        assert_eq!(rgb("return v.xy.yxx;", v, prev), [2., 1., 1.]);
+        assert_eq!(val("vec3 c = vec3(1., 2., 3.);\nreturn c.z;", 0.), 3.);
+
+        assert_eq!(rgb("v.gb = vec2(7., 8.);\nreturn v;", v, prev), [1., 7., 8.]);
+        assert_eq!(rgb("v.r += 1.;\nreturn v;", v, prev), [2., 2., 3.]);
+        assert_eq!(rgb("v.x = 9;\nreturn v;", v, prev), [9., 2., 3.]);
+        assert_eq!(rgb("prev.zx = v.xy;\nreturn prev;", v, prev), [2., 5., 1.]);
+        assert_eq!(rgb("v.rg *= 2.;\nreturn v;", v, prev), [2., 4., 3.]);
+    }
+
+    #[test]
+    fn if_else_and_early_return() {
+        let src = "if (x > 1.)\n  return 1.;\nelse if (x < 0.)\n  return -1.;\nreturn 0.;";
+        assert_eq!(val(src, 2.), 1.);
+        assert_eq!(val(src, -3.), -1.);
+        assert_eq!(val(src, 0.5), 0.);
+        assert_eq!(val("if (x > 0.) { return 1.; }\nfloat a = 2.;\nreturn a;", 1.), 1.);
+        assert_eq!(val("if (x > 0.) { return 1.; }\nfloat a = 2.;\nreturn a;", -1.), 2.);
+        assert_eq!(val("float a = 1.;\nif (x > 0.) { float a = 5.; a += 1.; } else { a = 3.; }\nreturn a;", 1.), 1.);
+        assert_eq!(val("float a = 1.;\nif (x > 0.) { float a = 5.; a += 1.; } else { a = 3.; }\nreturn a;", -1.), 3.);
+        assert_eq!(val("if (x > 0. && !(x > 2.) || x == -1.) return 1.;\nreturn 0.;", 1.), 1.);
+        assert_eq!(val("if (x > 0. && !(x > 2.) || x == -1.) return 1.;\nreturn 0.;", 3.), 0.);
+        assert_eq!(val("if (x > 0. && !(x > 2.) || x == -1.) return 1.;\nreturn 0.;", -1.), 1.);
+        // Like on a GPU, falling off the end is undefined, the interpreter gives zero.
+        assert_eq!(val("if (x > 0.) return 1.;", -1.), 0.);
+    }
+
+    #[test]
+    fn builtins() {
+        let cases = [
+            ("abs(-2.)", 2.),
+            ("abs(-2)", 2.),
+            ("sign(-0.5)", -1.),
+            ("sign(0.)", 0.),
+            ("floor(-1.5)", -2.),
+            ("ceil(1.2)", 2.),
+            ("fract(-1.25)", 0.75),
+            ("exp(0.)", 1.),
+            ("exp2(3.)", 8.),
+            ("log(1.)", 0.),
+            ("log2(8.)", 3.),
+            ("sqrt(9.)", 3.),
+            ("inversesqrt(4.)", 0.5),
+            ("sin(0.)", 0.),
+            ("cos(0.)", 1.),
+            ("tan(0.)", 0.),
+            ("asin(1.)", PI / 2.),
+            ("acos(1.)", 0.),
+            ("atan(1.)", PI / 4.),
+            ("atan(1., -1.)", 3. * PI / 4.),
+            ("pow(2., 10.)", 1024.),
+            ("mod(-1., 3.)", 2.),
+            ("mod(7, 3)", 1.),
+            ("min(1., 2)", 1.),
+            ("max(1, 2.)", 2.),
+            ("clamp(5., 0., 1.)", 1.),
+            ("clamp(-5, 0, 1)", 0.),
+            ("mix(2., 4., 0.25)", 2.5),
+            ("step(0.5, 0.4)", 0.),
+            ("step(0.5, 0.5)", 1.),
+            ("smoothstep(0., 2., 0.5)", 0.15625),
+            ("smoothstep(0., 1., 2.)", 1.),
+            ("length(vec2(3., 4.))", 5.),
+            ("distance(vec2(1.), vec2(4., 5.))", 5.),
+            ("dot(vec3(1., 2., 3.), vec3(1.))", 6.),
+            ("normalize(vec2(3., 4.)).y", 0.8),
+        ];
+        for (call, expected) in cases {
+            let found = val(&format!("return {};", call), 0.);
+            let close = (found - expected).abs() <= 1e-6 * expected.abs().max(1.);
+            assert!(close, "{} = {}, not {}", call, found, expected);
+        }
+        let (v, prev) = ([-1., 0.5, 2.], [1., 1., 0.]);
+        assert_eq!(rgb("return clamp(v, 0., 1.);", v, prev), [0., 0.5, 1.]);
+        assert_eq!(rgb("return mix(v, prev, 0.5);", v, prev), [0., 0.75, 1.]);
+        assert_eq!(rgb("return max(v, prev);", v, prev), [1., 1., 2.]);
+        assert_eq!(rgb("return step(vec3(0.), v);", v, prev), [0., 1., 1.]);
+    }
+}
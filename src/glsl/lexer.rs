@@ -0,0 +1,173 @@
+use super::GlslError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Ident(String),
+    Float(f32),
+    Int(i32),
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub tok: Tok,
+    pub line: usize,
+    pub col: usize,
+}
+
+// Longest first, so that `<=` is not lexed as `<` `=`.
+const PUNCTS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "++", "--", "+", "-", "*", "/",
+    "%", "<", ">", "=", "!", "?", ":", ";", ",", ".", "(", ")", "{", "}", "[", "]",
+];
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, GlslError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut line_start) = (0, 1, 0);
+
+    while i < chars.len() {
+        let c = chars[i];
+        let col = i - line_start + 1;
+        if c == '\n' {
+            line += 1;
+            line_start = i + 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token {
+                tok: Tok::Ident(chars[start..i].iter().collect()),
+                line,
+                col,
+            });
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit())) {
+            let (tok, len) = number(&chars[i..]).ok_or_else(|| GlslError::new("invalid number", line, col))?;
+            tokens.push(Token { tok, line, col });
+            i += len;
+        } else if let Some(p) = PUNCTS.iter().find(|p| p.chars().enumerate().all(|(k, pc)| chars.get(i + k) == Some(&pc))) {
+            tokens.push(Token {
+                tok: Tok::Punct(p),
+                line,
+                col,
+            });
+            i += p.len();
+        } else {
+            return Err(GlslError::new(&format!("unexpected character `{}`", c), line, col));
+        }
+    }
+    tokens.push(Token {
+        tok: Tok::Eof,
+        line,
+        col: i - line_start + 1,
+    });
+    Ok(tokens)
+}
+
+/// Lexes `1`, `1.`, `.5`, `1.5e-3`, `2.0f`, returning the token and its length.
+fn number(chars: &[char]) -> Option<(Tok, usize)> {
+    let mut i = 0;
+    let mut is_float = false;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        is_float = true;
+        i += 1;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+    if matches!(chars.get(i), Some('e' | 'E')) {
+        let mut j = i + 1;
+        if matches!(chars.get(j), Some('+' | '-')) {
+            j += 1;
+        }
+        if chars.get(j).is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
+            i = j;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+    }
+    let text: String = chars[..i].iter().collect();
+    if matches!(chars.get(i), Some('f' | 'F')) {
+        is_float = true;
+        i += 1;
+    }
+    if is_float {
+        Some((Tok::Float(text.parse().ok()?), i))
+    } else {
+        Some((Tok::Int(text.parse().ok()?), i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toks(src: &str) -> Vec<Tok> {
+        tokenize(src).unwrap().into_iter().map(|t| t.tok).collect()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            toks("1 1. .5 1.5e-3 2.0f 3e2 4E+1"),
+            [
+                Tok::Int(1),
+                Tok::Float(1.),
+                Tok::Float(0.5),
+                Tok::Float(1.5e-3),
+                Tok::Float(2.),
+                Tok::Float(300.),
+                Tok::Float(40.),
+                Tok::Eof
+            ]
+        );
+        // `e` without digits isn't an exponent.
+        assert_eq!(toks("2e"), [Tok::Int(2), Tok::Ident("e".to_string()), Tok::Eof]);
+    }
+
+    #[test]
+    fn longest_punctuation_wins() {
+        let ident = |s: &str| Tok::Ident(s.to_string());
+        assert_eq!(toks("a<=b"), [ident("a"), Tok::Punct("<="), ident("b"), Tok::Eof]);
+        assert_eq!(toks("i++ += +"), [ident("i"), Tok::Punct("++"), Tok::Punct("+="), Tok::Punct("+"), Tok::Eof]);
+        assert_eq!(toks("v.rg"), [ident("v"), Tok::Punct("."), ident("rg"), Tok::Eof]);
+    }
+
+    #[test]
+    fn comments_are_skipped_and_positions_kept() {
+        let tokens = tokenize("// one\n  /* two\n three */ x\n").unwrap();
+        assert_eq!(tokens[0].tok, Tok::Ident("x".to_string()));
+        assert_eq!((tokens[0].line, tokens[0].col), (3, 11));
+        assert_eq!((tokens[1].tok.clone(), tokens[1].line, tokens[1].col), (Tok::Eof, 4, 1));
+    }
+
+    #[test]
+    fn unexpected_characters_are_errors() {
+        assert_eq!(tokenize("x +\n  @y").unwrap_err(), GlslError::new("unexpected character `@`", 2, 3));
+        assert_eq!(tokenize("99999999999").unwrap_err(), GlslError::new("invalid number", 1, 1));
+    }
+}
@@ -0,0 +1,185 @@
+//! Interpreter for the subset of GLSL used in the `fun` body of programs, so
+//! they can run on the CPU.
+//!
+//! Supported are `bool`/`int`/`float`/`vec2..4` locals, arithmetic with
+//! scalar broadcasting, comparisons, `&&`/`||`/`!`, `?:`, `if`/`else`,
+//! (compound) assignment including to swizzles like `v.rg`, constructors and
+//! the common builtins (`clamp`, `min`, `max`, `mix`, `step`, `abs`, `exp`...).
+//! Loops and user defined functions are not.
+
+mod eval;
+mod lexer;
+mod parser;
+
+use std::fmt;
+
+use crate::{
+    cpu::CpuFun,
+    program::{ProgramError, ProgramKind, ProgramSpec},
+};
+
+pub use self::parser::Function;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Bool,
+    Int,
+    Float,
+    Vec(u8),
+}
+
+impl Type {
+    fn from_name(name: &str) -> Option<Type> {
+        Some(match name {
+            "bool" => Type::Bool,
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "vec2" => Type::Vec(2),
+            "vec3" => Type::Vec(3),
+            "vec4" => Type::Vec(4),
+            _ => return None,
+        })
+    }
+
+    fn of_len(len: usize) -> Type {
+        if len == 1 {
+            Type::Float
+        } else {
+            Type::Vec(len as u8)
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            Type::Vec(n) => n as usize,
+            _ => 1,
+        }
+    }
+
+    fn is_scalar(self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Vec(n) => write!(f, "vec{}", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i32),
+    Float(f32),
+    /// Length and components, unused components are zero.
+    Vec(u8, [f32; 4]),
+}
+
+impl Value {
+    fn zero(typ: Type) -> Value {
+        match typ {
+            Type::Bool => Value::Bool(false),
+            Type::Int => Value::Int(0),
+            Type::Float => Value::Float(0.),
+            Type::Vec(n) => Value::Vec(n, [0.; 4]),
+        }
+    }
+
+    /// Components as floats, and how many of them there are.
+    fn components(self) -> ([f32; 4], usize) {
+        match self {
+            Value::Bool(b) => ([if b { 1. } else { 0. }, 0., 0., 0.], 1),
+            Value::Int(i) => ([i as f32, 0., 0., 0.], 1),
+            Value::Float(f) => ([f, 0., 0., 0.], 1),
+            Value::Vec(n, c) => (c, n as usize),
+        }
+    }
+
+    fn from_components(typ: Type, c: [f32; 4]) -> Value {
+        match typ {
+            Type::Bool => Value::Bool(c[0] != 0.),
+            Type::Int => Value::Int(c[0] as i32),
+            Type::Float => Value::Float(c[0]),
+            Type::Vec(n) => {
+                let mut out = [0.; 4];
+                out[..n as usize].copy_from_slice(&c[..n as usize]);
+                Value::Vec(n, out)
+            }
+        }
+    }
+}
+
+/// Error in a `fun` body, `line` and `col` are 1-based and relative to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlslError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl GlslError {
+    fn new(message: &str, line: usize, col: usize) -> Self {
+        Self {
+            message: message.to_string(),
+            line,
+            col,
+        }
+    }
+}
+
+impl fmt::Display for GlslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl std::error::Error for GlslError {}
+
+/// Compiles a function body taking `params` and returning `ret`.
+pub fn compile(src: &str, params: &[(&str, Type)], ret: Type) -> Result<Function, GlslError> {
+    parser::parse_function(lexer::tokenize(src)?, params, ret)
+}
+
+/// Compiles the `fun` of a program with the signature its type gives it:
+/// `float fun(float x, float prev)` for `val`, `vec3 fun(vec3 v, vec3 prev)`
+/// for `rgb` and `sym`. The program's `params` are passed as further `float`
+/// arguments, which is how `fun` sees the uniforms of the GL programs.
+pub fn compile_fun(spec: &ProgramSpec) -> Result<CpuFun, ProgramError> {
+    let to_program_error = |e: GlslError| {
+        let line_start: usize = spec.fun.split_inclusive('\n').take(e.line - 1).map(str::len).sum();
+        spec.fun_error(e.message, line_start + e.col - 1)
+    };
+    let args = |names: [(&'static str, Type); 2]| {
+        let params = spec.params.names().iter().map(|name| (name.as_str(), Type::Float));
+        names.into_iter().chain(params).collect::<Vec<_>>()
+    };
+    match spec.kind {
+        ProgramKind::Val { .. } => {
+            let f = compile(&spec.fun, &args([("x", Type::Float), ("prev", Type::Float)]), Type::Float)
+                .map_err(to_program_error)?;
+            Ok(CpuFun::Val(Box::new(move |x, prev, params| {
+                match f.call_with_floats(&[Value::Float(x), Value::Float(prev)], params) {
+                    Value::Float(r) => r,
+                    _ => unreachable!("return type is checked"),
+                }
+            })))
+        }
+        ProgramKind::Rgb { .. } | ProgramKind::Sym { .. } => {
+            let f = compile(&spec.fun, &args([("v", Type::Vec(3)), ("prev", Type::Vec(3))]), Type::Vec(3))
+                .map_err(to_program_error)?;
+            let vec3 = |c: [f32; 3]| Value::Vec(3, [c[0], c[1], c[2], 0.]);
+            Ok(CpuFun::Rgb(Box::new(move |v, prev, params| {
+                match f.call_with_floats(&[vec3(v), vec3(prev)], params) {
+                    Value::Vec(_, c) => [c[0], c[1], c[2]],
+                    _ => unreachable!("return type is checked"),
+                }
+            })))
+        }
+    }
+}
@@ -0,0 +1,684 @@
+use std::collections::HashMap;
+
+use super::{
+    lexer::{Tok, Token},
+    GlslError, Type, Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Abs,
+    Sign,
+    Floor,
+    Ceil,
+    Fract,
+    Exp,
+    Exp2,
+    Log,
+    Log2,
+    Sqrt,
+    InverseSqrt,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Pow,
+    Mod,
+    Min,
+    Max,
+    Clamp,
+    Mix,
+    Step,
+    Smoothstep,
+    Length,
+    Distance,
+    Dot,
+    Normalize,
+}
+
+impl Builtin {
+    fn lookup(name: &str) -> Option<(Builtin, usize)> {
+        use Builtin::*;
+        Some(match name {
+            "abs" => (Abs, 1),
+            "sign" => (Sign, 1),
+            "floor" => (Floor, 1),
+            "ceil" => (Ceil, 1),
+            "fract" => (Fract, 1),
+            "exp" => (Exp, 1),
+            "exp2" => (Exp2, 1),
+            "log" => (Log, 1),
+            "log2" => (Log2, 1),
+            "sqrt" => (Sqrt, 1),
+            "inversesqrt" => (InverseSqrt, 1),
+            "sin" => (Sin, 1),
+            "cos" => (Cos, 1),
+            "tan" => (Tan, 1),
+            "asin" => (Asin, 1),
+            "acos" => (Acos, 1),
+            "atan" => (Atan, 1),
+            "pow" => (Pow, 2),
+            "mod" => (Mod, 2),
+            "min" => (Min, 2),
+            "max" => (Max, 2),
+            "clamp" => (Clamp, 3),
+            "mix" => (Mix, 3),
+            "step" => (Step, 2),
+            "smoothstep" => (Smoothstep, 3),
+            "length" => (Length, 1),
+            "distance" => (Distance, 2),
+            "dot" => (Dot, 2),
+            "normalize" => (Normalize, 1),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Const(Value),
+    Local(usize),
+    /// Component indices into a vector.
+    Swizzle(Box<Expr>, Vec<usize>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// Builtin function and its result type.
+    Call(Builtin, Type, Vec<Expr>),
+    Construct(Type, Vec<Expr>),
+    ToFloat(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Assign {
+        slot: usize,
+        swizzle: Option<Vec<usize>>,
+        value: Expr,
+    },
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    Return(Expr),
+    Expr(Expr),
+}
+
+/// A type checked `fun` body with its arguments in slots `0..params`.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub body: Vec<Stmt>,
+    pub slots: Vec<Type>,
+    pub ret: Type,
+}
+
+pub fn parse_function(
+    tokens: Vec<Token>,
+    params: &[(&str, Type)],
+    ret: Type,
+) -> Result<Function, GlslError> {
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        scopes: vec![HashMap::new()],
+        slots: Vec::new(),
+        ret,
+    };
+    for (name, typ) in params {
+        parser.declare(name, *typ);
+    }
+    let mut body = Vec::new();
+    while parser.peek() != &Tok::Eof {
+        parser.statement(&mut body)?;
+    }
+    Ok(Function {
+        body,
+        slots: parser.slots,
+        ret,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    scopes: Vec<HashMap<String, (usize, Type)>>,
+    slots: Vec<Type>,
+    ret: Type,
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.tok != Tok::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: &str) -> GlslError {
+        let token = &self.tokens[self.pos];
+        GlslError::new(message, token.line, token.col)
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(q) if *q == p)
+    }
+
+    fn eat(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, p: &str) -> Result<(), GlslError> {
+        if self.eat(p) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", p)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, GlslError> {
+        match self.peek().clone() {
+            Tok::Ident(name) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn declare(&mut self, name: &str, typ: Type) -> usize {
+        let slot = self.slots.len();
+        self.slots.push(typ);
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), (slot, typ));
+        slot
+    }
+
+    fn lookup(&self, name: &str) -> Option<(usize, Type)> {
+        self.scopes.iter().rev().find_map(|s| s.get(name).copied())
+    }
+
+    fn statement(&mut self, out: &mut Vec<Stmt>) -> Result<(), GlslError> {
+        if self.eat(";") {
+            return Ok(());
+        }
+        if self.eat("{") {
+            self.scopes.push(HashMap::new());
+            while !self.eat("}") {
+                if self.peek() == &Tok::Eof {
+                    return Err(self.error("expected `}`"));
+                }
+                self.statement(out)?;
+            }
+            self.scopes.pop();
+            return Ok(());
+        }
+        let word = match self.peek() {
+            Tok::Ident(w) => w.clone(),
+            _ => {
+                let expr = self.expression()?.0;
+                self.expect(";")?;
+                out.push(Stmt::Expr(expr));
+                return Ok(());
+            }
+        };
+        match word.as_str() {
+            "return" => {
+                self.pos += 1;
+                let value = self.expression()?;
+                let value = self.coerce(value, self.ret)?;
+                self.expect(";")?;
+                out.push(Stmt::Return(value));
+            }
+            "if" => {
+                self.pos += 1;
+                self.expect("(")?;
+                let cond = self.expression()?;
+                let cond = self.coerce(cond, Type::Bool)?;
+                self.expect(")")?;
+                let then = self.scoped_statement()?;
+                let otherwise = if matches!(self.peek(), Tok::Ident(w) if w == "else") {
+                    self.pos += 1;
+                    self.scoped_statement()?
+                } else {
+                    Vec::new()
+                };
+                out.push(Stmt::If(cond, then, otherwise));
+            }
+            "const" => {
+                self.pos += 1;
+                return self.statement(out);
+            }
+            "for" | "while" | "do" | "switch" | "break" | "continue" | "discard" => {
+                return Err(self.error(&format!("`{}` is not supported", word)));
+            }
+            _ => {
+                if let Some(typ) = Type::from_name(&word) {
+                    if !matches!(self.tokens[self.pos + 1].tok, Tok::Punct("(")) {
+                        self.pos += 1;
+                        return self.declaration(typ, out);
+                    }
+                }
+                self.assignment_or_expression(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Body of an `if`/`else`, which gets its own scope even without braces.
+    fn scoped_statement(&mut self) -> Result<Vec<Stmt>, GlslError> {
+        let mut body = Vec::new();
+        self.scopes.push(HashMap::new());
+        self.statement(&mut body)?;
+        self.scopes.pop();
+        Ok(body)
+    }
+
+    fn declaration(&mut self, typ: Type, out: &mut Vec<Stmt>) -> Result<(), GlslError> {
+        loop {
+            let name = self.ident()?;
+            let value = if self.eat("=") {
+                let value = self.expression()?;
+                self.coerce(value, typ)?
+            } else {
+                Expr::Const(Value::zero(typ))
+            };
+            // Declared after the initializer, so `float x = x;` sees the outer `x`.
+            let slot = self.declare(&name, typ);
+            out.push(Stmt::Assign {
+                slot,
+                swizzle: None,
+                value,
+            });
+            if !self.eat(",") {
+                break;
+            }
+        }
+        self.expect(";")
+    }
+
+    fn assignment_or_expression(&mut self, out: &mut Vec<Stmt>) -> Result<(), GlslError> {
+        let start = self.pos;
+        let name = self.ident()?;
+        let swizzle = if self.eat(".") {
+            Some(self.ident()?)
+        } else {
+            None
+        };
+        let op = match self.peek() {
+            Tok::Punct("=") => None,
+            Tok::Punct("+=") => Some(BinOp::Add),
+            Tok::Punct("-=") => Some(BinOp::Sub),
+            Tok::Punct("*=") => Some(BinOp::Mul),
+            Tok::Punct("/=") => Some(BinOp::Div),
+            Tok::Punct("++") => Some(BinOp::Add),
+            Tok::Punct("--") => Some(BinOp::Sub),
+            _ => {
+                self.pos = start;
+                let expr = self.expression()?.0;
+                self.expect(";")?;
+                out.push(Stmt::Expr(expr));
+                return Ok(());
+            }
+        };
+        let (slot, var_type) = self
+            .lookup(&name)
+            .ok_or_else(|| GlslError::new(&format!("unknown variable `{}`", name), self.tokens[start].line, self.tokens[start].col))?;
+        let (indices, target_type) = match &swizzle {
+            Some(s) => {
+                let indices = swizzle_indices(s, var_type).ok_or_else(|| self.error(&format!("invalid swizzle `.{}`", s)))?;
+                let mut seen = indices.clone();
+                seen.sort();
+                seen.dedup();
+                if seen.len() != indices.len() {
+                    return Err(self.error(&format!("cannot assign to repeated components `.{}`", s)));
+                }
+                let typ = Type::of_len(indices.len());
+                (Some(indices), typ)
+            }
+            None => (None, var_type),
+        };
+        let is_step = matches!(self.peek(), Tok::Punct("++" | "--"));
+        self.pos += 1;
+        let rhs = if is_step {
+            (Expr::Const(Value::Int(1)), Type::Int)
+        } else {
+            self.expression()?
+        };
+        let current = match &indices {
+            Some(i) => Expr::Swizzle(Box::new(Expr::Local(slot)), i.clone()),
+            None => Expr::Local(slot),
+        };
+        let value = match op {
+            Some(op) => self.binary(op, (current, target_type), rhs)?,
+            None => rhs,
+        };
+        let value = self.coerce(value, target_type)?;
+        self.expect(";")?;
+        out.push(Stmt::Assign {
+            slot,
+            swizzle: indices,
+            value,
+        });
+        Ok(())
+    }
+
+    /// Converts `value` to `typ`, allowing only the implicit int to float
+    /// conversions GLSL allows.
+    fn coerce(&self, (expr, from): (Expr, Type), typ: Type) -> Result<Expr, GlslError> {
+        if from == typ {
+            Ok(expr)
+        } else if from == Type::Int && typ == Type::Float {
+            Ok(to_float(expr))
+        } else {
+            Err(self.error(&format!("expected {}, found {}", typ, from)))
+        }
+    }
+
+    fn expression(&mut self) -> Result<(Expr, Type), GlslError> {
+        let cond = self.binary_level(0)?;
+        if !self.eat("?") {
+            return Ok(cond);
+        }
+        let cond = self.coerce(cond, Type::Bool)?;
+        let a = self.expression()?;
+        self.expect(":")?;
+        let b = self.expression()?;
+        let typ = self.common_type(a.1, b.1)?;
+        let a = self.coerce(a, typ)?;
+        let b = self.coerce(b, typ)?;
+        Ok((Expr::Ternary(Box::new(cond), Box::new(a), Box::new(b)), typ))
+    }
+
+    fn binary_level(&mut self, level: usize) -> Result<(Expr, Type), GlslError> {
+        const LEVELS: &[&[(&str, BinOp)]] = &[
+            &[("||", BinOp::Or)],
+            &[("&&", BinOp::And)],
+            &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+            &[("<", BinOp::Lt), (">", BinOp::Gt), ("<=", BinOp::Le), (">=", BinOp::Ge)],
+            &[("+", BinOp::Add), ("-", BinOp::Sub)],
+            &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Rem)],
+        ];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary_level(level + 1)?;
+        'outer: loop {
+            for (p, op) in LEVELS[level] {
+                if self.eat(p) {
+                    let rhs = self.binary_level(level + 1)?;
+                    lhs = self.binary(*op, lhs, rhs)?;
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn common_type(&self, a: Type, b: Type) -> Result<Type, GlslError> {
+        match (a, b) {
+            _ if a == b => Ok(a),
+            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Ok(Type::Float),
+            _ => Err(self.error(&format!("mismatched types {} and {}", a, b))),
+        }
+    }
+
+    fn binary(&self, op: BinOp, lhs: (Expr, Type), rhs: (Expr, Type)) -> Result<(Expr, Type), GlslError> {
+        let (a, b) = (lhs.1, rhs.1);
+        let (typ, operand) = match op {
+            BinOp::And | BinOp::Or => {
+                let lhs = self.coerce(lhs, Type::Bool)?;
+                let rhs = self.coerce(rhs, Type::Bool)?;
+                return Ok((Expr::Binary(op, Box::new(lhs), Box::new(rhs)), Type::Bool));
+            }
+            BinOp::Eq | BinOp::Ne => {
+                let t = self.common_type(a, b)?;
+                (Type::Bool, (t, t))
+            }
+            BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => {
+                if !a.is_scalar() || !b.is_scalar() {
+                    return Err(self.error(&format!("cannot compare {} and {}", a, b)));
+                }
+                let t = self.common_type(a, b)?;
+                (Type::Bool, (t, t))
+            }
+            BinOp::Rem => {
+                if a != Type::Int || b != Type::Int {
+                    return Err(self.error("`%` needs int operands, use mod() for floats"));
+                }
+                (Type::Int, (Type::Int, Type::Int))
+            }
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => match (a, b) {
+                (Type::Bool, _) | (_, Type::Bool) => {
+                    return Err(self.error(&format!("cannot do arithmetic on {} and {}", a, b)))
+                }
+                (Type::Vec(n), Type::Vec(m)) if n != m => {
+                    return Err(self.error(&format!("mismatched types {} and {}", a, b)))
+                }
+                (Type::Vec(_), Type::Vec(_)) => (a, (a, b)),
+                (Type::Vec(_), _) => (a, (a, Type::Float)),
+                (_, Type::Vec(_)) => (b, (Type::Float, b)),
+                _ => {
+                    let t = self.common_type(a, b)?;
+                    (t, (t, t))
+                }
+            },
+        };
+        let lhs = self.coerce(lhs, operand.0)?;
+        let rhs = self.coerce(rhs, operand.1)?;
+        Ok((Expr::Binary(op, Box::new(lhs), Box::new(rhs)), typ))
+    }
+
+    fn unary(&mut self) -> Result<(Expr, Type), GlslError> {
+        if self.eat("-") {
+            let (expr, typ) = self.unary()?;
+            if typ == Type::Bool {
+                return Err(self.error("cannot negate a bool"));
+            }
+            return Ok((Expr::Unary(UnOp::Neg, Box::new(expr)), typ));
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        if self.eat("!") {
+            let value = self.unary()?;
+            let expr = self.coerce(value, Type::Bool)?;
+            return Ok((Expr::Unary(UnOp::Not, Box::new(expr)), Type::Bool));
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<(Expr, Type), GlslError> {
+        let mut value = self.primary()?;
+        while self.eat(".") {
+            let name = self.ident()?;
+            let indices = swizzle_indices(&name, value.1).ok_or_else(|| self.error(&format!("invalid swizzle `.{}` on {}", name, value.1)))?;
+            let typ = Type::of_len(indices.len());
+            value = (Expr::Swizzle(Box::new(value.0), indices), typ);
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<(Expr, Type), GlslError> {
+        let token = self.next();
+        match &token.tok {
+            Tok::Float(f) => Ok((Expr::Const(Value::Float(*f)), Type::Float)),
+            Tok::Int(i) => Ok((Expr::Const(Value::Int(*i)), Type::Int)),
+            Tok::Punct("(") => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            Tok::Ident(name) if name == "true" || name == "false" => {
+                Ok((Expr::Const(Value::Bool(*name == "true")), Type::Bool))
+            }
+            Tok::Ident(name) if self.is_punct("(") => self.call(name, &token),
+            Tok::Ident(name) => match self.lookup(name) {
+                Some((slot, typ)) => Ok((Expr::Local(slot), typ)),
+                None => Err(GlslError::new(&format!("unknown variable `{}`", name), token.line, token.col)),
+            },
+            _ => {
+                self.pos -= 1;
+                Err(self.error("expected an expression"))
+            }
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(Expr, Type)>, GlslError> {
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if !self.eat(")") {
+            loop {
+                args.push(self.expression()?);
+                if self.eat(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(args)
+    }
+
+    fn call(&mut self, name: &str, token: &Token) -> Result<(Expr, Type), GlslError> {
+        let err = |message: String| GlslError::new(&message, token.line, token.col);
+        let args = self.arguments()?;
+
+        if let Some(typ) = Type::from_name(name) {
+            let components: usize = args.iter().map(|(_, t)| t.len()).sum();
+            let scalar_args = args.iter().all(|(_, t)| t.is_scalar());
+            let valid = match typ {
+                _ if args.is_empty() => false,
+                Type::Vec(n) => {
+                    (args.len() == 1 && (scalar_args || args[0].1.len() >= n as usize))
+                        || (args.len() > 1 && components == n as usize)
+                }
+                _ => args.len() == 1 && scalar_args,
+            };
+            if !valid {
+                return Err(err(format!("wrong arguments for {} constructor", typ)));
+            }
+            return Ok((Expr::Construct(typ, args.into_iter().map(|(e, _)| e).collect()), typ));
+        }
+
+        let (builtin, arity) = Builtin::lookup(name).ok_or_else(|| err(format!("unknown function `{}`", name)))?;
+        let arity_ok = args.len() == arity || (builtin == Builtin::Atan && args.len() == 2);
+        if !arity_ok {
+            return Err(err(format!("`{}` takes {} arguments, found {}", name, arity, args.len())));
+        }
+        if args.iter().any(|(_, t)| *t == Type::Bool) {
+            return Err(err(format!("`{}` does not take bool arguments", name)));
+        }
+        let int_overload = matches!(builtin, Builtin::Abs | Builtin::Sign | Builtin::Min | Builtin::Max | Builtin::Clamp)
+            && args.iter().all(|(_, t)| *t == Type::Int);
+        let widest = args.iter().map(|(_, t)| *t).find(|t| matches!(t, Type::Vec(_))).unwrap_or(Type::Float);
+        if let Some((_, t)) = args.iter().find(|(_, t)| matches!(t, Type::Vec(_)) && *t != widest) {
+            return Err(err(format!("`{}` got mismatched {} and {}", name, widest, t)));
+        }
+        let typ = match builtin {
+            _ if int_overload => Type::Int,
+            Builtin::Length | Builtin::Distance | Builtin::Dot => Type::Float,
+            _ => widest,
+        };
+        let args = args
+            .into_iter()
+            .map(|(e, t)| if t == Type::Int && !int_overload { to_float(e) } else { e })
+            .collect();
+        Ok((Expr::Call(builtin, typ, args), typ))
+    }
+}
+
+fn to_float(expr: Expr) -> Expr {
+    match expr {
+        Expr::Const(Value::Int(i)) => Expr::Const(Value::Float(i as f32)),
+        other => Expr::ToFloat(Box::new(other)),
+    }
+}
+
+fn swizzle_indices(name: &str, typ: Type) -> Option<Vec<usize>> {
+    let len = match typ {
+        Type::Vec(n) => n as usize,
+        _ => return None,
+    };
+    if name.is_empty() || name.len() > 4 {
+        return None;
+    }
+    ["xyzw", "rgba", "stpq"].iter().find_map(|set| {
+        name.chars()
+            .map(|c| set.find(c).filter(|i| *i < len))
+            .collect::<Option<Vec<usize>>>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::glsl::{compile, GlslError, Type};
+
+    fn error(src: &str) -> GlslError {
+        compile(src, &[("x", Type::Float), ("v", Type::Vec(3))], Type::Float).unwrap_err()
+    }
+
+    #[test]
+    fn errors_point_at_line_and_column() {
+        let cases = [
+            ("return x +;", "expected an expression", 1, 11),
+            ("float a = 1.;\nreturn b;", "unknown variable `b`", 2, 8),
+            ("return x", "expected `;`", 1, 9),
+            ("float a = 1.;\n  for (;;) {}", "`for` is not supported", 2, 3),
+            ("{ return x;", "expected `}`", 1, 12),
+            ("return foo(x);", "unknown function `foo`", 1, 8),
+            ("return clamp(x, 0.);", "`clamp` takes 3 arguments, found 2", 1, 8),
+            ("return vec2(x, x, x).x;", "wrong arguments for vec2 constructor", 1, 8),
+            ("return x.y;", "invalid swizzle `.y` on float", 1, 11),
+            ("v.xx = vec2(1.);", "cannot assign to repeated components `.xx`", 1, 6),
+            ("return v.x + v.xy;", "expected float, found vec2", 1, 18),
+            ("return true + x;", "cannot do arithmetic on bool and float", 1, 16),
+            ("return length(v + vec2(x));", "mismatched types vec3 and vec2", 1, 26),
+            ("if (x) return 1.;", "expected bool, found float", 1, 6),
+            ("return true;", "expected float, found bool", 1, 12),
+            ("return 5 % 2.;", "`%` needs int operands, use mod() for floats", 1, 14),
+            ("y = 1.;", "unknown variable `y`", 1, 1),
+        ];
+        for (src, message, line, col) in cases {
+            assert_eq!(error(src), GlslError::new(message, line, col), "{}", src);
+        }
+    }
+
+    #[test]
+    fn locals_are_scoped() {
+        assert_eq!(error("if (x > 0.) { float a = 1.; }\nreturn a;"), GlslError::new("unknown variable `a`", 2, 8));
+        assert_eq!(error("if (x > 0.) float a = 1.;\nreturn a;"), GlslError::new("unknown variable `a`", 2, 8));
+    }
+}
@@ -0,0 +1,707 @@
+//! Initial boards described by the `init:` section of a program file.
+//!
+//! `init` is a list of layers painted in order onto a black board, each a map
+//! with a single key naming the layer:
+//!
+//! ```yaml
+//! init:
+//!   - fill: {r: 1}
+//!   - circle: {center: [480, 270], radius: 20, channel: g}
+//!   - noise: {density: 0.01, channel: g}
+//! ```
+//!
+//! Positions and sizes are in cells, with `[0, 0]` the bottom-left cell. Most
+//! layers take `channel` (one or more of `rgba`, default `r`), `value` (a
+//! number or a `[min, max]` range, default 1) and `blend` (`set`, `add`,
+//! `multiply` or `max`, default `set`).
+//!
+//! `image` layers copy a PNG, PPM/PGM or PFM file onto the board, given as a
+//! path (relative to the program file) or as a map with `path`, `fit` and
+//! `map`, see `ImageSource`. A single layer can be given without the list, as
+//! in `init: {image: start.png}`.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    cpu::CpuBoard,
+    formats,
+    program::{spec::Node, ProgramError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Blend {
+    Set,
+    Add,
+    Multiply,
+    Max,
+}
+
+impl Blend {
+    fn apply(self, old: f32, new: f32) -> f32 {
+        match self {
+            Blend::Set => new,
+            Blend::Add => old + new,
+            Blend::Multiply => old * new,
+            Blend::Max => old.max(new),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    /// The whole board.
+    Fill,
+    /// Each cell with probability `density`, with a random value from the range.
+    Noise { density: f32 },
+    /// `value` scaled by a normal distribution, to 3 `sigma`.
+    Gaussian { center: (f32, f32), sigma: f32 },
+    Circle { center: (f32, f32), radius: f32 },
+    /// Cells from `from` up to but excluding `to`.
+    Rect { from: (f32, f32), to: (f32, f32) },
+    /// Smooth noise mapped onto the value range, `scale` is the size of a
+    /// lattice cell of the first octave.
+    Perlin { scale: f32, octaves: u32 },
+    ValueNoise { scale: f32, octaves: u32 },
+    /// Channels of an image, scaled by `value`. The layer's channels are the
+    /// board channels of the image's `map`.
+    Image(ImageSource),
+}
+
+/// How an image is fitted onto a board of a different size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fit {
+    /// Scaled to the board size, ignoring the aspect ratio.
+    Stretch,
+    /// Centered without scaling, cells outside the image are left as they are.
+    Crop,
+    /// Scaled to cover the whole board keeping the aspect ratio, centered.
+    Cover,
+}
+
+impl FromStr for Fit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stretch" => Ok(Fit::Stretch),
+            "crop" => Ok(Fit::Crop),
+            "cover" => Ok(Fit::Cover),
+            _ => Err(format!("unknown fit `{}`, expected `stretch`, `crop` or `cover`", s)),
+        }
+    }
+}
+
+/// An image file to start a board from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSource {
+    pub path: PathBuf,
+    pub fit: Fit,
+    /// Pairs of board channel and image channel, indices into RGBA with 4
+    /// standing for the image's luminance.
+    pub map: Vec<(usize, usize)>,
+}
+
+impl ImageSource {
+    /// Stretched, with red, green and blue copied.
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            fit: Fit::Stretch,
+            map: vec![(0, 0), (1, 1), (2, 2)],
+        }
+    }
+
+    /// Parses a channel mapping such as `rgb=rgb`, `g=l` (luminance into
+    /// green) or `r=a,g=l`. Board channels are on the left; a single image
+    /// channel on the right goes to all of them.
+    pub fn parse_map(s: &str) -> Option<Vec<(usize, usize)>> {
+        let mut map = Vec::new();
+        for part in s.split(',') {
+            let (to, from) = part.trim().split_once('=')?;
+            let to: Vec<usize> = to.chars().map(|c| "rgba".find(c)).collect::<Option<_>>()?;
+            let from: Vec<usize> = from.chars().map(|c| "rgbal".find(c)).collect::<Option<_>>()?;
+            match from.len() {
+                1 => map.extend(to.iter().map(|t| (*t, from[0]))),
+                n if n == to.len() => map.extend(to.into_iter().zip(from)),
+                _ => return None,
+            }
+        }
+        Some(map).filter(|map| !map.is_empty())
+    }
+
+    /// Loads the image and fits it onto a `width` x `height` board, `None`
+    /// for cells it doesn't cover.
+    fn fitted(&self, width: u32, height: u32) -> io::Result<Vec<Option<[f32; 5]>>> {
+        let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", self.path.display(), e));
+        let image = formats::load(&self.path).map_err(with_path)?;
+        if image.width == 0 || image.height == 0 {
+            return Err(with_path(io::Error::new(io::ErrorKind::InvalidData, "empty image")));
+        }
+        let (iw, ih) = (image.width as f32, image.height as f32);
+        let (w, h) = (width as f32, height as f32);
+        // Image coordinate of a cell center is (x + 0.5 - offset) / scale - 0.5.
+        let (scale, offset) = match self.fit {
+            Fit::Stretch => ((w / iw, h / ih), (0., 0.)),
+            Fit::Crop => ((1., 1.), (((w - iw) / 2.).floor(), ((h - ih) / 2.).floor())),
+            Fit::Cover => {
+                let s = (w / iw).max(h / ih);
+                ((s, s), ((w - iw * s) / 2., (h - ih * s) / 2.))
+            }
+        };
+
+        let mut cells = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5 - offset.0) / scale.0 - 0.5;
+                let v = (y as f32 + 0.5 - offset.1) / scale.1 - 0.5;
+                let pixel = if self.fit == Fit::Crop {
+                    (u >= 0. && v >= 0. && u < iw && v < ih).then(|| image.get(u as u32, v as u32))
+                } else {
+                    Some(bilinear(&image, u, v))
+                };
+                cells.push(pixel.map(|[r, g, b, a]| [r, g, b, a, 0.2126 * r + 0.7152 * g + 0.0722 * b]));
+            }
+        }
+        Ok(cells)
+    }
+}
+
+/// Samples between pixel centers, clamping at the edges.
+fn bilinear(image: &CpuBoard, u: f32, v: f32) -> [f32; 4] {
+    let u = u.clamp(0., (image.width - 1) as f32);
+    let v = v.clamp(0., (image.height - 1) as f32);
+    let (x0, y0) = (u.floor() as u32, v.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(image.width - 1), (y0 + 1).min(image.height - 1));
+    let (tx, ty) = (u - x0 as f32, v - y0 as f32);
+    let (p00, p10, p01, p11) = (image.get(x0, y0), image.get(x1, y0), image.get(x0, y1), image.get(x1, y1));
+    let mut pixel = [0.; 4];
+    for c in 0..4 {
+        let bottom = lerp(p00[c], p10[c], tx);
+        let top = lerp(p01[c], p11[c], tx);
+        pixel[c] = lerp(bottom, top, ty);
+    }
+    pixel
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub shape: Shape,
+    /// Indices into RGBA.
+    pub channels: Vec<usize>,
+    /// Inclusive range, `min == max` for a constant value.
+    pub value: (f32, f32),
+    pub blend: Blend,
+}
+
+const LAYERS: &str =
+    "a layer: `fill`, `noise`, `gaussian`, `circle`, `rect`, `perlin`, `value_noise` or `image`";
+
+/// Reads the `init` list, empty when the key is absent.
+pub(crate) fn parse(node: Node) -> Result<Vec<Layer>, ProgramError> {
+    if node.yaml.is_badvalue() {
+        return Ok(Vec::new());
+    }
+    let mut layers = Vec::new();
+    if node.yaml.as_hash().is_some() {
+        layer(node, &mut layers)?;
+    } else {
+        for i in 0..node.vec()?.len() {
+            layer(node.index(i), &mut layers)?;
+        }
+    }
+    Ok(layers)
+}
+
+fn layer(item: Node, layers: &mut Vec<Layer>) -> Result<(), ProgramError> {
+    let name = match item.yaml.as_hash().filter(|h| h.len() == 1) {
+        Some(hash) => hash.keys().next().unwrap().as_str().unwrap_or_default().to_string(),
+        None => return Err(item.wrong_type(LAYERS)),
+    };
+    let params = item.get(&name);
+    match name.as_str() {
+        "fill" => {
+            layers.extend(fill(params)?);
+            return Ok(());
+        }
+        "image" => {
+            layers.push(image(params)?);
+            return Ok(());
+        }
+        _ => (),
+    }
+    let shape = match name.as_str() {
+        "noise" => Shape::Noise {
+            density: optional(&params.get("density"), 0.5, Node::f32)?,
+        },
+        "gaussian" => Shape::Gaussian {
+            center: pair(&params.get("center"))?,
+            sigma: positive(&params.get("sigma"))?,
+        },
+        "circle" => Shape::Circle {
+            center: pair(&params.get("center"))?,
+            radius: positive(&params.get("radius"))?,
+        },
+        "rect" => Shape::Rect {
+            from: pair(&params.get("from"))?,
+            to: pair(&params.get("to"))?,
+        },
+        "perlin" => Shape::Perlin {
+            scale: optional(&params.get("scale"), 32., positive)?,
+            octaves: optional(&params.get("octaves"), 1, Node::u32)?,
+        },
+        "value_noise" => Shape::ValueNoise {
+            scale: optional(&params.get("scale"), 32., positive)?,
+            octaves: optional(&params.get("octaves"), 1, Node::u32)?,
+        },
+        _ => return Err(item.wrong_type(LAYERS)),
+    };
+    let default_blend = match shape {
+        // Blobs fade out instead of ending, setting would clear the board.
+        Shape::Gaussian { .. } => Blend::Add,
+        _ => Blend::Set,
+    };
+    layers.push(Layer {
+        shape,
+        channels: optional(&params.get("channel"), vec![0], channels)?,
+        value: optional(&params.get("value"), (1., 1.), range)?,
+        blend: optional(&params.get("blend"), default_blend, blend)?,
+    });
+    Ok(())
+}
+
+/// `image: path` or `image: {path, fit, map}`, with `value` scaling it.
+fn image(node: Node) -> Result<Layer, ProgramError> {
+    let mut source = match node.yaml.as_str() {
+        Some(path) => ImageSource::new(PathBuf::from(path)),
+        None => ImageSource::new(PathBuf::from(node.get("path").str()?)),
+    };
+    if node.yaml.as_hash().is_some() {
+        source.fit = optional(&node.get("fit"), Fit::Stretch, |n| {
+            n.str()?.parse().map_err(|_| n.wrong_type("`stretch`, `crop` or `cover`"))
+        })?;
+        source.map = optional(&node.get("map"), source.map.clone(), |n| {
+            ImageSource::parse_map(n.str()?).ok_or_else(|| n.wrong_type("a channel mapping, e.g. `rgb=rgb` or `g=l`"))
+        })?;
+    }
+    Ok(Layer {
+        channels: source.map.iter().map(|(to, _)| *to).collect(),
+        shape: Shape::Image(source),
+        value: optional(&node.get("value"), (1., 1.), range)?,
+        blend: optional(&node.get("blend"), Blend::Set, blend)?,
+    })
+}
+
+/// `fill: {r: 1, g: 0.5}` becomes one layer per channel.
+fn fill(node: Node) -> Result<Vec<Layer>, ProgramError> {
+    let hash = node
+        .yaml
+        .as_hash()
+        .ok_or_else(|| node.wrong_type("a map of channels to values, e.g. {r: 1}"))?;
+    let mut layers = Vec::new();
+    for key in hash.keys() {
+        let name = key.as_str().unwrap_or_default();
+        if name == "blend" {
+            continue;
+        }
+        let value = node.get(name);
+        let channels: Option<Vec<usize>> = name.chars().map(|c| "rgba".find(c)).collect();
+        let channels = channels
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| value.wrong_type("keyed by channels from `rgba`, e.g. `r` or `rg`"))?;
+        let value = value.f32()?;
+        layers.push(Layer {
+            shape: Shape::Fill,
+            channels,
+            value: (value, value),
+            blend: optional(&node.get("blend"), Blend::Set, blend)?,
+        });
+    }
+    Ok(layers)
+}
+
+fn optional<'a, T>(
+    node: &Node<'a>,
+    default: T,
+    read: impl Fn(&Node<'a>) -> Result<T, ProgramError>,
+) -> Result<T, ProgramError> {
+    if node.yaml.is_badvalue() {
+        Ok(default)
+    } else {
+        read(node)
+    }
+}
+
+fn positive(node: &Node) -> Result<f32, ProgramError> {
+    Some(node.f32()?)
+        .filter(|x| *x > 0.)
+        .ok_or_else(|| node.wrong_type("a positive number"))
+}
+
+fn pair(node: &Node) -> Result<(f32, f32), ProgramError> {
+    if node.vec()?.len() != 2 {
+        return Err(node.wrong_type("a pair [x, y]"));
+    }
+    Ok((node.index(0).f32()?, node.index(1).f32()?))
+}
+
+fn range(node: &Node) -> Result<(f32, f32), ProgramError> {
+    if node.yaml.as_vec().is_some() {
+        pair(node)
+    } else {
+        let value = node.f32()?;
+        Ok((value, value))
+    }
+}
+
+fn channels(node: &Node) -> Result<Vec<usize>, ProgramError> {
+    let names = node.str()?;
+    let channels: Option<Vec<usize>> = names.chars().map(|c| "rgba".find(c)).collect();
+    channels
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| node.wrong_type("channels from `rgba`, e.g. `r` or `rg`"))
+}
+
+fn blend(node: &Node) -> Result<Blend, ProgramError> {
+    match node.str()? {
+        "set" => Ok(Blend::Set),
+        "add" => Ok(Blend::Add),
+        "multiply" => Ok(Blend::Multiply),
+        "max" => Ok(Blend::Max),
+        _ => Err(node.wrong_type("`set`, `add`, `multiply` or `max`")),
+    }
+}
+
+/// Whether any of the layers is random.
+pub fn uses_seed(layers: &[Layer]) -> bool {
+    layers.iter().any(|layer| {
+        matches!(
+            layer.shape,
+            Shape::Noise { .. } | Shape::Perlin { .. } | Shape::ValueNoise { .. }
+        )
+    })
+}
+
+/// Makes relative image paths relative to `dir`, the directory of the
+/// program file.
+pub fn resolve_paths(layers: &mut [Layer], dir: &Path) {
+    for layer in layers {
+        if let Shape::Image(source) = &mut layer.shape {
+            source.path = dir.join(&source.path);
+        }
+    }
+}
+
+/// Paints `layers` onto a black board. Random layers draw from one generator
+/// seeded with `seed`, in order, so the result only depends on the seed.
+/// Fails if an image can't be loaded.
+pub fn initial_board(layers: &[Layer], width: u32, height: u32, seed: u64) -> io::Result<CpuBoard> {
+    let mut board = CpuBoard::new(width, height);
+    let mut rng = StdRng::seed_from_u64(seed);
+    for layer in layers {
+        paint(layer, &mut board, &mut rng)?;
+    }
+    Ok(board)
+}
+
+/// A board with just the image on it, fitted and mapped as `source` says.
+pub fn image_board(source: &ImageSource, width: u32, height: u32) -> io::Result<CpuBoard> {
+    let layer = Layer {
+        channels: source.map.iter().map(|(to, _)| *to).collect(),
+        shape: Shape::Image(source.clone()),
+        value: (1., 1.),
+        blend: Blend::Set,
+    };
+    initial_board(&[layer], width, height, 0)
+}
+
+fn paint(layer: &Layer, board: &mut CpuBoard, rng: &mut StdRng) -> io::Result<()> {
+    let (min, max) = layer.value;
+    if let Shape::Image(source) = &layer.shape {
+        let cells = source.fitted(board.width, board.height)?;
+        for (i, cell) in cells.into_iter().enumerate() {
+            let Some(cell) = cell else { continue };
+            let (x, y) = (i as u32 % board.width, i as u32 / board.width);
+            let mut pixel = board.get(x, y);
+            for (to, from) in &source.map {
+                pixel[*to] = layer.blend.apply(pixel[*to], min * cell[*from]);
+            }
+            board.set(x, y, pixel);
+        }
+        return Ok(());
+    }
+    let lattice = match layer.shape {
+        Shape::Perlin { .. } | Shape::ValueNoise { .. } => Some(Lattice::new(rng)),
+        _ => None,
+    };
+    for y in 0..board.height {
+        for x in 0..board.width {
+            let (fx, fy) = (x as f32, y as f32);
+            let value = match &layer.shape {
+                Shape::Fill => Some(min),
+                Shape::Noise { density } => {
+                    // Always draw both numbers so the pattern doesn't depend on the range.
+                    let (hit, t) = (rng.gen::<f32>() < *density, rng.gen::<f32>());
+                    hit.then_some(min + (max - min) * t)
+                }
+                Shape::Gaussian { center, sigma } => {
+                    let d2 = (fx - center.0).powi(2) + (fy - center.1).powi(2);
+                    (d2 <= (3. * sigma).powi(2)).then(|| min * (-d2 / (2. * sigma * sigma)).exp())
+                }
+                Shape::Circle { center, radius } => {
+                    let d2 = (fx - center.0).powi(2) + (fy - center.1).powi(2);
+                    (d2 <= radius * radius).then_some(min)
+                }
+                Shape::Rect { from, to } => {
+                    let inside = fx >= from.0.min(to.0) && fx < from.0.max(to.0);
+                    (inside && fy >= from.1.min(to.1) && fy < from.1.max(to.1)).then_some(min)
+                }
+                Shape::Perlin { scale, octaves } => {
+                    let n = lattice.as_ref().unwrap().fractal(fx, fy, *scale, *octaves, Lattice::perlin);
+                    Some(min + (max - min) * n)
+                }
+                Shape::ValueNoise { scale, octaves } => {
+                    let n = lattice.as_ref().unwrap().fractal(fx, fy, *scale, *octaves, Lattice::value);
+                    Some(min + (max - min) * n)
+                }
+                Shape::Image(_) => unreachable!("painted above"),
+            };
+            if let Some(value) = value {
+                let mut pixel = board.get(x, y);
+                for c in &layer.channels {
+                    pixel[*c] = layer.blend.apply(pixel[*c], value);
+                }
+                board.set(x, y, pixel);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Random values and gradients on an integer lattice repeating every 256
+/// cells, shared by Perlin and value noise.
+struct Lattice {
+    perm: [u8; 512],
+    values: [f32; 256],
+}
+
+impl Lattice {
+    fn new(rng: &mut StdRng) -> Self {
+        let mut perm = [0u8; 512];
+        let mut table: Vec<u8> = (0..=255).collect();
+        for i in (1..256).rev() {
+            table.swap(i, rng.gen_range(0..=i));
+        }
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i % 256];
+        }
+        let mut values = [0.; 256];
+        for v in values.iter_mut() {
+            *v = rng.gen();
+        }
+        Self { perm, values }
+    }
+
+    fn hash(&self, x: i32, y: i32) -> usize {
+        let x = (x & 255) as usize;
+        let y = (y & 255) as usize;
+        self.perm[self.perm[x] as usize + y] as usize
+    }
+
+    /// Octaves of noise, each at half the scale and half the weight of the
+    /// previous one, normalized to 0..1.
+    fn fractal(&self, x: f32, y: f32, scale: f32, octaves: u32, noise: fn(&Self, f32, f32) -> f32) -> f32 {
+        let (mut sum, mut weight, mut total, mut scale) = (0., 1., 0., scale);
+        for _ in 0..octaves.max(1) {
+            sum += noise(self, x / scale, y / scale) * weight;
+            total += weight;
+            weight *= 0.5;
+            scale = (scale * 0.5).max(1.);
+        }
+        sum / total
+    }
+
+    /// Interpolated random lattice values, in 0..1.
+    fn value(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+        let (u, v) = (fade(x - x0 as f32), fade(y - y0 as f32));
+        let at = |dx, dy| self.values[self.hash(x0 + dx, y0 + dy)];
+        lerp(lerp(at(0, 0), at(1, 0), u), lerp(at(0, 1), at(1, 1), u), v)
+    }
+
+    /// Classic gradient noise, mapped from about -1..1 to 0..1.
+    fn perlin(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let (u, v) = (fade(fx), fade(fy));
+        let grad = |dx: i32, dy: i32| {
+            let (px, py) = (fx - dx as f32, fy - dy as f32);
+            match self.hash(x0 + dx, y0 + dy) & 7 {
+                0 => px + py,
+                1 => px - py,
+                2 => -px + py,
+                3 => -px - py,
+                4 => px,
+                5 => -px,
+                6 => py,
+                _ => -py,
+            }
+        };
+        let n = lerp(lerp(grad(0, 0), grad(1, 0), u), lerp(grad(0, 1), grad(1, 1), u), v);
+        (n * 0.5 + 0.5).clamp(0., 1.)
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::ProgramSpec;
+
+    /// Layers of a 4x4 program whose `init:` key is on line 5.
+    fn layers(init: &str) -> Result<Vec<Layer>, ProgramError> {
+        let src = format!("screen: [4, 4]\ntype: val\nkernel: [[1]]\nfun: return x;\ninit:{}", init);
+        src.parse::<ProgramSpec>().map(|spec| spec.init)
+    }
+
+    fn board(init: &str, seed: u64) -> CpuBoard {
+        initial_board(&layers(init).unwrap(), 4, 4, seed).unwrap()
+    }
+
+    /// Red of the cells, top row first as the board would be drawn.
+    fn red(board: &CpuBoard) -> Vec<Vec<f32>> {
+        (0..board.height).rev().map(|y| (0..board.width).map(|x| board.get(x, y)[0]).collect()).collect()
+    }
+
+    #[test]
+    fn parse_layers() {
+        let circle = "\n  - circle: {center: [1, 2], radius: 3, channel: gb, value: [0.5, 1], blend: max}\n";
+        let parsed = layers(circle).unwrap();
+        let expected = Layer {
+            shape: Shape::Circle { center: (1., 2.), radius: 3. },
+            channels: vec![1, 2],
+            value: (0.5, 1.),
+            blend: Blend::Max,
+        };
+        assert_eq!(parsed, [expected]);
+        // One layer per key of `fill`, gaussians add by default.
+        let parsed = layers("\n  - fill: {r: 1, ga: 0.5}\n  - gaussian: {center: [0, 0], sigma: 2}\n").unwrap();
+        let channels: Vec<_> = parsed.iter().map(|layer| (layer.channels.clone(), layer.value, layer.blend)).collect();
+        assert_eq!(
+            channels,
+            [(vec![0], (1., 1.), Blend::Set), (vec![1, 3], (0.5, 0.5), Blend::Set), (vec![0], (1., 1.), Blend::Add)]
+        );
+        assert_eq!(layers(" []\n").unwrap(), []);
+    }
+
+    #[test]
+    fn a_single_layer_needs_no_list() {
+        let single = layers(" {noise: {density: 0.25}}\n").unwrap();
+        assert_eq!(single, layers("\n  - noise: {density: 0.25}\n").unwrap());
+        assert_eq!(layers("\n  rect: {from: [0, 0], to: [2, 2]}\n").unwrap().len(), 1);
+        let image = layers(" {image: {path: start.png, fit: cover, map: g=l}}\n").unwrap();
+        let source = ImageSource { path: PathBuf::from("start.png"), fit: Fit::Cover, map: vec![(1, 4)] };
+        assert_eq!((&image[0].shape, &image[0].channels), (&Shape::Image(source), &vec![1]));
+    }
+
+    #[test]
+    fn parse_errors_have_lines() {
+        let errors = [
+            ("\n  - fill: {r: 1}\n  - square: {}\n", "line 7: `init[1]` must be a layer"),
+            ("\n  - fill: {x: 1}\n", "line 6: `init[0].fill.x` must be keyed by channels from `rgba`"),
+            ("\n  - circle: {center: [1, 2], radius: 0}\n", "line 6: `init[0].circle.radius` must be a positive"),
+            ("\n  - circle: {radius: 1}\n", "line 6: missing `init[0].circle.center`"),
+            ("\n  - rect: {from: [0], to: [1, 1]}\n", "line 6: `init[0].rect.from` must be a pair [x, y]"),
+            ("\n  - noise:\n      channel: x\n", "line 7: `init[0].noise.channel` must be channels from `rgba`"),
+            ("\n  - fill: {r: 1, blend: mix}\n", "line 6: `init[0].fill.blend` must be `set`, `add`"),
+            ("\n  - image: {path: a.png, fit: zoom}\n", "line 6: `init[0].image.fit` must be `stretch`, `crop`"),
+            (" 3\n", "line 5: `init` must be a list"),
+        ];
+        for (init, message) in errors {
+            let error = layers(init).unwrap_err().to_string();
+            assert!(error.starts_with(message), "{} gave {}", init, error);
+        }
+    }
+
+    #[test]
+    fn shapes() {
+        assert!(board("\n  - fill: {rb: 0.5}\n", 0).data.chunks(4).all(|p| p == [0.5, 0., 0.5, 1.]));
+        let circle = [
+            [0., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [1., 1., 1., 0.],
+            [0., 1., 0., 0.],
+        ];
+        assert_eq!(red(&board("\n  - circle: {center: [1, 1], radius: 1}\n", 0)), circle);
+        // Up to but excluding `to`, corners in any order.
+        let rect = [
+            [0., 0., 0., 0.],
+            [0., 0., 0., 0.],
+            [0., 2., 2., 0.],
+            [0., 0., 0., 0.],
+        ];
+        assert_eq!(red(&board("\n  - rect: {from: [3, 2], to: [1, 1], value: 2}\n", 0)), rect);
+
+        let gaussian = board("\n  - gaussian: {center: [0, 0], sigma: 1, value: 2}\n", 0);
+        assert_eq!(gaussian.get(0, 0)[0], 2.);
+        assert_eq!(gaussian.get(1, 0)[0], 2. * (-0.5f32).exp());
+        assert_eq!(gaussian.get(3, 0)[0], 2. * (-4.5f32).exp());
+        // Beyond 3 sigma.
+        assert_eq!(gaussian.get(3, 1)[0], 0.);
+    }
+
+    #[test]
+    fn the_seed_decides_random_layers() {
+        for init in [
+            "\n  - noise: {value: [0.25, 0.75]}\n",
+            "\n  - perlin: {scale: 2, octaves: 3, value: [0, 1]}\n",
+            "\n  - value_noise: {scale: 2, octaves: 2, value: [0, 1]}\n",
+        ] {
+            assert!(uses_seed(&layers(init).unwrap()));
+            let one = board(init, 1);
+            assert_eq!(one, board(init, 1), "{}", init);
+            assert_ne!(one, board(init, 2), "{}", init);
+            assert!(one.data.chunks(4).all(|p| (0. ..=1.).contains(&p[0])), "{}", init);
+        }
+        let noise = board("\n  - noise: {value: [0.25, 0.75]}\n", 1);
+        assert!(noise.data.chunks(4).all(|p| p[0] == 0. || (0.25..=0.75).contains(&p[0])));
+        assert!(!uses_seed(&layers("\n  - fill: {r: 1}\n").unwrap()));
+    }
+
+    #[test]
+    fn blending() {
+        let blended = |blend: &str| {
+            let rect = format!("{{from: [0, 0], to: [1, 1], value: 0.25, blend: {}}}", blend);
+            let init = format!("\n  - fill: {{r: 0.5}}\n  - rect: {}\n", rect);
+            board(&init, 0).get(0, 0)[0]
+        };
+        assert_eq!(blended("set"), 0.25);
+        assert_eq!(blended("add"), 0.75);
+        assert_eq!(blended("multiply"), 0.125);
+        assert_eq!(blended("max"), 0.5);
+        // Layers are painted in order, the later fill wins.
+        assert!(board("\n  - fill: {r: 1}\n  - fill: {r: 0.5}\n", 0).data.chunks(4).all(|p| p[0] == 0.5));
+    }
+
+    #[test]
+    fn channel_maps() {
+        assert_eq!(ImageSource::parse_map("rgb=rgb"), Some(vec![(0, 0), (1, 1), (2, 2)]));
+        assert_eq!(ImageSource::parse_map("g=l"), Some(vec![(1, 4)]));
+        assert_eq!(ImageSource::parse_map("r=a, gb=l"), Some(vec![(0, 3), (1, 4), (2, 4)]));
+        for bad in ["", "rg=rgb", "x=r", "r", "r=q"] {
+            assert_eq!(ImageSource::parse_map(bad), None, "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+pub mod backend;
+pub mod board;
+pub mod cpu;
+pub mod cycle;
+pub mod formats;
+pub mod glsl;
+pub mod init;
+pub mod program;
+pub mod record;
+pub mod sheet;
+pub mod state;
+pub mod stats;
+pub mod stop;
+pub mod watch;
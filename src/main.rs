@@ -1,83 +1,528 @@
-use std::{env, fs, time};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process, time,
+};
 
 use glium::{
     glutin::{
         dpi::PhysicalSize,
-        event::{self, ElementState, MouseButton},
+        event::{self, ElementState, ModifiersState, MouseButton},
         window::Fullscreen,
     },
-    texture::{MipmapsOption, UncompressedFloatFormat},
-    BlitTarget, Surface,
+    BlitTarget, Surface, Texture2d,
 };
-use yaml_rust::YamlLoader;
 
-use crate::{
-    board::{empty_board, random_board, random_board_binary},
-    program::program_from_yaml,
+use gpu_convolution::{
+    backend::{Backend, GlBackend},
+    board::{empty_board, random_board, random_board_binary, random_seed, InitialBoard},
+    cpu::CpuBoard,
+    formats::{self, Format},
+    init::{self, Fit, ImageSource},
+    cycle::{CycleAction, CycleDetector},
+    program::{shader, Params, Program, ProgramSpec, StageDuration},
+    record::{RecordOptions, Recorder},
+    state::State,
+    stats::StatsWriter,
+    watch::FileWatch,
 };
 
-mod board;
-mod program;
+mod check;
+mod run;
+mod sweep;
+
+const USAGE: &str = "usage: gpu_convolution <program.yaml | directory> [--seed N] [--init INIT] [--fit FIT] [--map MAP]
+                                                            [--export FORMAT] [--export-dir DIR]
+                                                            [--record PATH] [--record-every N] [--record-scale N]
+                                                            [--stats FILE] [--stats-every N]
+                                                            [--cycle print|pause|reseed] [--cycle-every N]
+                                                            [--param NAME=VALUE]...
+       gpu_convolution --resume <file.state> [options]
+       gpu_convolution run <program.yaml> [options]
+       gpu_convolution sweep <program.yaml> --param NAME=VALUES... [options]
+       gpu_convolution check <program.yaml | directory>...";
+
+struct ViewerArgs {
+    /// Path of the program, or of the state file it is resumed from.
+    program: String,
+    resume: bool,
+    seed: Option<u64>,
+    init: InitialBoard,
+    /// Format and directory of the boards saved with `e`.
+    export: Format,
+    export_dir: PathBuf,
+    /// Recording started with the viewer, and the settings of recordings
+    /// started with `v`.
+    record: Option<PathBuf>,
+    record_every: u32,
+    record_scale: u32,
+    stats: Option<PathBuf>,
+    stats_every: u32,
+    /// What to do when the board stops changing or repeats, nothing if unset.
+    cycle: Option<CycleAction>,
+    cycle_every: u32,
+    /// `--param` values, applied over the program's or state file's.
+    params: Vec<(String, f32)>,
+}
+
+impl ViewerArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut program = None;
+        let mut resume = None;
+        let mut seed = None;
+        let mut init = InitialBoard::Program;
+        let (mut fit, mut map) = (None, None);
+        let mut export = Format::Png;
+        let mut export_dir = PathBuf::from(".");
+        let mut record = None;
+        let (mut record_every, mut record_scale) = (1, 1);
+        let (mut stats, mut stats_every) = (None, 1);
+        let (mut cycle, mut cycle_every) = (None, 1);
+        let mut params = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => seed = Some(parse_seed(args.next())?),
+                "--resume" => resume = Some(args.next().ok_or("`--resume` needs a state file")?.clone()),
+                "--init" => init = args.next().ok_or("`--init` needs a value")?.parse().unwrap(),
+                "--fit" => fit = Some(parse_fit(args.next())?),
+                "--map" => map = Some(parse_map(args.next())?),
+                "--export" => export = args.next().ok_or("`--export` needs a value")?.parse()?,
+                "--export-dir" => export_dir = PathBuf::from(args.next().ok_or("`--export-dir` needs a value")?),
+                "--record" => record = Some(PathBuf::from(args.next().ok_or("`--record` needs a value")?)),
+                "--record-every" => record_every = parse_positive(arg, args.next())?,
+                "--record-scale" => record_scale = parse_positive(arg, args.next())?,
+                "--stats" => stats = Some(PathBuf::from(args.next().ok_or("`--stats` needs a value")?)),
+                "--stats-every" => stats_every = parse_positive(arg, args.next())?,
+                "--cycle" => cycle = Some(args.next().ok_or("`--cycle` needs a value")?.parse()?),
+                "--cycle-every" => cycle_every = parse_positive(arg, args.next())?,
+                "--param" => params.push(parse_param(args.next())?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ if program.is_none() => program = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+        set_image_options(&mut init, fit, map)?;
+        let (program, resume) = match (program, resume) {
+            (Some(program), None) => (program, false),
+            (None, Some(state)) => (state, true),
+            (Some(_), Some(_)) => return Err("`--resume` takes the program from the state file, leave out the program".into()),
+            (None, None) => return Err("missing program file".into()),
+        };
+        Ok(Self {
+            program,
+            resume,
+            seed,
+            init,
+            export,
+            export_dir,
+            record,
+            record_every,
+            record_scale,
+            stats,
+            stats_every,
+            cycle,
+            cycle_every,
+            params,
+        })
+    }
+}
+
+fn parse_seed(value: Option<&String>) -> Result<u64, String> {
+    let value = value.ok_or("`--seed` needs a value")?;
+    value
+        .parse()
+        .map_err(|_| format!("`--seed` must be a non-negative integer, got `{}`", value))
+}
+
+fn parse_positive(arg: &str, value: Option<&String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("`{}` needs a value", arg))?;
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("`{}` must be a positive number, got `{}`", arg, value)),
+    }
+}
+
+fn parse_fit(value: Option<&String>) -> Result<Fit, String> {
+    value.ok_or("`--fit` needs a value")?.parse()
+}
+
+fn parse_map(value: Option<&String>) -> Result<Vec<(usize, usize)>, String> {
+    let value = value.ok_or("`--map` needs a value")?;
+    ImageSource::parse_map(value).ok_or(format!("`--map` must look like `rgb=rgb` or `g=l`, got `{}`", value))
+}
+
+fn parse_param(value: Option<&String>) -> Result<(String, f32), String> {
+    let value = value.ok_or("`--param` needs a value")?;
+    let error = || format!("`--param` must look like `NAME=VALUE`, e.g. `f=0.055`, got `{}`", value);
+    let (name, number) = value.split_once('=').ok_or_else(error)?;
+    Ok((name.trim().to_string(), number.trim().parse().map_err(|_| error())?))
+}
+
+/// Sets parameter values on a program, every name must be in its `params:`.
+fn set_params<B>(program: &mut dyn Program<B>, params: &[(String, f32)]) -> Result<(), String> {
+    for (name, value) in params {
+        if !program.set_param(name, *value) {
+            return Err(format!("the program has no parameter `{}`", name));
+        }
+    }
+    Ok(())
+}
+
+/// Applies `--fit` and `--map`, which only make sense for an image file.
+fn set_image_options(init: &mut InitialBoard, fit: Option<Fit>, map: Option<Vec<(usize, usize)>>) -> Result<(), String> {
+    match init {
+        InitialBoard::File(source) => {
+            source.fit = fit.unwrap_or(source.fit);
+            source.map = map.unwrap_or_else(|| source.map.clone());
+            Ok(())
+        }
+        _ if fit.is_some() || map.is_some() => Err("`--fit` and `--map` need `--init` with an image file".into()),
+        _ => Ok(()),
+    }
+}
+
 fn main() {
     use glium::glutin;
 
     let args: Vec<String> = env::args().collect();
 
-    let doc = if let Some(path) = args.get(1) {
-        let string = fs::read_to_string(path).unwrap();
-        let docs = YamlLoader::load_from_str(&string).unwrap();
-        docs[0].to_owned()
+    let viewer_args = match args.get(1).map(String::as_str) {
+        Some("run") => process::exit(run::main(&args[2..])),
+        Some("sweep") => process::exit(sweep::main(&args[2..])),
+        Some("check") => process::exit(check::main(&args[2..])),
+        Some(_) => match ViewerArgs::parse(&args[1..]) {
+            Ok(viewer_args) => viewer_args,
+            Err(e) => {
+                eprintln!("{}\n{}", e, USAGE);
+                process::exit(2);
+            }
+        },
+        None => {
+            println!("{}", USAGE);
+            return;
+        }
+    };
+    let path = Path::new(&viewer_args.program);
+    let resumed = viewer_args.resume.then(|| match State::load(path) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            process::exit(1);
+        }
+    });
+    // Programs `n` and `p` switch between, all in the directory when given one.
+    let browsing = path.is_dir();
+    let programs = if browsing {
+        match check::yaml_files(path) {
+            Ok(files) if !files.is_empty() => files,
+            Ok(_) => {
+                eprintln!("{}: no .yaml or .yml programs", path.display());
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
     } else {
-        println!("Provide a program .yaml file.");
-        return;
+        vec![path.to_path_buf()]
+    };
+    // Index of the running program in `programs`, the first one that parses.
+    let mut current = 0;
+    // The source is kept for save states, which carry the program with them.
+    let (mut source, mut stages) = match &resumed {
+        Some(state) => parse_program(&state.program, path).map(|stages| (state.program.clone(), stages)),
+        None => loop {
+            match read_program(&programs[current]) {
+                Err(e) if current + 1 < programs.len() => {
+                    eprintln!("{}: {}", programs[current].display(), e);
+                    current += 1;
+                }
+                read => break read,
+            }
+        },
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("{}: {}", programs[current].display(), e);
+        process::exit(1);
+    });
+    // Index in `stages` of the running document of a playlist, and its spec.
+    let mut stage = resumed.as_ref().map_or(0, |state| state.stage).min(stages.len() - 1);
+    let mut spec = stages[stage].clone();
+    let mut name = match &resumed {
+        Some(state) => state.name.clone(),
+        None => program_name(&programs[current]),
     };
+    // Seed of the next random fill, replaced after every fill.
+    let mut seed = viewer_args.seed.or(spec.seed).unwrap_or_else(random_seed);
 
-    let height = doc["screen"][1].as_i64().unwrap().try_into().unwrap();
-    let width = doc["screen"][0].as_i64().unwrap().try_into().unwrap();
-    let aspect_ratio = width as f32 / height as f32;
+    let (width, height) = (spec.width, spec.height);
+    let mut aspect_ratio = width as f32 / height as f32;
 
     let event_loop = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         // .with_fullscreen(Some(glutin::window::Fullscreen::Borderless(None)))
         .with_min_inner_size(PhysicalSize::new(width, height))
         .with_inner_size(PhysicalSize::new(width, height))
+        .with_title(&name)
         .with_decorations(true);
     let cb = glutin::ContextBuilder::new();
-    let display = glium::Display::new(wb, cb, &event_loop).unwrap();
+    let backend = GlBackend::new(glium::Display::new(wb, cb, &event_loop).unwrap());
+    let display = backend.facade().clone();
+
+    let mut program = match backend.program(&spec) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let saved_params = resumed.as_ref().map_or(&[][..], |state| &state.params[..]);
+    if browsing {
+        // Only the programs having them get `--param` values.
+        for (param, value) in &viewer_args.params {
+            program.set_param(param, *value);
+        }
+    } else {
+        for params in [saved_params, &viewer_args.params] {
+            if let Err(e) = set_params(program.as_mut(), params) {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+    let (mut width, mut height) = program.get_dimensions();
+    // Parameter changed by `[`, `]`, PgUp and PgDn, picked with Tab.
+    let mut selected_param = 0;
+    let position = (current, programs.len());
+    let stage_position = (stage, stages.len());
+    let title = window_title(&name, position, stage_position, program.params(), selected_param);
+    display.gl_window().window().set_title(&title);
 
-    let program = program_from_yaml(&doc, &display);
+    let mut speed = 32;
+    // Steps since the board was last filled, for the names of saved boards.
+    let mut step: u64 = 0;
+    // Time spent in the playlist stage, for its `duration`.
+    let mut stage_steps: u64 = 0;
+    let mut stage_started = time::Instant::now();
 
-    let mut board = glium::texture::Texture2d::with_format(
-        &display,
-        vec![vec![(0., 0., 0., 1.); width as usize]; height as usize],
-        UncompressedFloatFormat::F32F32F32F32,
-        MipmapsOption::NoMipmap,
-    )
-    .unwrap();
+    let initial_board = viewer_args.init;
+    let mut board = if let Some(state) = &resumed {
+        if (state.board.width, state.board.height) != (width, height) {
+            eprintln!(
+                "{}: board is {}x{}, program needs {}x{}",
+                path.display(),
+                state.board.width,
+                state.board.height,
+                width,
+                height
+            );
+            process::exit(1);
+        }
+        speed = state.speed;
+        step = state.step;
+        seed = viewer_args.seed.unwrap_or(state.seed);
+        backend.upload(&state.board)
+    } else {
+        let board = match initial_board.board(&spec, seed) {
+            Ok(start) => backend.upload(&start),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+        if initial_board.uses_seed(&spec) {
+            println!("seed: {}", seed);
+            seed = random_seed();
+        }
+        board
+    };
 
-    let mut mouse_pressed = (false, false);
+    let mut mouse_pressed = false;
     let mut active_color = ActiveColor::Red;
 
     let mut last_frame_instant = time::Instant::now();
     let mut last_frame_time = time::Duration::ZERO;
 
-    let mut speed = 32;
     let mut step_counter = 0;
 
+    let (record_every, record_scale) = (viewer_args.record_every, viewer_args.record_scale);
+    let record_options = move |path: PathBuf| RecordOptions {
+        path,
+        every: record_every,
+        scale: record_scale,
+    };
+    let mut recorder = viewer_args
+        .record
+        .clone()
+        .and_then(|path| start_recording(record_options(path), &backend, &board, width, height));
+
+    let mut stats_writer = viewer_args.stats.as_ref().and_then(|path| match StatsWriter::create(path) {
+        Ok(writer) => Some((writer, path.clone())),
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            None
+        }
+    });
+    let stats_every = viewer_args.stats_every;
+    write_stats(&mut stats_writer, stats_every, step, &backend, &board);
+
+    let cycle_action = viewer_args.cycle;
+    let mut cycles = CycleDetector::new(viewer_args.cycle_every);
+
     let mut draw_queue = Vec::<(u32, u32)>::new();
 
+    // A state file carries its program, there is nothing to reload.
+    let mut watch = (!viewer_args.resume).then(|| FileWatch::new(&programs[current]));
+    // Program to switch to, with its index in `programs` and how the board
+    // carries over.
+    let mut pending: Option<(usize, Loaded<Texture2d>, Carry)> = None;
+    let mut modifiers = ModifiersState::empty();
+
     event_loop.run(move |ev, _, control_flow| {
         if last_frame_instant.elapsed() >= time::Duration::from_nanos(16_666_667) {
             last_frame_instant = time::Instant::now();
 
-            if speed < 32 {
-                step_counter += 1;
-                if speed > 0 && step_counter % (32 / speed) == 0 {
-                    program.step(&mut board);
+            if let Some(watch) = watch.as_mut().filter(|_| pending.is_none()) {
+                if watch.changed() {
+                    match reload(watch.path(), &backend, stage, &spec, program.as_ref()) {
+                        Ok(loaded) => {
+                            println!("reloaded {}", watch.path().display());
+                            pending = Some((current, loaded, Carry::Crop));
+                        }
+                        Err(e) => eprintln!("{}: {}", watch.path().display(), e),
+                    }
+                }
+            }
+            let stage_over = match spec.duration {
+                Some(StageDuration::Steps(steps)) => stage_steps >= steps,
+                Some(StageDuration::Seconds(seconds)) => stage_started.elapsed().as_secs_f32() >= seconds,
+                None => false,
+            };
+            if stage_over && stages.len() > 1 && pending.is_none() {
+                let next = (stage + 1) % stages.len();
+                match backend.program(&stages[next]) {
+                    Ok(program) => {
+                        let loaded = Loaded {
+                            source: source.clone(),
+                            stages: stages.clone(),
+                            stage: next,
+                            program,
+                        };
+                        pending = Some((current, loaded, Carry::Resample));
+                    }
+                    Err(e) => {
+                        eprintln!("stage {}: {}", next + 1, e);
+                        (stage_steps, stage_started) = (0, time::Instant::now());
+                    }
+                }
+            }
+            if let Some((index, mut loaded, carry)) = pending.take() {
+                if index != current {
+                    current = index;
+                    name = program_name(&programs[current]);
+                    if watch.is_some() {
+                        watch = Some(FileWatch::new(&programs[current]));
+                    }
+                    println!("program: {}", name);
+                }
+                // Anything but a reload of the same stage starts a new program.
+                if carry != Carry::Crop || loaded.stage != stage {
+                    for (param, value) in &viewer_args.params {
+                        loaded.program.set_param(param, *value);
+                    }
+                    (stage_steps, stage_started) = (0, time::Instant::now());
+                    if loaded.stages.len() > 1 {
+                        println!("stage {}/{}", loaded.stage + 1, loaded.stages.len());
+                    }
+                }
+                let (new_width, new_height) = loaded.program.get_dimensions();
+                let resized = (new_width, new_height) != (width, height);
+                if resized {
+                    if recorder.is_some() {
+                        println!("board resized, recording stopped");
+                        stop_recording(recorder.take());
+                    }
+                    (width, height) = (new_width, new_height);
+                    aspect_ratio = width as f32 / height as f32;
+                    let gl_window = display.gl_window();
+                    gl_window.window().set_min_inner_size(Some(PhysicalSize::new(width, height)));
+                    gl_window.window().set_inner_size(PhysicalSize::new(width, height));
+                    draw_queue.clear();
+                }
+                spec = loaded.stages[loaded.stage].clone();
+                (source, stages, stage, program) = (loaded.source, loaded.stages, loaded.stage, loaded.program);
+                match carry {
+                    Carry::Reset => {
+                        if let Some(start) = initial(&initial_board, &spec, &mut seed, &backend) {
+                            board = start;
+                            step = 0;
+                        }
+                    }
+                    Carry::Crop if resized => {
+                        board = backend.upload(&backend.download(&board).resized(width, height));
+                    }
+                    Carry::Resample if resized => {
+                        board = backend.upload(&backend.download(&board).resampled(width, height));
+                    }
+                    _ => (),
+                }
+                if selected_param >= program.params().len() {
+                    selected_param = 0;
                 }
+                let position = (current, programs.len());
+                let stage_position = (stage, stages.len());
+                let title = window_title(&name, position, stage_position, program.params(), selected_param);
+                display.gl_window().window().set_title(&title);
+                cycles.reset();
+            }
+
+            let steps = if speed < 32 {
+                step_counter += 1;
+                (speed > 0 && step_counter % (32 / speed) == 0) as u32
             } else {
-                for _ in 1..=speed / 32 {
-                    program.step(&mut board);
+                speed / 32
+            };
+            for _ in 0..steps {
+                program.step(&mut board);
+                step += 1;
+                stage_steps += 1;
+                record_step(&mut recorder, &backend, &board);
+                write_stats(&mut stats_writer, stats_every, step, &backend, &board);
+                // The next stage starts with the following frame.
+                if spec.duration == Some(StageDuration::Steps(stage_steps)) && stages.len() > 1 {
+                    break;
+                }
+
+                let Some(action) = cycle_action.filter(|_| cycles.due(step)) else { continue };
+                let Some(cycle) = cycles.check(step, &backend.download(&board)) else { continue };
+                println!("step {}: {}", step, cycle);
+                match action {
+                    CycleAction::Print => (),
+                    CycleAction::Pause => {
+                        speed = 0;
+                        break;
+                    }
+                    CycleAction::Reseed => {
+                        // Like `i` when the start board is random, like space otherwise.
+                        let start = if initial_board.uses_seed(&spec) {
+                            initial_board.board(&spec, seed)
+                        } else {
+                            Ok(CpuBoard::from_values(&random_board_binary(width, height, seed)))
+                        };
+                        match start {
+                            Ok(start) => {
+                                println!("seed: {}", seed);
+                                board = backend.upload(&start);
+                                seed = random_seed();
+                                step = 0;
+                                cycles.reset();
+                            }
+                            Err(e) => eprintln!("{}", e),
+                        }
+                        break;
+                    }
                 }
             }
 
@@ -109,25 +554,19 @@ fn main() {
             target.finish().unwrap();
 
             if !draw_queue.is_empty() {
-                let mut buffer: Vec<Vec<(f32, f32, f32, f32)>> = unsafe { board.unchecked_read() };
+                let mut buffer = backend.download(&board);
 
-                // let mut map_write = buffer.map_write();
                 for (x, y) in &draw_queue {
-                    buffer[*y as usize][*x as usize] = match active_color {
-                        ActiveColor::Red => (1., 0., 0., 1.),
-                        ActiveColor::Green => (0., 1., 0., 1.),
-                        ActiveColor::Blue => (0., 0., 1., 1.),
-                        ActiveColor::White => (1., 1., 1., 1.),
-                    }
+                    buffer.set(*x, *y, match active_color {
+                        ActiveColor::Red => [1., 0., 0., 1.],
+                        ActiveColor::Green => [0., 1., 0., 1.],
+                        ActiveColor::Blue => [0., 0., 1., 1.],
+                        ActiveColor::White => [1., 1., 1., 1.],
+                    });
                 }
-                board = glium::texture::Texture2d::with_format(
-                    &display,
-                    buffer,
-                    UncompressedFloatFormat::F32F32F32F32,
-                    MipmapsOption::NoMipmap,
-                )
-                .unwrap();
+                board = backend.upload(&buffer);
                 draw_queue.truncate(0);
+                cycles.reset();
             }
 
             last_frame_time = last_frame_instant.elapsed();
@@ -136,105 +575,415 @@ fn main() {
             }
         }
 
+        if let event::Event::LoopDestroyed = ev {
+            stop_recording(recorder.take());
+        }
+
         // println!("{} ms/f", next_frame_time.duration_since(last_frame_time).as_millis());
-        match ev {
-            event::Event::WindowEvent { event, .. } => match event {
+        if let event::Event::WindowEvent { event, .. } = ev {
+            match event {
+                event::WindowEvent::ModifiersChanged(state) => modifiers = state,
                 event::WindowEvent::CloseRequested => {
                     *control_flow = glutin::event_loop::ControlFlow::Exit;
-                    return;
                 }
                 event::WindowEvent::MouseInput { state, button, .. } => match (state, button) {
-                    (ElementState::Pressed, MouseButton::Left) => mouse_pressed.0 = true,
-                    (ElementState::Released, MouseButton::Left) => mouse_pressed.0 = false,
-                    (ElementState::Pressed, MouseButton::Right) => mouse_pressed.1 = true,
-                    (ElementState::Released, MouseButton::Right) => mouse_pressed.1 = false,
+                    (ElementState::Pressed, MouseButton::Left) => mouse_pressed = true,
+                    (ElementState::Released, MouseButton::Left) => mouse_pressed = false,
                     _ => (),
                 },
-                event::WindowEvent::CursorMoved { position, .. } => {
-                    if mouse_pressed.0 {
-                        let inner_size = display.gl_window().window().inner_size();
-                        let x: u32 = (position.x as u32 * width) / inner_size.width;
-                        let t: u32 = (position.y as u32 * height) / inner_size.height;
-                        let y: u32 = height - t.min(height);
-                        // println!("mp: {} {}, inner_size: {} {}, board_size: {} {}, xy: {} {}", mouse_pos.x, mouse_pos.y, inner_size.width, inner_size.height, width, height, x, y);
-                        draw_queue.push((x.min(width - 1), y.min(height - 1)));
-                    }
+                event::WindowEvent::CursorMoved { position, .. } if mouse_pressed => {
+                    let inner_size = display.gl_window().window().inner_size();
+                    let x: u32 = (position.x as u32 * width) / inner_size.width;
+                    let t: u32 = (position.y as u32 * height) / inner_size.height;
+                    let y: u32 = height - t.min(height);
+                    // println!("mp: {} {}, inner_size: {} {}, board_size: {} {}, xy: {} {}", mouse_pos.x, mouse_pos.y, inner_size.width, inner_size.height, width, height, x, y);
+                    draw_queue.push((x.min(width - 1), y.min(height - 1)));
                 }
-                event::WindowEvent::KeyboardInput { input, .. } => {
-                    if input.state == ElementState::Pressed {
-                        match input.scancode {
-                            57 => {
-                                // space
-                                board = glium::texture::Texture2d::with_format(
-                                    &display,
-                                    random_board_binary(width, height),
-                                    UncompressedFloatFormat::F32F32F32F32,
-                                    MipmapsOption::NoMipmap,
-                                )
-                                .unwrap();
+                event::WindowEvent::KeyboardInput { input, .. } if input.state == ElementState::Pressed => {
+                    match input.scancode {
+                        57 => {
+                            // space
+                            println!("seed: {}", seed);
+                            board = backend.upload(&CpuBoard::from_values(&random_board_binary(width, height, seed)));
+                            step = 0;
+                            cycles.reset();
+                            seed = random_seed();
+                        }
+                        45 => {
+                            // x
+                            println!("seed: {}", seed);
+                            board = backend.upload(&CpuBoard::from_values(&random_board(width, height, seed)));
+                            step = 0;
+                            cycles.reset();
+                            seed = random_seed();
+                        }
+                        23 => {
+                            // i
+                            if let Some(start) = initial(&initial_board, &spec, &mut seed, &backend) {
+                                board = start;
+                                step = 0;
+                                cycles.reset();
                             }
-                            45 => {
-                                // x
-                                board = glium::texture::Texture2d::with_format(
-                                    &display,
-                                    random_board(width, height),
-                                    UncompressedFloatFormat::F32F32F32F32,
-                                    MipmapsOption::NoMipmap,
-                                )
-                                .unwrap();
+                        }
+                        46 => {
+                            // c
+                            board = backend.upload(&CpuBoard::from_values(&empty_board(width, height)));
+                            step = 0;
+                            cycles.reset();
+                        }
+                        19 | 34 | 48 | 17 => {
+                            // r, g, b, w
+                            match input.scancode {
+                                34 => active_color = ActiveColor::Green,
+                                48 => active_color = ActiveColor::Blue,
+                                17 => active_color = ActiveColor::White,
+                                _ => active_color = ActiveColor::Red,
                             }
-                            46 => {
-                                // c
-                                board = glium::texture::Texture2d::with_format(
-                                    &display,
-                                    empty_board(width, height),
-                                    UncompressedFloatFormat::F32F32F32F32,
-                                    MipmapsOption::NoMipmap,
-                                )
-                                .unwrap();
+                        }
+                        18 => {
+                            // e
+                            let path = viewer_args
+                                .export_dir
+                                .join(format!("{}_{:06}.{}", name, step, viewer_args.export.extension()));
+                            let saved = fs::create_dir_all(&viewer_args.export_dir)
+                                .and_then(|()| formats::save(&path, &backend.download(&board)));
+                            match saved {
+                                Ok(()) => println!("saved {}", path.display()),
+                                Err(e) => eprintln!("{}: {}", path.display(), e),
                             }
-                            19 | 34 | 48 | 17 => {
-                                // r, g, b, w
-                                match input.scancode {
-                                    34 => active_color = ActiveColor::Green,
-                                    48 => active_color = ActiveColor::Blue,
-                                    17 => active_color = ActiveColor::White,
-                                    _ => active_color = ActiveColor::Red,
-                                }
+                        }
+                        63 => {
+                            // F5
+                            let params = program.params().iter().map(|(name, value)| (name.to_string(), value));
+                            let state = State {
+                                name: name.clone(),
+                                program: source.clone(),
+                                board: backend.download(&board),
+                                step,
+                                speed,
+                                seed,
+                                params: params.collect(),
+                                stage,
+                            };
+                            let path = viewer_args.export_dir.join(format!("{}_{:06}.state", name, step));
+                            let saved = fs::create_dir_all(&viewer_args.export_dir).and_then(|()| state.save(&path));
+                            match saved {
+                                Ok(()) => println!("saved {}", path.display()),
+                                Err(e) => eprintln!("{}: {}", path.display(), e),
                             }
-                            33 => {
-                                // f
-                                println!("frametime: {}ms", last_frame_time.as_millis());
+                        }
+                        47 => {
+                            // v
+                            if recorder.is_some() {
+                                stop_recording(recorder.take());
+                            } else {
+                                let path = viewer_args.export_dir.join(format!("{}_{:06}.gif", name, step));
+                                recorder = fs::create_dir_all(&viewer_args.export_dir)
+                                    .map_err(|e| eprintln!("{}: {}", viewer_args.export_dir.display(), e))
+                                    .ok()
+                                    .and_then(|()| {
+                                        start_recording(record_options(path), &backend, &board, width, height)
+                                    });
                             }
-                            1 | 16 => {
-                                // esc | q
-                                *control_flow = glutin::event_loop::ControlFlow::Exit;
+                        }
+                        31 => {
+                            // s
+                            println!("step {}: {}", step, backend.stats(&board).summary());
+                        }
+                        33 => {
+                            // f
+                            println!("frametime: {}ms", last_frame_time.as_millis());
+                        }
+                        1 | 16 => {
+                            // esc | q
+                            *control_flow = glutin::event_loop::ControlFlow::Exit;
+                        }
+                        87 => {
+                            // F11
+                            let gl_window = display.gl_window();
+                            let window = gl_window.window();
+                            if window.fullscreen().is_none() {
+                                window.set_fullscreen(Some(Fullscreen::Borderless(None)));
+                            } else {
+                                window.set_fullscreen(None);
                             }
-                            87 => {
-                                // F11
-                                let gl_window = display.gl_window();
-                                let window = gl_window.window();
-                                if window.fullscreen().is_none() {
-                                    window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-                                } else {
-                                    window.set_fullscreen(None);
+                        }
+                        x @ 2..=12 => {
+                            // 1..9..-
+                            let mode = x - 2;
+                            speed = 1 << mode;
+                        }
+                        13 => speed = 0, // =
+                        15 | 26 | 27 | 104 | 109 if !program.params().is_empty() => {
+                            // Tab | [ | ] | PgUp | PgDn
+                            let params = program.params_mut();
+                            let value = match input.scancode {
+                                15 => {
+                                    selected_param = (selected_param + 1) % params.len();
+                                    params.values()[selected_param]
                                 }
+                                scancode => {
+                                    let steps = match scancode {
+                                        26 => -1,
+                                        27 => 1,
+                                        104 => 10,
+                                        _ => -10,
+                                    };
+                                    // Boards seen with the old value don't predict repeats.
+                                    cycles.reset();
+                                    params.nudge(selected_param, steps)
+                                }
+                            };
+                            println!("{} = {}", params.names()[selected_param], value);
+                            let position = (current, programs.len());
+                            let stage_position = (stage, stages.len());
+                            let title = window_title(&name, position, stage_position, program.params(), selected_param);
+                            display.gl_window().window().set_title(&title);
+                        }
+                        49 | 25 => {
+                            // n | p
+                            let by = if input.scancode == 49 { 1 } else { -1 };
+                            let carry = if modifiers.shift() { Carry::Reset } else { Carry::Resample };
+                            if let Some((index, loaded)) = switch(&programs, current, by, &backend) {
+                                pending = Some((index, loaded, carry));
                             }
-                            x if x >= 2 && x <= 12 => {
-                                // 1..9..-
-                                let mode = x - 2;
-                                speed = 1 << mode;
-                            }
-                            13 => speed = 0, // =
-                            x => println!("{}", x),
                         }
+                        x => println!("{}", x),
                     }
                 }
                 _ => (),
-            },
-            _ => (),
+            }
+        }
+    });
+}
+
+/// Name of the files saved from a program, its file name without the
+/// extension.
+fn program_name(path: &Path) -> String {
+    path.file_stem()
+        .map_or("board".into(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Reads and parses the documents of a program file, keeping its source.
+fn read_program(path: &Path) -> Result<(String, Vec<ProgramSpec>), String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let stages = parse_program(&source, path)?;
+    Ok((source, stages))
+}
+
+/// What happens to the board when another program takes over.
+#[derive(Clone, Copy, PartialEq)]
+enum Carry {
+    /// Kept centered, cut off or padded if the size changes, for reloads.
+    Crop,
+    /// Scaled to the new size.
+    Resample,
+    /// Replaced by the new program's initial board, as with `i`.
+    Reset,
+}
+
+/// The `--init` board of `spec`, printing the seed when it is random and
+/// replacing it for the next fill.
+fn initial<B: Backend>(init: &InitialBoard, spec: &ProgramSpec, seed: &mut u64, backend: &B) -> Option<B::Board> {
+    let random = init.uses_seed(spec);
+    if random {
+        println!("seed: {}", seed);
+    }
+    let board = init.board(spec, *seed).map_err(|e| eprintln!("{}", e)).ok()?;
+    if random {
+        *seed = random_seed();
+    }
+    Some(backend.upload(&board))
+}
+
+/// A program built from its file, with the source kept for save states.
+struct Loaded<B> {
+    source: String,
+    /// Documents of the file, the stages of a playlist.
+    stages: Vec<ProgramSpec>,
+    /// The document `program` is built from.
+    stage: usize,
+    program: Box<dyn Program<B>>,
+}
+
+/// Reads the program file at `path` and builds its document `stage`, or the
+/// first if it has fewer. Files that don't parse, validate or compile are
+/// errors, callers report them and keep running the program they have.
+fn load<B: Backend>(path: &Path, backend: &B, stage: usize) -> Result<Loaded<B::Board>, String> {
+    let (source, stages) = read_program(path)?;
+    let stage = if stage < stages.len() { stage } else { 0 };
+    let program = backend.program(&stages[stage]).map_err(|e| match stages.len() {
+        1 => e.to_string(),
+        _ => format!("stage {}: {}", stage + 1, e),
+    })?;
+    Ok(Loaded {
+        source,
+        stages,
+        stage,
+        program,
+    })
+}
+
+/// Reads and builds the program at `path` again, staying at `stage` of a
+/// playlist. Parameters keep the values they were tuned to unless the file
+/// changed them. On errors the running program stays, see [`load`].
+fn reload<B: Backend>(
+    path: &Path,
+    backend: &B,
+    stage: usize,
+    spec: &ProgramSpec,
+    program: &dyn Program<B::Board>,
+) -> Result<Loaded<B::Board>, String> {
+    let mut loaded = load(path, backend, stage)?;
+    let params = &loaded.stages[loaded.stage].params;
+    for (name, value) in program.params().iter() {
+        if params.get(name) == spec.params.get(name) {
+            loaded.program.set_param(name, value);
         }
+    }
+    Ok(loaded)
+}
+
+/// Builds the program after `current` in `programs` (before it for a `by` of
+/// -1), skipping and reporting those that fail to load or compile.
+fn switch<B: Backend>(
+    programs: &[PathBuf],
+    current: usize,
+    by: isize,
+    backend: &B,
+) -> Option<(usize, Loaded<B::Board>)> {
+    let len = programs.len() as isize;
+    (1..len)
+        .map(|i| (current as isize + by * i).rem_euclid(len) as usize)
+        .find_map(|index| match load(&programs[index], backend, 0) {
+            Ok(loaded) => Some((index, loaded)),
+            Err(e) => {
+                eprintln!("{}: {}", programs[index].display(), e);
+                None
+            }
+        })
+}
+
+/// `name (2/9) stage 1/3  a=1 [b=0.5] c=2` with the selected parameter in
+/// brackets, the position and stage only when there are several.
+fn window_title(
+    name: &str,
+    (index, count): (usize, usize),
+    (stage, stages): (usize, usize),
+    params: &Params,
+    selected: usize,
+) -> String {
+    let mut title = name.to_string();
+    if count > 1 {
+        title += &format!(" ({}/{})", index + 1, count);
+    }
+    if stages > 1 {
+        title += &format!(" stage {}/{}", stage + 1, stages);
+    }
+    for (i, (param, value)) in params.iter().enumerate() {
+        let (open, close) = if i == selected { ("[", "]") } else { ("", "") };
+        title += &format!("{}{}{}={}{}", if i == 0 { "  " } else { " " }, open, param, value, close);
+    }
+    title
+}
+
+/// Starts a recording with the current board as its first frame, reporting
+/// failures.
+fn start_recording<B: Backend>(
+    options: RecordOptions,
+    backend: &B,
+    board: &B::Board,
+    width: u32,
+    height: u32,
+) -> Option<Recorder> {
+    let path = options.path.clone();
+    let started = Recorder::new(options, width, height).and_then(|mut recorder| {
+        recorder.frame(&backend.download(board))?;
+        Ok(recorder)
     });
+    match started {
+        Ok(recorder) => {
+            println!("recording to {}", path.display());
+            Some(recorder)
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Counts a step for the recording, if there is one, and records the board
+/// when it's due. A recording that fails is stopped.
+fn record_step<B: Backend>(recorder: &mut Option<Recorder>, backend: &B, board: &B::Board) {
+    if let Some(active) = recorder {
+        if active.step() {
+            if let Err(e) = active.frame(&backend.download(board)) {
+                eprintln!("{}: {}", active.path().display(), e);
+                stop_recording(recorder.take());
+            }
+        }
+    }
+}
+
+/// Writes statistics every `every` steps while there is a writer. A writer
+/// that fails is dropped.
+fn write_stats<B: Backend>(
+    writer: &mut Option<(StatsWriter, PathBuf)>,
+    every: u32,
+    step: u64,
+    backend: &B,
+    board: &B::Board,
+) {
+    if let Some((active, path)) = writer {
+        if step.is_multiple_of(every as u64) {
+            if let Err(e) = active.write(step, &backend.stats(board)) {
+                eprintln!("{}: {}", path.display(), e);
+                *writer = None;
+            }
+        }
+    }
+}
+
+fn stop_recording(recorder: Option<Recorder>) {
+    if let Some(recorder) = recorder {
+        let path = recorder.path().to_owned();
+        match recorder.finish() {
+            Ok(frames) => println!("recorded {} frames to {}", frames, path.display()),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Reads, parses and validates a program file, exiting with an error message
+/// if that fails.
+fn read_spec(path: &str) -> ProgramSpec {
+    match load_spec(path) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads a program file, checking every stage of a playlist but returning
+/// only the first.
+fn load_spec(path: &str) -> Result<ProgramSpec, String> {
+    let source = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Ok(parse_program(&source, Path::new(path))?.swap_remove(0))
+}
+
+/// Parses and validates the documents of a program read from `path`, which
+/// image paths in `init:` are relative to (a state file's directory for a
+/// resumed program).
+fn parse_program(source: &str, path: &Path) -> Result<Vec<ProgramSpec>, String> {
+    let mut stages = ProgramSpec::playlist(source).map_err(|e| e.to_string())?;
+    for spec in &mut stages {
+        shader::validate(spec).map_err(|e| e.to_string())?;
+        init::resolve_paths(&mut spec.init, path.parent().unwrap_or(Path::new("")));
+    }
+    Ok(stages)
 }
 
 enum ActiveColor {
@@ -243,3 +992,51 @@ enum ActiveColor {
     Blue,
     White,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpu_convolution::backend::CpuBackend;
+
+    fn program(params: &str) -> String {
+        format!("screen: [4, 4]\ntype: val\nkernel: [[1]]\nparams: {}\nfun: return x;\n", params)
+    }
+
+    #[test]
+    fn reload_keeps_tuned_params() {
+        let path = env::temp_dir().join(format!("gpu_convolution_reload_{}.yaml", process::id()));
+        let backend = CpuBackend::default();
+        fs::write(&path, program("{a: 1, b: 2, c: 3}")).unwrap();
+        let mut running = load(&path, &backend, 0).unwrap();
+        for name in ["a", "b", "c"] {
+            assert!(running.program.set_param(name, 9.));
+        }
+        let spec = running.stages[running.stage].clone();
+        // `a` is as it was, `b` changed in the file and `c` is gone.
+        fs::write(&path, program("{a: 1, b: 5, d: 4}")).unwrap();
+        let reloaded = reload(&path, &backend, 0, &spec, running.program.as_ref()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let params: Vec<_> = reloaded.program.params().iter().map(|(n, v)| (n.to_string(), v)).collect();
+        assert_eq!(params, [("a".to_string(), 9.), ("b".to_string(), 5.), ("d".to_string(), 4.)]);
+    }
+
+    #[test]
+    fn switch_skips_broken_programs() {
+        let dir = env::temp_dir().join(format!("gpu_convolution_switch_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let programs: Vec<_> = ["a", "b", "c"].iter().map(|name| dir.join(format!("{}.yaml", name))).collect();
+        fs::write(&programs[0], program("{f: 1}")).unwrap();
+        fs::write(&programs[1], "screen: [4, 4]\ntype: val\n").unwrap();
+        fs::write(&programs[2], program("{f: 3}")).unwrap();
+        let backend = CpuBackend::default();
+        let index = |by| switch(&programs, 0, by, &backend).map(|(index, _)| index);
+        assert_eq!(index(1), Some(2));
+        assert_eq!(index(-1), Some(2));
+        // Switched to programs start from the values in their file.
+        let (_, loaded) = switch(&programs, 2, 1, &backend).unwrap();
+        assert_eq!(loaded.program.params().get("f"), Some(1.));
+        fs::write(&programs[0], "type: val\n").unwrap();
+        assert!(switch(&programs, 2, 1, &backend).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// Error produced while turning a program file into a runnable program.
+///
+/// Every variant carries the key path inside the document (e.g. `kernel[1][2]`)
+/// and, when known, the 1-based line in the source file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    /// The file is not valid YAML.
+    Syntax { message: String, line: usize },
+    /// A required key is absent.
+    Missing { path: String, line: Option<usize> },
+    /// A key is present but holds a value of the wrong type.
+    WrongType { path: String, expected: &'static str, line: Option<usize> },
+    /// A kernel is not a usable matrix.
+    BadKernel { path: String, reason: String, line: Option<usize> },
+    /// `type` is not one of `val`, `rgb`, `sym`.
+    UnknownType { found: String, line: Option<usize> },
+    /// `edge` is not `wrap`, `clamp` or a value of the right shape.
+    InvalidEdge { reason: String, line: Option<usize> },
+    /// `fun` does not compile; `fun_line` counts from the first line of `fun`.
+    Fun { message: String, fun_line: usize, line: Option<usize> },
+}
+
+impl ProgramError {
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            ProgramError::Syntax { line, .. } => Some(*line),
+            ProgramError::Missing { line, .. }
+            | ProgramError::WrongType { line, .. }
+            | ProgramError::BadKernel { line, .. }
+            | ProgramError::UnknownType { line, .. }
+            | ProgramError::InvalidEdge { line, .. }
+            | ProgramError::Fun { line, .. } => *line,
+        }
+    }
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line() {
+            write!(f, "line {}: ", line)?;
+        }
+        match self {
+            ProgramError::Syntax { message, .. } => write!(f, "invalid YAML: {}", message),
+            ProgramError::Missing { path, .. } => write!(f, "missing `{}`", path),
+            ProgramError::WrongType { path, expected, .. } => {
+                write!(f, "`{}` must be {}", path, expected)
+            }
+            ProgramError::BadKernel { path, reason, .. } => {
+                write!(f, "bad kernel `{}`: {}", path, reason)
+            }
+            ProgramError::UnknownType { found, .. } => write!(
+                f,
+                "unknown program type `{}` (expected `val`, `rgb` or `sym`)",
+                found
+            ),
+            ProgramError::InvalidEdge { reason, .. } => write!(f, "invalid `edge`: {}", reason),
+            ProgramError::Fun { message, fun_line, .. } => {
+                write!(f, "`fun` line {}: {}", fun_line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
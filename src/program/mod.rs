@@ -1,30 +1,129 @@
 pub mod val_program;
 pub mod rgb_program;
 pub mod symmetric_rgb_program;
+pub mod spec;
+pub mod error;
+pub mod shader;
+pub mod params;
 
-use glium::{Texture2d, Display};
+use glium::{backend::Facade, program::ProgramCreationError, uniforms::{UniformValue, Uniforms}, Texture2d};
 use yaml_rust::Yaml;
 
 use self::{rgb_program::RgbProgram, symmetric_rgb_program::SymmetricRgbProgram, val_program::ValProgram};
+pub use self::{error::ProgramError, params::{ParamRange, Params}, shader::shader_source, spec::{FunLines, ProgramKind, ProgramSpec, StageDuration}};
 
-pub trait Program {
-    fn from_yaml(doc: &Yaml, display: &Display) -> Self where Self: Sized;
-    fn step(&self, board: &mut Texture2d);
+/// A compiled program that advances a board of type `B` (by default the GL
+/// board texture) by one simulation step.
+pub trait Program<B = Texture2d> {
+    fn step(&self, board: &mut B);
     fn get_dimensions(&self) -> (u32, u32);
+
+    /// Current values of the program's `params`.
+    fn params(&self) -> &Params;
+    fn params_mut(&mut self) -> &mut Params;
+
+    fn param(&self, name: &str) -> Option<f32> {
+        self.params().get(name)
+    }
+
+    /// Sets a parameter for the following steps, false if the program has no
+    /// parameter called `name`.
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        self.params_mut().set(name, value)
+    }
+}
+
+/// Uniforms of a shader together with a program's `params`.
+pub(crate) struct WithParams<'p, U> {
+    pub uniforms: U,
+    pub params: &'p Params,
 }
 
-pub fn program_from_yaml(doc: &Yaml, display: &Display) -> Box<dyn Program> {
-    let typ = doc["type"].as_str().unwrap();
-    match typ {
-        "val" => Box::new(ValProgram::from_yaml(doc, display)),
-        "rgb" => Box::new(RgbProgram::from_yaml(doc, display)),
-        "sym" => Box::new(SymmetricRgbProgram::from_yaml(doc, display)),
-        _ => {
-            panic!("Invalid program type!")
+impl<U: Uniforms> Uniforms for WithParams<'_, U> {
+    fn visit_values<'a, F: FnMut(&str, UniformValue<'a>)>(&'a self, mut output: F) {
+        self.uniforms.visit_values(&mut output);
+        for (name, value) in self.params.iter() {
+            output(name, UniformValue::Float(value));
         }
     }
 }
 
+/// Builds the program a document describes, checking `fun` with
+/// [`shader::validate`] first like `GlBackend::program` does.
+pub fn program_from_yaml<F: Facade + ?Sized>(doc: &Yaml, facade: &F) -> Result<Box<dyn Program>, ProgramError> {
+    program_from_spec(ProgramSpec::from_yaml(doc)?, facade)
+}
+
+/// Validates `spec` and compiles its shaders, a driver error is reported as an
+/// error in `fun` at the line the log points to.
+pub fn program_from_spec<F: Facade + ?Sized>(spec: ProgramSpec, facade: &F) -> Result<Box<dyn Program>, ProgramError> {
+    shader::validate(&spec)?;
+    let ProgramSpec { width, height, ref fun, anchor, ref params, .. } = spec;
+    let program: Result<Box<dyn Program>, _> = match spec.kind.clone() {
+        ProgramKind::Val { kernel, edge } => {
+            ValProgram::new(width, height, fun, params.clone(), kernel, anchor, facade, edge).map(|p| Box::new(p) as _)
+        }
+        ProgramKind::Rgb { kernel, edge } => {
+            RgbProgram::new(width, height, fun, params.clone(), kernel, anchor, facade, edge).map(|p| Box::new(p) as _)
+        }
+        ProgramKind::Sym { kernel_hor, kernel_ver, edge } => {
+            SymmetricRgbProgram::new(width, height, fun, params.clone(), kernel_hor, kernel_ver, anchor, facade, edge)
+                .map(|p| Box::new(p) as _)
+        }
+    };
+    program.map_err(|e| match e {
+        ProgramError::Fun { message, fun_line, .. } => {
+            let offset = spec.fun.split_inclusive('\n').take(fun_line - 1).map(str::len).sum();
+            spec.fun_error(message, offset)
+        }
+        e => e,
+    })
+}
+
+/// Turns a failed shader compilation into an error in `fun`. Drivers prefix
+/// messages with the source line, e.g. `0:12(5): error: ...` (Mesa) or
+/// `0(12) : error C1008: ...` (NVIDIA); that line is counted from the start of
+/// `fun` inside `source`. `line` is left for the caller, which knows the file.
+pub(crate) fn compile_error(error: ProgramCreationError, source: &str, fun: &str) -> ProgramError {
+    let message = match error {
+        ProgramCreationError::CompilationError(log, _) | ProgramCreationError::LinkingError(log) => {
+            log.trim().to_string()
+        }
+        e => e.to_string(),
+    };
+    let fun_start = shader::fun_offset(source, fun)
+        .filter(|_| !fun.is_empty())
+        .map(|at| source[..at].matches('\n').count() + 1);
+    let fun_line = match (fun_start, log_line(&message)) {
+        (Some(start), Some(line)) => line.saturating_sub(start).min(fun.lines().count().saturating_sub(1)) + 1,
+        _ => 1,
+    };
+    ProgramError::Fun { message, fun_line, line: None }
+}
+
+/// First source line a driver log refers to, the number after the `0:` or
+/// `0(` source string index.
+fn log_line(log: &str) -> Option<usize> {
+    log.lines().find_map(|text| {
+        let bytes = text.as_bytes();
+        (1..bytes.len()).find_map(|i| {
+            let starts = bytes[i - 1] == b'0'
+                && (i == 1 || !bytes[i - 2].is_ascii_digit())
+                && (bytes[i] == b':' || bytes[i] == b'(');
+            let rest = &text[i + 1..];
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).map_or(rest, |end| &rest[..end]);
+            starts.then(|| digits.parse().ok()).flatten()
+        })
+    })
+}
+
+/// Error returned by the `from_yaml` constructors when the document describes a
+/// different program type than the one being constructed.
+pub(crate) fn kind_mismatch(expected: &'static str) -> ProgramError {
+    ProgramError::WrongType { path: "type".to_string(), expected, line: None }
+}
+
+#[derive(Debug, Clone)]
 pub enum EdgeSolution<T> {
     Clamp, Wrap, Value(T)
 }
@@ -62,12 +161,50 @@ impl EdgeSolution<(f32, f32, f32)> {
 }
 
 
-const CSAMPLE_CLAMP_SRC: &'static str = r#"
+const CSAMPLE_CLAMP_SRC: &str = r#"
 i = ivec2(clamp(i.x, 0, int(uWidth)-1), clamp(i.y, 0, int(uHeight)-1));
 return imageLoad(uTexture, i);
 "#;
-const CSAMPLE_WRAP_SRC: &'static str = r#"
+const CSAMPLE_WRAP_SRC: &str = r#"
 i = ivec2(mod(i.x, int(uWidth)), mod(i.y, int(uHeight)));
 return imageLoad(uTexture, i);
 "#;
 
+
+#[cfg(test)]
+mod tests {
+    use glium::program::ShaderType;
+
+    use super::*;
+
+    #[test]
+    fn compile_error_counts_lines_from_fun() {
+        let fun = "float a = 1.;\nreturn a +;";
+        let source = format!("#version 430\nuniform uint uWidth;\nfloat fun(float x, float prev) {{\n{}\n}}\n", fun);
+        let logs = [
+            "0:5(10): error: syntax error",
+            "0(5) : error C0000: syntax error",
+            "ERROR: 0:5: '+' : syntax error",
+        ];
+        for log in logs {
+            let creation_error = ProgramCreationError::CompilationError(log.to_string(), ShaderType::Compute);
+            let error = compile_error(creation_error, &source, fun);
+            assert_eq!(error, ProgramError::Fun { message: log.to_string(), fun_line: 2, line: None }, "{}", log);
+        }
+    }
+
+    #[test]
+    fn compile_error_outside_fun_is_clamped() {
+        let fun = "return x;";
+        let source = format!("#version 430\nfloat fun(float x, float prev) {{\n{}\n}}\nvoid main() {{ 1 }}\n", fun);
+        let log = "0:5(15): error: syntax error".to_string();
+        match compile_error(ProgramCreationError::CompilationError(log, ShaderType::Compute), &source, fun) {
+            ProgramError::Fun { fun_line, .. } => assert_eq!(fun_line, 1),
+            e => panic!("unexpected {:?}", e),
+        }
+        match compile_error(ProgramCreationError::CompilationNotSupported, &source, fun) {
+            ProgramError::Fun { fun_line, line, .. } => assert_eq!((fun_line, line), (1, None)),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Named `float` parameters of a program, from its `params:` section.
+//!
+//! Every parameter becomes a `uniform float` of the same name in the
+//! generated shader, so `fun` uses it like a constant while the value can
+//! change between steps without recompiling:
+//!
+//! ```yaml
+//! params: {f: 0.055, k: 0.062}
+//! fun: >
+//!   v.g = B + (D_b * v.g + A * B*B - (k + f) * B);
+//! ```
+//!
+//! A parameter can also give the range and step the viewer's keys move it
+//! by, `f: {value: 0.055, min: 0, max: 0.1, step: 0.001}`.
+
+/// Names `fun` can't use for a parameter: its arguments and the functions,
+/// uniforms and buffers of the generated shaders.
+const RESERVED: &[&str] = &[
+    "x", "v", "prev", "fun", "main", "csample", "kernel", "uWidth", "uHeight", "uKernelSize", "uAnchor", "uKernel",
+    "uKernelDir", "uTexture", "uTextureWrite",
+];
+
+/// GLSL keywords and types likely to be tried as names.
+const KEYWORDS: &[&str] = &[
+    "bool", "break", "buffer", "case", "const", "continue", "default", "discard", "do", "else", "false", "float",
+    "for", "if", "in", "inout", "int", "ivec2", "ivec3", "ivec4", "layout", "mat2", "mat3", "mat4", "out",
+    "return", "shared", "struct", "switch", "true", "uint", "uniform", "uvec2", "uvec3", "uvec4", "vec2", "vec3",
+    "vec4", "void", "while",
+];
+
+/// GLSL built-in functions, a uniform of the same name would hide them from
+/// `fun` and from the generated code (`clamp`, `mod`, `imageLoad`).
+const BUILTINS: &[&str] = &[
+    "abs", "acos", "acosh", "all", "any", "asin", "asinh", "atan", "atanh", "barrier", "bitCount", "ceil", "clamp",
+    "cos", "cosh", "cross", "degrees", "determinant", "distance", "dot", "equal", "exp", "exp2", "faceforward",
+    "findLSB", "findMSB", "floatBitsToInt", "floatBitsToUint", "floor", "fma", "fract", "greaterThan",
+    "greaterThanEqual", "imageLoad", "imageSize", "imageStore", "intBitsToFloat", "inverse", "inversesqrt", "isinf",
+    "isnan", "length", "lessThan", "lessThanEqual", "log", "log2", "matrixCompMult", "max", "memoryBarrier", "min",
+    "mix", "mod", "modf", "normalize", "not", "notEqual", "outerProduct", "pow", "radians", "reflect", "refract",
+    "round", "roundEven", "sign", "sin", "sinh", "smoothstep", "sqrt", "step", "tan", "tanh", "texelFetch",
+    "texture", "transpose", "trunc", "uintBitsToFloat",
+];
+
+/// Bounds of a parameter and how far one key press moves it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParamRange {
+    /// Infinite when unbounded.
+    pub min: f32,
+    pub max: f32,
+    /// Fine step, a coarse one is ten of these.
+    pub step: f32,
+}
+
+impl ParamRange {
+    /// Unbounded, stepping by a hundredth of `value` (0.01 for 0).
+    pub fn around(value: f32) -> Self {
+        Self {
+            min: f32::NEG_INFINITY,
+            max: f32::INFINITY,
+            step: if value == 0. { 0.01 } else { value.abs() / 100. },
+        }
+    }
+}
+
+/// Parameter values in file order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    names: Vec<String>,
+    values: Vec<f32>,
+    ranges: Vec<ParamRange>,
+}
+
+impl Params {
+    /// Whether `name` can be declared as a uniform next to the generated code.
+    pub fn valid_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with("gl_")
+            && !name.contains("__")
+            && !RESERVED.contains(&name)
+            && !KEYWORDS.contains(&name)
+            && !BUILTINS.contains(&name)
+    }
+
+    /// Adds a parameter, or replaces the value of an existing one.
+    pub fn insert(&mut self, name: &str, value: f32) {
+        self.insert_with_range(name, value, ParamRange::around(value));
+    }
+
+    pub fn insert_with_range(&mut self, name: &str, value: f32, range: ParamRange) {
+        match self.index(name) {
+            Some(i) => {
+                self.values[i] = value;
+                self.ranges[i] = range;
+            }
+            None => {
+                self.names.push(name.to_string());
+                self.values.push(value);
+                self.ranges.push(range);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.index(name).map(|i| self.values[i])
+    }
+
+    /// Sets an existing parameter, false if there is none called `name`.
+    pub fn set(&mut self, name: &str, value: f32) -> bool {
+        match self.index(name) {
+            Some(i) => {
+                self.values[i] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Values in the order of `names`.
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    /// Range of the parameter at `index` in `names`.
+    pub fn range(&self, index: usize) -> ParamRange {
+        self.ranges[index]
+    }
+
+    /// Moves the parameter at `index` in `names` by `steps` of its step, within
+    /// its range, and returns the new value. Values are rounded to a digit
+    /// below the step so that repeated steps don't drift.
+    pub fn nudge(&mut self, index: usize, steps: i32) -> f32 {
+        let range = self.ranges[index];
+        let value = self.values[index] as f64 + steps as f64 * range.step as f64;
+        let digits = (-(range.step as f64).log10()).ceil().max(0.) as i32 + 1;
+        let scale = 10f64.powi(digits);
+        let value = ((value * scale).round() / scale) as f32;
+        self.values[index] = value.clamp(range.min, range.max);
+        self.values[index]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.names.iter().map(String::as_str).zip(self.values.iter().copied())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// `uniform float NAME;` for every parameter, each on its own line with
+    /// the indentation of the generated shaders.
+    pub(crate) fn declarations(&self) -> String {
+        self.names.iter().map(|name| format!("    uniform float {};\n", name)).collect()
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_must_not_clash_with_glsl() {
+        for name in ["f", "k", "decay", "D_b", "_rate", "uWidth2", "mixing"] {
+            assert!(Params::valid_name(name), "{}", name);
+        }
+        let clashing = [
+            "", "2f", "a-b", "a b", "gl_Foo", "a__b", "x", "prev", "kernel", "uWidth", "float", "vec3", "clamp", "mod",
+            "min", "max", "mix", "imageLoad", "imageStore", "sin", "smoothstep",
+        ];
+        for name in clashing {
+            assert!(!Params::valid_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn nudge_rounds_to_the_step() {
+        let mut params = Params::default();
+        params.insert_with_range("f", 0.055, ParamRange { min: 0., max: 0.1, step: 0.001 });
+        for _ in 0..10 {
+            params.nudge(0, 1);
+        }
+        // Every step is rounded, so ten of them land on 0.065 exactly.
+        assert_eq!(params.get("f"), Some(0.065));
+        assert_eq!(params.nudge(0, -10), 0.055);
+        params.insert("k", 0.);
+        assert_eq!(params.nudge(1, 3), 0.03);
+        params.insert_with_range("d", 1., ParamRange { min: 0., max: 10., step: 0.3 });
+        assert_eq!(params.nudge(2, 1), 1.3);
+    }
+
+    #[test]
+    fn nudge_stays_in_range() {
+        let mut params = Params::default();
+        params.insert_with_range("f", 0.055, ParamRange { min: 0., max: 0.1, step: 0.001 });
+        assert_eq!(params.nudge(0, -100), 0.);
+        assert_eq!(params.nudge(0, 1000), 0.1);
+        assert_eq!(params.values(), [0.1]);
+        // Unbounded parameters go anywhere.
+        params.insert("k", -1.);
+        assert_eq!(params.nudge(1, -1000), -11.);
+    }
+}
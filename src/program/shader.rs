@@ -0,0 +1,527 @@
+//! GLSL sources of the compute shaders, generated from a program's `fun`,
+//! edge handling and kernel size.
+
+use std::error::Error;
+
+use naga::{
+    front::{
+        glsl::{Frontend, Options},
+        Typifier,
+    },
+    proc::ResolveContext,
+    valid::{Capabilities, ExpressionError, FunctionError, ValidationError, ValidationFlags, Validator},
+    BinaryOperator, Block, Expression, Handle, Module, Scalar, ScalarKind, ShaderStage, Span, Statement, TypeInner,
+};
+
+use super::{Params, ProgramError, ProgramKind, ProgramSpec};
+
+/// Local work group size of every shader, programs dispatch
+/// `ceil(width / x) * ceil(height / y)` groups.
+pub const WORK_GROUP_SIZE: (u32, u32) = (16, 16);
+
+/// The convolution shader a `ProgramSpec` compiles to. `val` programs also
+/// run [`swap_shader_src`] after it.
+pub fn shader_source(spec: &ProgramSpec) -> String {
+    match &spec.kind {
+        ProgramKind::Val { kernel, edge } => {
+            val_shader_src(&spec.fun, &spec.params, &edge.clone().csample_src(), kernel.len() * kernel.len())
+        }
+        ProgramKind::Rgb { kernel, edge } => {
+            rgb_shader_src(&spec.fun, &spec.params, &edge.clone().csample_src(), kernel.len() * kernel.len())
+        }
+        ProgramKind::Sym { kernel_hor, edge, .. } => {
+            sym_shader_src(&spec.fun, &spec.params, &edge.clone().csample_src(), kernel_hor.len())
+        }
+    }
+}
+
+/// Checks a program without a GL context, so mistakes are reported with lines
+/// of the program file instead of a driver log for the generated shader.
+///
+/// The whole convolution shader from [`shader_source`] is compiled with naga's
+/// GLSL front-end and validator, after [`naga_source`] adapted it to what naga
+/// takes. Like drivers, an `int` returned from a `float` function is converted
+/// rather than rejected.
+pub fn validate(spec: &ProgramSpec) -> Result<(), ProgramError> {
+    let source = naga_source(&shader_source(spec));
+    let fun_start = fun_offset(&source, &spec.fun).unwrap_or(0);
+    // Up to the closing brace of `fun`, which is where a missing `;` shows.
+    let fun_end = source[fun_start..].find('}').map_or(source.len(), |at| fun_start + at);
+
+    // Spans outside of `fun` (e.g. a missing closing brace or a parameter
+    // shadowing a name `main` uses) go to its start or end.
+    let error = |message: String, span: Span| {
+        let range = span.to_range().unwrap_or(fun_start..fun_start);
+        let message = if range.start < fun_start || range.start > fun_end {
+            format!("{} (in the generated shader)", message)
+        } else {
+            message
+        };
+        let offset = range.start.saturating_sub(fun_start).min(spec.fun.trim_end().len());
+        Err(spec.fun_error(message, offset))
+    };
+
+    let mut module = match Frontend::default().parse(&Options::from(ShaderStage::Compute), &source) {
+        Ok(module) => module,
+        Err(e) => {
+            return match e.errors.first() {
+                Some(first) => error(first.kind.to_string(), first.meta),
+                None => error(e.to_string(), Span::default()),
+            }
+        }
+    };
+    loop {
+        let e = match Validator::new(ValidationFlags::all(), Capabilities::all()).validate(&module) {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        if convert_return(&mut module, e.as_inner()) {
+            continue;
+        }
+        let span = e.spans().last().map_or(Span::default(), |(span, _)| *span);
+        return error(describe(&module, e.as_inner()), span);
+    }
+}
+
+/// The generated GLSL as naga takes it: `#version 450`, plain uniforms
+/// gathered into a block and explicit bindings for the kernel and images.
+/// Drivers get the source unchanged.
+pub(crate) fn naga_source(source: &str) -> String {
+    let mut uniforms = Vec::new();
+    let mut lines = Vec::new();
+    let mut images = 0;
+    for line in source.lines() {
+        let text = line.trim();
+        if text.starts_with("#version") {
+            lines.push("#version 450".to_string());
+        } else if let Some(image) = text.strip_prefix("uniform layout(").and_then(|rest| rest.split_once(')')) {
+            // `uniform layout(binding=3, rgba32f) image2D uTexture;`, images share binding 3.
+            let format = image.0.rsplit(',').next().unwrap_or("rgba32f").trim();
+            lines.push(format!("layout(set = 1, binding = {}, {}) uniform{}", images, format, image.1));
+            images += 1;
+        } else if let Some(block) = text.strip_prefix("uniform uKernel") {
+            lines.push(format!("layout(set = 0, binding = 1) uniform uKernel{}", block));
+        } else if let Some(uniform) = text.strip_prefix("uniform ") {
+            if uniforms.is_empty() {
+                lines.push(String::new());
+            }
+            uniforms.push(uniform.to_string());
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if let Some(at) = lines.iter().position(String::is_empty) {
+        lines[at] = format!("layout(set = 0, binding = 0) uniform Uniforms {{ {} }};", uniforms.join(" "));
+    }
+    lines.join("\n")
+}
+
+/// Byte offset of `fun` in a generated shader `source`.
+pub(crate) fn fun_offset(source: &str, fun: &str) -> Option<usize> {
+    let signature = source.find(" fun(")?;
+    source[signature..].find(fun).map(|at| signature + at)
+}
+
+/// Applies GLSL's implicit conversion when `error` is an `int` (or `ivec`)
+/// returned from a function returning `float` (or `vec`), which naga rejects.
+/// Returns false for any other error.
+fn convert_return(module: &mut Module, error: &ValidationError) -> bool {
+    let (function, value) = match error {
+        ValidationError::Function {
+            handle,
+            source: FunctionError::InvalidReturnType { expression: Some(value), .. },
+            ..
+        } => (*handle, *value),
+        _ => return false,
+    };
+    let Some(result) = module.functions[function].result.as_ref().map(|result| result.ty) else {
+        return false;
+    };
+    let f = &module.functions[function];
+    let mut typifier = Typifier::new();
+    let context = ResolveContext::with_locals(module, &f.local_variables, &f.arguments);
+    if typifier.grow(value, &f.expressions, &context).is_err() {
+        return false;
+    }
+    let (found, expected) = (typifier.get(value, &module.types), &module.types[result].inner);
+    let convertible = match (found, expected) {
+        (TypeInner::Scalar(found), TypeInner::Scalar(expected)) => is_int(found) && expected.kind == ScalarKind::Float,
+        (TypeInner::Vector { size, scalar: found }, TypeInner::Vector { size: expected_size, scalar: expected }) => {
+            size == expected_size && is_int(found) && expected.kind == ScalarKind::Float
+        }
+        _ => false,
+    };
+    if !convertible {
+        return false;
+    }
+
+    let f = &mut module.functions[function];
+    let span = f.expressions.get_span(value);
+    let converted = Expression::As { expr: value, kind: ScalarKind::Float, convert: Some(4) };
+    let converted = f.expressions.append(converted, span);
+    replace_return(&mut f.body, value, converted)
+}
+
+fn is_int(scalar: &Scalar) -> bool {
+    matches!(scalar.kind, ScalarKind::Sint | ScalarKind::Uint)
+}
+
+/// Makes the `return` of `value` in `block` return `converted`, emitting it
+/// just before.
+fn replace_return(block: &mut Block, value: Handle<Expression>, converted: Handle<Expression>) -> bool {
+    let returns_value = |statement: &Statement| {
+        matches!(statement, Statement::Return { value: Some(v) } if *v == value)
+    };
+    if let Some(at) = block.iter().position(returns_value) {
+        let span = block.span_iter().nth(at).map_or(Span::default(), |(_, span)| *span);
+        let mut replacement = Block::new();
+        replacement.push(Statement::Emit(naga::Range::new_from_bounds(converted, converted)), span);
+        replacement.push(Statement::Return { value: Some(converted) }, span);
+        block.splice(at..at + 1, replacement);
+        return true;
+    }
+    block.iter_mut().any(|statement| match statement {
+        Statement::Block(inner) => replace_return(inner, value, converted),
+        Statement::If { accept, reject, .. } => {
+            replace_return(accept, value, converted) || replace_return(reject, value, converted)
+        }
+        Statement::Loop { body, continuing, .. } => {
+            replace_return(body, value, converted) || replace_return(continuing, value, converted)
+        }
+        Statement::Switch { cases, .. } => {
+            cases.iter_mut().any(|case| replace_return(&mut case.body, value, converted))
+        }
+        _ => false,
+    })
+}
+
+/// A validation error in words, naga's messages name expressions and types by
+/// their handles, e.g. `The `return` expression Some([26]) does not match the
+/// declared return type Some([2])`.
+fn describe(module: &Module, error: &ValidationError) -> String {
+    if let ValidationError::Function { handle, name, source, .. } = error {
+        let function = &module.functions[*handle];
+        let type_of = |value: Handle<Expression>| {
+            let mut typifier = Typifier::new();
+            let context = ResolveContext::with_locals(module, &function.local_variables, &function.arguments);
+            typifier.grow(value, &function.expressions, &context).ok()?;
+            Some(glsl_type(module, typifier.get(value, &module.types)))
+        };
+        match source {
+            FunctionError::InvalidReturnType { expression, expected_ty } => {
+                let expected = expected_ty.map_or("void".to_string(), |ty| glsl_type(module, &module.types[ty].inner));
+                return match expression.and_then(type_of) {
+                    Some(found) => format!("`{}` must return {}, not {}", name, expected, found),
+                    None => format!("`{}` must return {}", name, expected),
+                };
+            }
+            FunctionError::InvalidStoreTypes { pointer, value } => {
+                if let (Some(variable), Some(value)) = (type_of(*pointer), type_of(*value)) {
+                    return format!("can't assign {} to a {} variable", value, variable);
+                }
+            }
+            FunctionError::Expression {
+                source: ExpressionError::InvalidBinaryOperandTypes { op, lhs_type, rhs_type, .. },
+                ..
+            } => {
+                let (lhs, rhs) = (glsl_type(module, lhs_type), glsl_type(module, rhs_type));
+                return format!("`{}` can't take {} and {}", binary_operator(*op), lhs, rhs);
+            }
+            FunctionError::Expression { handle: value, source } => {
+                let found = type_of(*value).map_or(String::new(), |ty| format!(" of type {}", ty));
+                return format!("invalid expression{}: {}", found, without_handles(&source.to_string()));
+            }
+            _ => (),
+        }
+    }
+    // The innermost error is the most specific one.
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(inner) = source {
+        message = inner.to_string();
+        source = inner.source();
+    }
+    without_handles(&message)
+}
+
+/// Drops arena handles like `[12]` and `Some([12])` from a naga message.
+fn without_handles(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(open) = rest.find('[') {
+        let digits = rest[open + 1..].find(']').filter(|&len| {
+            len > 0 && rest[open + 1..open + 1 + len].bytes().all(|b| b.is_ascii_digit())
+        });
+        let Some(len) = digits else {
+            out.push_str(&rest[..=open]);
+            rest = &rest[open + 1..];
+            continue;
+        };
+        let (mut before, mut after) = (&rest[..open], &rest[open + len + 2..]);
+        if let (Some(b), Some(a)) = (before.strip_suffix("Some("), after.strip_prefix(')')) {
+            (before, after) = (b, a);
+        }
+        out.push_str(before.trim_end());
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+fn binary_operator(op: BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Add => "+",
+        BinaryOperator::Subtract => "-",
+        BinaryOperator::Multiply => "*",
+        BinaryOperator::Divide => "/",
+        BinaryOperator::Modulo => "%",
+        BinaryOperator::Equal => "==",
+        BinaryOperator::NotEqual => "!=",
+        BinaryOperator::Less => "<",
+        BinaryOperator::LessEqual => "<=",
+        BinaryOperator::Greater => ">",
+        BinaryOperator::GreaterEqual => ">=",
+        BinaryOperator::And => "&",
+        BinaryOperator::ExclusiveOr => "^",
+        BinaryOperator::InclusiveOr => "|",
+        BinaryOperator::LogicalAnd => "&&",
+        BinaryOperator::LogicalOr => "||",
+        BinaryOperator::ShiftLeft => "<<",
+        BinaryOperator::ShiftRight => ">>",
+    }
+}
+
+/// GLSL spelling of a naga type, e.g. `ivec3`.
+fn glsl_type(module: &Module, ty: &TypeInner) -> String {
+    let prefix = |scalar: &Scalar| match scalar.kind {
+        ScalarKind::Sint => "i",
+        ScalarKind::Uint => "u",
+        ScalarKind::Bool => "b",
+        _ => "",
+    };
+    match ty {
+        TypeInner::Scalar(scalar) => match scalar.kind {
+            ScalarKind::Sint => "int".to_string(),
+            ScalarKind::Uint => "uint".to_string(),
+            ScalarKind::Bool => "bool".to_string(),
+            _ => "float".to_string(),
+        },
+        TypeInner::Vector { size, scalar } => format!("{}vec{}", prefix(scalar), *size as u8),
+        TypeInner::Matrix { columns, rows, .. } if *columns as u8 == *rows as u8 => format!("mat{}", *rows as u8),
+        TypeInner::Matrix { columns, rows, .. } => format!("mat{}x{}", *columns as u8, *rows as u8),
+        TypeInner::Pointer { base, .. } => glsl_type(module, &module.types[*base].inner),
+        TypeInner::ValuePointer { size: None, scalar, .. } => glsl_type(module, &TypeInner::Scalar(*scalar)),
+        TypeInner::ValuePointer { size: Some(size), scalar, .. } => {
+            glsl_type(module, &TypeInner::Vector { size: *size, scalar: *scalar })
+        }
+        TypeInner::Array { base, .. } => format!("{}[]", glsl_type(module, &module.types[*base].inner)),
+        TypeInner::Struct { .. } => "a struct".to_string(),
+        _ => "an opaque type".to_string(),
+    }
+}
+
+/// Swaps the red and green channels, moving the value a `val` step wrote to
+/// green into red.
+pub fn swap_shader_src() -> String {
+    format!(r#"
+#version 430
+
+layout(local_size_x = {}, local_size_y = {}, local_size_z = 1) in;
+
+uniform uint uWidth;
+uniform uint uHeight;
+uniform layout(binding=3, rgba32f) image2D uTexture;
+
+
+void main() {{
+    ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
+    if (i.x >= int(uWidth) || i.y >= int(uHeight))
+        return;
+
+    vec4 pixel_sample = imageLoad(uTexture, i);
+    imageStore(uTexture, i, vec4(pixel_sample.g, pixel_sample.r, pixel_sample.b, pixel_sample.a) );
+}}
+"#, WORK_GROUP_SIZE.0, WORK_GROUP_SIZE.1)
+}
+
+/// Reduces a board to statistics: every work group writes four `vec4`s to
+/// `uPartials`, see `BoardStats::from_partials`.
+pub fn stats_shader_src() -> String {
+    format!(r#"
+#version 430
+
+layout(local_size_x = {0}, local_size_y = {1}, local_size_z = 1) in;
+
+uniform uint uWidth;
+uniform uint uHeight;
+uniform layout(binding=3, rgba32f) readonly image2D uTexture;
+buffer uPartials {{
+    vec4 partials[];
+}};
+
+const uint N = {0} * {1};
+shared vec4 sSum[N];
+shared vec4 sSquares[N];
+shared vec4 sMin[N];
+shared vec4 sMax[N];
+
+void main() {{
+    uint l = gl_LocalInvocationIndex;
+    ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
+    if (i.x < int(uWidth) && i.y < int(uHeight)) {{
+        vec4 p = imageLoad(uTexture, i);
+        sSum[l] = vec4(p.rgb, p.r >= 0.5 ? 1. : 0.);
+        sSquares[l] = vec4(p.rgb * p.rgb, 1.);
+        sMin[l] = p;
+        sMax[l] = p;
+    }} else {{
+        float inf = uintBitsToFloat(0x7f800000u);
+        sSum[l] = vec4(0.);
+        sSquares[l] = vec4(0.);
+        sMin[l] = vec4(inf);
+        sMax[l] = vec4(-inf);
+    }}
+    barrier();
+
+    for (uint s = N / 2u; s > 0u; s >>= 1u) {{
+        if (l < s) {{
+            sSum[l] += sSum[l + s];
+            sSquares[l] += sSquares[l + s];
+            sMin[l] = min(sMin[l], sMin[l + s]);
+            sMax[l] = max(sMax[l], sMax[l + s]);
+        }}
+        barrier();
+    }}
+
+    if (l == 0u) {{
+        uint g = 4u * (gl_WorkGroupID.y * gl_NumWorkGroups.x + gl_WorkGroupID.x);
+        partials[g] = sSum[0];
+        partials[g + 1u] = sSquares[0];
+        partials[g + 2u] = sMin[0];
+        partials[g + 3u] = sMax[0];
+    }}
+}}
+"#, WORK_GROUP_SIZE.0, WORK_GROUP_SIZE.1)
+}
+
+pub(crate) fn val_shader_src(fun_src: &str, params: &Params, csample_src: &str, kernel_size_sq: usize) -> String {
+    format!(
+        "#version 430
+
+    layout(local_size_x = {}, local_size_y = {}, local_size_z = 1) in;
+    
+    uniform uint uWidth;
+    uniform uint uHeight;
+    uniform int uKernelSize;
+    uniform ivec2 uAnchor;
+    uniform uKernel{{
+        float kernel[{kernel_size_sq}];
+    }};
+    uniform layout(binding=3, rgba32f) image2D uTexture;
+{params}
+    vec4 csample(ivec2 i) {{
+        {csample_src}
+    }}
+    float fun(float x, float prev) {{
+        {fun_src}
+    }}
+
+    void main() {{
+        ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
+        if (i.x >= int(uWidth) || i.y >= int(uHeight))
+            return;
+
+        float sum = 0;
+        for (int k = 0; k < uKernelSize*uKernelSize; ++k)
+            sum += csample(i + ivec2(mod(k, uKernelSize), k / uKernelSize) - uAnchor).r * kernel[k];
+
+        vec4 pixel_sample = imageLoad(uTexture, i);
+        imageStore(uTexture, i, vec4(pixel_sample.r, fun(sum, pixel_sample.r), pixel_sample.b, pixel_sample.a) );
+    }}", WORK_GROUP_SIZE.0, WORK_GROUP_SIZE.1, params = params.declarations())
+}
+
+pub(crate) fn rgb_shader_src(fun_src: &str, params: &Params, csample_src: &str, kernel_size_sq: usize) -> String {
+    format!(
+        "#version 430
+
+    layout(local_size_x = {}, local_size_y = {}, local_size_z = 1) in;
+    
+    uniform uint uWidth;
+    uniform uint uHeight;
+    uniform int uKernelSize;
+    uniform ivec2 uAnchor;
+    uniform uKernel{{
+        float kernel[{kernel_size_sq}];
+    }};
+    uniform layout(binding=3, rgba32f) image2D uTextureWrite;
+    uniform layout(binding=3, rgba32f) image2D uTexture;
+{params}
+    vec4 csample(ivec2 i) {{
+        {csample_src}
+    }}
+    vec3 fun(vec3 v, vec3 prev) {{
+        {fun_src}
+    }}
+
+    void main() {{
+        ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
+        if (i.x >= int(uWidth) || i.y >= int(uHeight))
+            return;
+
+        vec3 sum = vec3(0.);
+        for (int k = 0; k < uKernelSize*uKernelSize; ++k)
+            sum += csample(i + ivec2(mod(k, uKernelSize), k / uKernelSize) - uAnchor).rgb * vec3(kernel[k]);
+
+        vec4 pixel_sample = imageLoad(uTexture, i);
+        imageStore(uTextureWrite, i, vec4(fun(sum, pixel_sample.rgb), pixel_sample.a) );
+    }}", WORK_GROUP_SIZE.0, WORK_GROUP_SIZE.1, params = params.declarations())
+}
+
+pub(crate) fn sym_shader_src(fun_src: &str, params: &Params, csample_src: &str, kernel_size: usize) -> String {
+    format!(
+        "#version 430
+
+    layout(local_size_x = {}, local_size_y = {}, local_size_z = 1) in;
+    
+    uniform uint uWidth;
+    uniform uint uHeight;
+    uniform int uKernelSize;
+    uniform ivec2 uAnchor;
+    uniform uKernel{{
+        float kernel[{kernel_size}];
+    }};
+    uniform int uKernelDir;
+    uniform layout(binding=3, rgba32f) image2D uTextureWrite;
+    uniform layout(binding=3, rgba32f) image2D uTexture;
+{params}
+    vec4 csample(ivec2 i) {{
+        {csample_src}
+    }}
+    vec3 fun(vec3 v, vec3 prev) {{
+        {fun_src}
+    }}
+
+    void main() {{
+        ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
+        if (i.x >= int(uWidth) || i.y >= int(uHeight))
+            return;
+
+        vec3 sum = vec3(0);
+
+        int offset;
+        ivec2 p = ivec2(0);
+        if (uKernelDir == 0 ) {{
+            p.x = 1;
+            offset = uAnchor.x;
+        }} else {{
+            p.y = 1;
+            offset = uAnchor.y;
+        }}
+        for (int k = 0; k < uKernelSize; ++k)
+            sum += csample(i + (k - offset)*p).rgb * vec3(kernel[k]);
+        
+
+        vec4 pixel_sample = imageLoad(uTexture, i);
+        imageStore(uTextureWrite, i, vec4(fun(sum, pixel_sample.rgb), pixel_sample.a) );
+    }}", WORK_GROUP_SIZE.0, WORK_GROUP_SIZE.1, params = params.declarations())
+}
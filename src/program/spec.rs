@@ -0,0 +1,937 @@
+use std::{collections::HashMap, ops::Range, str::FromStr};
+
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+    Yaml, YamlLoader,
+};
+
+use super::{
+    error::ProgramError,
+    params::{ParamRange, Params},
+    EdgeSolution,
+};
+use crate::{
+    init::{self, Layer},
+    stop::{self, StopConditions},
+};
+
+/// A program file parsed into typed values, independent of any GL context.
+#[derive(Debug, Clone)]
+pub struct ProgramSpec {
+    pub width: u32,
+    pub height: u32,
+    pub fun: String,
+    pub kind: ProgramKind,
+    /// Kernel cell (column, row) that is centered on the updated cell.
+    pub anchor: (u32, u32),
+    /// Where `fun` is in the program file, empty when parsed without source.
+    pub fun_lines: FunLines,
+    /// Seed for random fills, `--seed` on the command line takes precedence.
+    pub seed: Option<u64>,
+    /// Layers of the `init:` section, the starting board when not empty.
+    pub init: Vec<Layer>,
+    /// The `stop:` section, conditions that end a headless run early.
+    pub stop: StopConditions,
+    /// The `params:` section, uniforms `fun` can use.
+    pub params: Params,
+    /// How long the program runs as a stage of a playlist, until the viewer
+    /// moves on to the next document of the file.
+    pub duration: Option<StageDuration>,
+}
+
+/// Length of a playlist stage, `duration: {steps: 50}` (or just `50`) or
+/// `duration: {seconds: 30}`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StageDuration {
+    Steps(u64),
+    Seconds(f32),
+}
+
+/// Maps byte offsets in `fun` to lines of the program file.
+///
+/// `fun` is usually a folded (`>`) block scalar, which joins its source lines
+/// with spaces, so lines of `fun` can't simply be added to the line of the key.
+/// Instead the position of every source line inside `fun` is recorded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunLines {
+    key: Option<usize>,
+    /// (offset in `fun`, file line), ascending.
+    starts: Vec<(usize, usize)>,
+}
+
+impl FunLines {
+    fn locate(src: &str, fun: &str, key: Option<usize>) -> Self {
+        let mut starts = Vec::new();
+        if let Some(key) = key {
+            let mut cursor = 0;
+            for (i, text) in src.lines().enumerate().skip(key - 1) {
+                let mut text = text.trim();
+                if i == key - 1 {
+                    // Part after `fun:`, unless it's the indicator of a block scalar.
+                    text = text.split_once(':').map_or("", |(_, value)| value.trim());
+                    if text.starts_with(['|', '>']) {
+                        continue;
+                    }
+                }
+                if text.is_empty() {
+                    continue;
+                }
+                match fun[cursor..].find(text) {
+                    Some(at) => {
+                        starts.push((cursor + at, i + 1));
+                        cursor += at + text.len();
+                    }
+                    None if i == key - 1 => (),
+                    None => break,
+                }
+            }
+        }
+        Self { key, starts }
+    }
+
+    /// File line containing byte `offset` of `fun`.
+    pub fn line_at(&self, offset: usize) -> Option<usize> {
+        self.starts
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= offset)
+            .map(|(_, line)| *line)
+            .or(self.key)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ProgramKind {
+    Val {
+        kernel: Vec<Vec<f32>>,
+        edge: EdgeSolution<f32>,
+    },
+    Rgb {
+        kernel: Vec<Vec<f32>>,
+        edge: EdgeSolution<(f32, f32, f32)>,
+    },
+    Sym {
+        kernel_hor: Vec<f32>,
+        kernel_ver: Vec<f32>,
+        edge: EdgeSolution<(f32, f32, f32)>,
+    },
+}
+
+impl ProgramKind {
+    /// Side length of the kernel, the same for both passes of `sym`.
+    pub fn kernel_size(&self) -> usize {
+        match self {
+            ProgramKind::Val { kernel, .. } | ProgramKind::Rgb { kernel, .. } => kernel.len(),
+            ProgramKind::Sym { kernel_hor, .. } => kernel_hor.len(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProgramKind::Val { .. } => "val",
+            ProgramKind::Rgb { .. } => "rgb",
+            ProgramKind::Sym { .. } => "sym",
+        }
+    }
+}
+
+impl ProgramSpec {
+    /// Builds a spec from an already loaded document. Errors carry no line
+    /// numbers, use [`ProgramSpec::from_str`] when the source text is available.
+    pub fn from_yaml(doc: &Yaml) -> Result<Self, ProgramError> {
+        Self::from_node(Node::root(doc, &Lines::default()))
+    }
+
+    fn from_node(doc: Node) -> Result<Self, ProgramError> {
+        let screen = doc.get("screen");
+        let width = screen.index(0).u32()?;
+        let height = screen.index(1).u32()?;
+        let fun = doc.get("fun").str()?.to_string();
+
+        let typ = doc.get("type");
+        let kind = match typ.str()? {
+            "val" => ProgramKind::Val {
+                kernel: square_matrix(doc.get("kernel"))?,
+                edge: edge_f32(doc.get("edge"))?,
+            },
+            "rgb" => ProgramKind::Rgb {
+                kernel: square_matrix(doc.get("kernel"))?,
+                edge: edge_3f32(doc.get("edge"))?,
+            },
+            "sym" => {
+                let kernel_hor = vector(doc.get("kernelHor"))?;
+                let ver = doc.get("kernelVer");
+                let kernel_ver = vector(ver.clone())?;
+                if kernel_hor.len() != kernel_ver.len() {
+                    return Err(ver.bad_kernel(&format!(
+                        "has {} entries but `kernelHor` has {}",
+                        kernel_ver.len(),
+                        kernel_hor.len()
+                    )));
+                }
+                ProgramKind::Sym {
+                    kernel_hor,
+                    kernel_ver,
+                    edge: edge_3f32(doc.get("edge"))?,
+                }
+            }
+            other => {
+                return Err(ProgramError::UnknownType {
+                    found: other.to_string(),
+                    line: typ.line(),
+                })
+            }
+        };
+
+        let kernel_key = match kind {
+            ProgramKind::Sym { .. } => "kernelHor",
+            _ => "kernel",
+        };
+        let anchor = anchor(doc.get("anchor"), doc.get(kernel_key), kind.kernel_size())?;
+        let seed = doc.get("seed");
+        let seed = if seed.yaml.is_badvalue() { None } else { Some(seed.u64()?) };
+        let init = init::parse(doc.get("init"))?;
+        let stop = stop::parse(doc.get("stop"))?;
+        let params = params(doc.get("params"))?;
+        let duration = duration(doc.get("duration"))?;
+
+        Ok(Self {
+            width,
+            height,
+            fun,
+            kind,
+            anchor,
+            fun_lines: FunLines::default(),
+            seed,
+            init,
+            stop,
+            params,
+            duration,
+        })
+    }
+
+    /// Parses every document of a program file, the stages of a playlist.
+    /// Documents left empty, e.g. by a trailing `---`, are skipped.
+    pub fn playlist(src: &str) -> Result<Vec<Self>, ProgramError> {
+        let docs = load(src)?;
+        let lines = Lines::collect(src);
+        let stages: Vec<Self> = docs
+            .iter()
+            .zip(&lines)
+            .filter(|(doc, _)| !doc.is_null())
+            .map(|(doc, lines)| Self::from_document(src, doc, lines))
+            .collect::<Result<_, _>>()?;
+        if stages.is_empty() {
+            return Err(ProgramError::Missing {
+                path: "type".to_string(),
+                line: None,
+            });
+        }
+        Ok(stages)
+    }
+
+    fn from_document(src: &str, doc: &Yaml, lines: &Lines) -> Result<Self, ProgramError> {
+        let mut spec = Self::from_node(Node::root(doc, lines))?;
+        spec.fun_lines = FunLines::locate(src, &spec.fun, lines.0.get("fun").copied());
+        Ok(spec)
+    }
+
+    /// Replaces the literal of the first `float NAME = literal;` in `fun`,
+    /// returning false when there is none.
+    pub fn set_constant(&mut self, name: &str, value: f32) -> bool {
+        match constant_literal(&self.fun, name) {
+            Some(range) => {
+                // `{:?}` always writes a float literal, e.g. `1.0` rather than `1`.
+                self.fun.replace_range(range, &format!("{:?}", value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Error in `fun` at byte `offset`, with the line inside `fun` and, when
+    /// known, in the program file.
+    pub fn fun_error(&self, message: String, offset: usize) -> ProgramError {
+        let offset = offset.min(self.fun.len());
+        ProgramError::Fun {
+            message,
+            fun_line: self.fun.as_bytes()[..offset].iter().filter(|b| **b == b'\n').count() + 1,
+            line: self.fun_lines.line_at(offset),
+        }
+    }
+}
+
+impl FromStr for ProgramSpec {
+    type Err = ProgramError;
+
+    /// Parses the first document of a program file, keeping track of source
+    /// lines for error reporting.
+    fn from_str(src: &str) -> Result<Self, ProgramError> {
+        let docs = load(src)?;
+        let lines = Lines::collect(src);
+        match docs.first() {
+            Some(doc) => Self::from_document(src, doc, &lines[0]),
+            None => Err(ProgramError::Missing {
+                path: "type".to_string(),
+                line: None,
+            }),
+        }
+    }
+}
+
+fn load(src: &str) -> Result<Vec<Yaml>, ProgramError> {
+    YamlLoader::load_from_str(src).map_err(|e| ProgramError::Syntax {
+        message: e.to_string(),
+        line: e.marker().line(),
+    })
+}
+
+/// Reads `params: {name: value, ...}`, empty when the key is absent.
+fn params(node: Node) -> Result<Params, ProgramError> {
+    let mut params = Params::default();
+    if node.yaml.is_badvalue() {
+        return Ok(params);
+    }
+    let hash = node
+        .yaml
+        .as_hash()
+        .ok_or_else(|| node.wrong_type("a map of names to numbers, e.g. {f: 0.055}"))?;
+    for key in hash.keys() {
+        let name = key.as_str().unwrap_or_default();
+        let value = node.get(name);
+        if !Params::valid_name(name) {
+            return Err(value.wrong_type(
+                "named like a GLSL variable, not a keyword, built-in function or name of the generated shader",
+            ));
+        }
+        if value.yaml.as_hash().is_none() {
+            params.insert(name, value.finite_f32()?);
+            continue;
+        }
+        let (number, range) = param_range(&value)?;
+        params.insert_with_range(name, number, range);
+    }
+    Ok(params)
+}
+
+/// Reads `{value: 0.055, min: 0, max: 0.1, step: 0.001}`, where all but
+/// `value` are optional.
+fn param_range(node: &Node) -> Result<(f32, ParamRange), ProgramError> {
+    for key in node.yaml.as_hash().into_iter().flat_map(|hash| hash.keys()) {
+        let key = key.as_str().unwrap_or_default();
+        if !["value", "min", "max", "step"].contains(&key) {
+            return Err(node.get(key).wrong_type("`value`, `min`, `max` or `step`"));
+        }
+    }
+    let optional = |key: &str| {
+        let node = node.get(key);
+        if node.yaml.is_badvalue() {
+            Ok(None)
+        } else {
+            node.finite_f32().map(Some)
+        }
+    };
+    let value = node.get("value").finite_f32()?;
+    let mut range = ParamRange::around(value);
+    range.min = optional("min")?.unwrap_or(range.min);
+    range.max = optional("max")?.unwrap_or(range.max);
+    if range.min.is_finite() && range.max.is_finite() {
+        range.step = (range.max - range.min) / 100.;
+    }
+    range.step = optional("step")?.unwrap_or(range.step);
+    if !(range.min..=range.max).contains(&value) {
+        return Err(node.get("value").wrong_type("between `min` and `max`"));
+    }
+    if range.step <= 0. {
+        return Err(node.get("step").wrong_type("a positive number"));
+    }
+    Ok((value, range))
+}
+
+fn duration(node: Node) -> Result<Option<StageDuration>, ProgramError> {
+    if node.yaml.is_badvalue() {
+        return Ok(None);
+    }
+    if node.yaml.as_hash().is_none() {
+        return Ok(Some(StageDuration::Steps(node.u32()?.into())));
+    }
+    let (steps, seconds) = (node.get("steps"), node.get("seconds"));
+    let keys = node.yaml.as_hash().map_or(0, |hash| hash.len());
+    match (steps.yaml.is_badvalue(), seconds.yaml.is_badvalue()) {
+        (false, true) if keys == 1 => Ok(Some(StageDuration::Steps(steps.u32()?.into()))),
+        (true, false) if keys == 1 => {
+            let value = seconds.f32()?;
+            if value <= 0. {
+                return Err(seconds.wrong_type("a positive number"));
+            }
+            Ok(Some(StageDuration::Seconds(value)))
+        }
+        _ => Err(node.wrong_type("`{steps: N}` or `{seconds: S}`")),
+    }
+}
+
+/// Byte range of the literal in the first `float NAME = literal;` of `fun`.
+fn constant_literal(fun: &str, name: &str) -> Option<Range<usize>> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut from = 0;
+    while let Some(at) = fun[from..].find("float") {
+        let start = from + at;
+        from = start + "float".len();
+        if fun[..start].chars().next_back().is_some_and(is_ident) {
+            continue;
+        }
+        let rest = &fun[from..];
+        let Some(after_name) = rest.trim_start().strip_prefix(name) else { continue };
+        if rest.len() == rest.trim_start().len() || after_name.starts_with(is_ident) {
+            continue;
+        }
+        let Some(value) = after_name.trim_start().strip_prefix('=') else { continue };
+        let Some(end) = value.find(';') else { continue };
+        let literal = value[..end].trim();
+        if literal.trim_end_matches(['f', 'F']).parse::<f32>().is_err() {
+            continue;
+        }
+        let literal_start = fun.len() - value.trim_start().len();
+        return Some(literal_start..literal_start + literal.len());
+    }
+    None
+}
+
+fn square_matrix(node: Node) -> Result<Vec<Vec<f32>>, ProgramError> {
+    let size = node.vec()?.len();
+    if size == 0 {
+        return Err(node.bad_kernel("kernel is empty"));
+    }
+    (0..size)
+        .map(|i| {
+            let row = node.index(i);
+            let values = vector(row.clone())?;
+            if values.len() != size {
+                return Err(row.bad_kernel(&format!(
+                    "row has {} entries, expected {} (kernels must be square)",
+                    values.len(),
+                    size
+                )));
+            }
+            Ok(values)
+        })
+        .collect()
+}
+
+/// Reads `anchor: [x, y]`, which defaults to the center of odd-sized kernels
+/// and is required for even-sized ones.
+fn anchor(node: Node, kernel: Node, size: usize) -> Result<(u32, u32), ProgramError> {
+    if node.yaml.is_badvalue() {
+        if size.is_multiple_of(2) {
+            return Err(kernel.bad_kernel(&format!(
+                "size {} is even, add `anchor: [x, y]` to choose its center",
+                size
+            )));
+        }
+        let center = (size / 2) as u32;
+        return Ok((center, center));
+    }
+    if node.vec()?.len() != 2 {
+        return Err(node.wrong_type("a pair of kernel indices [x, y]"));
+    }
+    let anchor = (node.index(0).index_u32()?, node.index(1).index_u32()?);
+    if anchor.0 as usize >= size || anchor.1 as usize >= size {
+        return Err(node.bad_kernel(&format!(
+            "({}, {}) lies outside the {}x{} kernel",
+            anchor.0, anchor.1, size, size
+        )));
+    }
+    Ok(anchor)
+}
+
+fn vector(node: Node) -> Result<Vec<f32>, ProgramError> {
+    let len = node.vec()?.len();
+    if len == 0 {
+        return Err(node.bad_kernel("kernel is empty"));
+    }
+    (0..len).map(|i| node.index(i).f32()).collect()
+}
+
+fn edge_f32(node: Node) -> Result<EdgeSolution<f32>, ProgramError> {
+    if node.yaml.is_badvalue() {
+        return Ok(EdgeSolution::Clamp);
+    }
+    if let Some(edge) = edge_keyword(&node)? {
+        return Ok(edge);
+    }
+    match as_f32(node.yaml) {
+        Some(val) => Ok(EdgeSolution::Value(val)),
+        None => Err(ProgramError::InvalidEdge {
+            reason: "expected `wrap`, `clamp` or a number".to_string(),
+            line: node.line(),
+        }),
+    }
+}
+
+fn edge_3f32(node: Node) -> Result<EdgeSolution<(f32, f32, f32)>, ProgramError> {
+    if node.yaml.is_badvalue() {
+        return Ok(EdgeSolution::Clamp);
+    }
+    if let Some(edge) = edge_keyword(&node)? {
+        return Ok(edge);
+    }
+    let val: Option<Vec<f32>> = node.yaml.as_vec().and_then(|v| v.iter().map(as_f32).collect());
+    match val {
+        Some(val) if val.len() == 3 => Ok(EdgeSolution::Value((val[0], val[1], val[2]))),
+        _ => Err(ProgramError::InvalidEdge {
+            reason: "expected `wrap`, `clamp` or a tuple of 3 (r, g, b) values".to_string(),
+            line: node.line(),
+        }),
+    }
+}
+
+fn edge_keyword<T>(node: &Node) -> Result<Option<EdgeSolution<T>>, ProgramError> {
+    match node.yaml.as_str() {
+        Some("wrap") => Ok(Some(EdgeSolution::Wrap)),
+        Some("clamp") => Ok(Some(EdgeSolution::Clamp)),
+        Some(other) => Err(ProgramError::InvalidEdge {
+            reason: format!("unknown edge mode `{}`", other),
+            line: node.line(),
+        }),
+        None => Ok(None),
+    }
+}
+
+fn as_f32(yaml: &Yaml) -> Option<f32> {
+    match yaml {
+        Yaml::Real(_) => yaml.as_f64().map(|x| x as f32),
+        Yaml::Integer(i) => Some(*i as f32),
+        _ => None,
+    }
+}
+
+/// A value inside a document together with its key path, used to produce
+/// errors that point at the offending key.
+#[derive(Clone)]
+pub(crate) struct Node<'a> {
+    pub(crate) yaml: &'a Yaml,
+    pub(crate) path: String,
+    lines: &'a Lines,
+}
+
+impl<'a> Node<'a> {
+    pub(crate) fn root(yaml: &'a Yaml, lines: &'a Lines) -> Self {
+        Self {
+            yaml,
+            path: String::new(),
+            lines,
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Node<'a> {
+        Node {
+            yaml: &self.yaml[key],
+            path: if self.path.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", self.path, key)
+            },
+            lines: self.lines,
+        }
+    }
+
+    pub(crate) fn index(&self, i: usize) -> Node<'a> {
+        Node {
+            yaml: &self.yaml[i],
+            path: format!("{}[{}]", self.path, i),
+            lines: self.lines,
+        }
+    }
+
+    /// Line of this node, or of the closest ancestor below the document root
+    /// that exists in the source.
+    pub(crate) fn line(&self) -> Option<usize> {
+        let mut path = self.path.as_str();
+        loop {
+            if let Some(line) = self.lines.0.get(path) {
+                return Some(*line);
+            }
+            match path.rfind(['.', '[']) {
+                Some(i) => path = &path[..i],
+                None => return None,
+            }
+        }
+    }
+
+    fn require(&self) -> Result<&'a Yaml, ProgramError> {
+        if self.yaml.is_badvalue() {
+            Err(ProgramError::Missing {
+                path: self.path.clone(),
+                line: self.line(),
+            })
+        } else {
+            Ok(self.yaml)
+        }
+    }
+
+    pub(crate) fn wrong_type(&self, expected: &'static str) -> ProgramError {
+        ProgramError::WrongType {
+            path: self.path.clone(),
+            expected,
+            line: self.line(),
+        }
+    }
+
+    fn bad_kernel(&self, reason: &str) -> ProgramError {
+        ProgramError::BadKernel {
+            path: self.path.clone(),
+            reason: reason.to_string(),
+            line: self.line(),
+        }
+    }
+
+    pub(crate) fn str(&self) -> Result<&'a str, ProgramError> {
+        self.require()?
+            .as_str()
+            .ok_or_else(|| self.wrong_type("a string"))
+    }
+
+    pub(crate) fn f32(&self) -> Result<f32, ProgramError> {
+        as_f32(self.require()?).ok_or_else(|| self.wrong_type("a number"))
+    }
+
+    /// A number other than `.inf`, `-.inf` and `.nan`.
+    pub(crate) fn finite_f32(&self) -> Result<f32, ProgramError> {
+        Some(self.f32()?)
+            .filter(|x| x.is_finite())
+            .ok_or_else(|| self.wrong_type("a finite number"))
+    }
+
+    pub(crate) fn bool(&self) -> Result<bool, ProgramError> {
+        self.require()?
+            .as_bool()
+            .ok_or_else(|| self.wrong_type("`true` or `false`"))
+    }
+
+    pub(crate) fn u32(&self) -> Result<u32, ProgramError> {
+        self.require()?
+            .as_i64()
+            .and_then(|x| u32::try_from(x).ok())
+            .filter(|x| *x > 0)
+            .ok_or_else(|| self.wrong_type("a positive integer"))
+    }
+
+    pub(crate) fn index_u32(&self) -> Result<u32, ProgramError> {
+        self.require()?
+            .as_i64()
+            .and_then(|x| u32::try_from(x).ok())
+            .ok_or_else(|| self.wrong_type("a non-negative integer"))
+    }
+
+    pub(crate) fn u64(&self) -> Result<u64, ProgramError> {
+        self.require()?
+            .as_i64()
+            .and_then(|x| u64::try_from(x).ok())
+            .ok_or_else(|| self.wrong_type("a non-negative integer"))
+    }
+
+    pub(crate) fn vec(&self) -> Result<&'a Vec<Yaml>, ProgramError> {
+        self.require()?
+            .as_vec()
+            .ok_or_else(|| self.wrong_type("a list"))
+    }
+}
+
+/// Maps key paths of a document (`screen[0]`, `kernel[1][2]`, ...) to the
+/// 1-based line of the file they start on.
+#[derive(Default)]
+pub(crate) struct Lines(HashMap<String, usize>);
+
+impl Lines {
+    /// Lines of every document, in order.
+    pub(crate) fn collect(src: &str) -> Vec<Self> {
+        let mut receiver = LineReceiver::default();
+        let mut parser = Parser::new(src.chars());
+        // Syntax errors are reported by YamlLoader, here we only need lines.
+        let _ = parser.load(&mut receiver, true);
+        let mut docs: Vec<Self> = receiver.docs.into_iter().map(Lines).collect();
+        if docs.is_empty() {
+            docs.push(Lines::default());
+        }
+        docs
+    }
+}
+
+enum Frame {
+    Seq { path: String, index: usize },
+    Map { path: String, key: Option<(String, usize)> },
+}
+
+#[derive(Default)]
+struct LineReceiver {
+    docs: Vec<HashMap<String, usize>>,
+    stack: Vec<Frame>,
+}
+
+impl LineReceiver {
+    /// Path and line of the node that starts now, or `None` when the event is
+    /// a mapping key. Mapping values are placed on the line of their key.
+    fn enter(&mut self, ev: &Event, line: usize) -> Option<(String, usize)> {
+        match self.stack.last_mut() {
+            None => Some((String::new(), line)),
+            Some(Frame::Seq { path, index }) => Some((format!("{}[{}]", path, index), line)),
+            Some(Frame::Map { path, key }) => match key.take() {
+                Some((k, line)) if path.is_empty() => Some((k, line)),
+                Some((k, line)) => Some((format!("{}.{}", path, k), line)),
+                None => {
+                    let name = match ev {
+                        Event::Scalar(s, ..) => s.clone(),
+                        _ => String::new(),
+                    };
+                    *key = Some((name, line));
+                    None
+                }
+            },
+        }
+    }
+
+    /// Lines of the document being read.
+    fn lines(&mut self) -> &mut HashMap<String, usize> {
+        if self.docs.is_empty() {
+            self.docs.push(HashMap::new());
+        }
+        self.docs.last_mut().unwrap()
+    }
+
+    fn leave(&mut self) {
+        if let Some(Frame::Seq { index, .. }) = self.stack.last_mut() {
+            *index += 1;
+        }
+    }
+}
+
+impl MarkedEventReceiver for LineReceiver {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::DocumentStart => self.docs.push(HashMap::new()),
+            Event::Scalar(..) | Event::Alias(_) => {
+                if let Some((path, line)) = self.enter(&ev, mark.line()) {
+                    self.lines().entry(path).or_insert(line);
+                    self.leave();
+                }
+            }
+            Event::SequenceStart(_) | Event::MappingStart(_) => {
+                let (path, line) = self.enter(&ev, mark.line()).unwrap_or_default();
+                self.lines().entry(path.clone()).or_insert(line);
+                self.stack.push(match ev {
+                    Event::SequenceStart(_) => Frame::Seq { path, index: 0 },
+                    _ => Frame::Map { path, key: None },
+                });
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                self.stack.pop();
+                self.leave();
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAGE: &str = "screen: [4, 4]\ntype: val\nkernel: [[1]]\nfun: return x;\n";
+
+    fn stage_duration(duration: &str) -> Result<Option<StageDuration>, ProgramError> {
+        let src = format!("{}duration: {}\n", STAGE, duration);
+        Ok(ProgramSpec::playlist(&src)?[0].duration)
+    }
+
+    const VAL: &str = "screen: [4, 4]\ntype: val\nkernel: [[1]]\nedge: wrap\nfun: return x;\n";
+
+    /// Parses `VAL` with the line starting with `key` replaced by `line`, or
+    /// removed if `line` is empty.
+    fn val_error(key: &str, line: &str) -> ProgramError {
+        let src: String = VAL
+            .lines()
+            .map(|l| if l.starts_with(key) { line } else { l })
+            .filter(|l| !l.is_empty())
+            .map(|l| format!("{}\n", l))
+            .collect();
+        src.parse::<ProgramSpec>().unwrap_err()
+    }
+
+    #[test]
+    fn errors_carry_lines() {
+        let missing = val_error("screen", "screen: [4]");
+        assert_eq!(missing, ProgramError::Missing { path: "screen[1]".to_string(), line: Some(1) });
+        assert_eq!(missing.to_string(), "line 1: missing `screen[1]`");
+        // A key that isn't there has no line of its own.
+        let missing = val_error("fun", "");
+        assert_eq!(missing, ProgramError::Missing { path: "fun".to_string(), line: None });
+        assert_eq!(missing.to_string(), "missing `fun`");
+
+        let wrong = val_error("screen", "screen: [4, -4]");
+        let path = "screen[1]".to_string();
+        assert_eq!(wrong, ProgramError::WrongType { path, expected: "a positive integer", line: Some(1) });
+        assert_eq!(wrong.to_string(), "line 1: `screen[1]` must be a positive integer");
+        let wrong = val_error("fun", "fun: [1, 2]");
+        assert_eq!(wrong.to_string(), "line 5: `fun` must be a string");
+
+        let unknown = val_error("type", "type: hex");
+        assert_eq!(unknown, ProgramError::UnknownType { found: "hex".to_string(), line: Some(2) });
+        assert_eq!(unknown.to_string(), "line 2: unknown program type `hex` (expected `val`, `rgb` or `sym`)");
+
+        let edge = val_error("edge", "edge: mirror");
+        assert_eq!(edge, ProgramError::InvalidEdge { reason: "unknown edge mode `mirror`".to_string(), line: Some(4) });
+        assert_eq!(edge.to_string(), "line 4: invalid `edge`: unknown edge mode `mirror`");
+        let edge = val_error("edge", "edge: [1, 2]");
+        assert_eq!(edge.to_string(), "line 4: invalid `edge`: expected `wrap`, `clamp` or a number");
+        let rgb = "screen: [4, 4]\ntype: rgb\nkernel: [[1]]\nedge: 0.5\nfun: return v;\n".parse::<ProgramSpec>();
+        let reason = "expected `wrap`, `clamp` or a tuple of 3 (r, g, b) values".to_string();
+        assert_eq!(rgb.unwrap_err(), ProgramError::InvalidEdge { reason, line: Some(4) });
+
+        let syntax = "screen: [4, 4\ntype: val\n".parse::<ProgramSpec>().unwrap_err();
+        assert!(matches!(syntax, ProgramError::Syntax { line: 2.., .. }), "{:?}", syntax);
+        assert!(syntax.to_string().starts_with(&format!("line {}: invalid YAML: ", syntax.line().unwrap())));
+    }
+
+    #[test]
+    fn errors_without_source_have_no_lines() {
+        let docs = YamlLoader::load_from_str("screen: [4, 4]\ntype: hex\nfun: return x;\n").unwrap();
+        let error = ProgramSpec::from_yaml(&docs[0]).unwrap_err();
+        assert_eq!(error, ProgramError::UnknownType { found: "hex".to_string(), line: None });
+        assert_eq!(error.to_string(), "unknown program type `hex` (expected `val`, `rgb` or `sym`)");
+    }
+
+    /// A val program of `kernel`, given from line 3 on.
+    fn spec(kernel: &str) -> Result<ProgramSpec, ProgramError> {
+        format!("screen: [4, 4]\ntype: val\n{}fun: return x;\n", kernel).parse()
+    }
+
+    fn error(kernel: &str) -> String {
+        spec(kernel).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn kernels_must_be_square() {
+        let ragged = error("kernel:\n  - [1, 1]\n  - [1]\n");
+        assert_eq!(ragged, "line 5: bad kernel `kernel[1]`: row has 1 entries, expected 2 (kernels must be square)");
+        let wide = error("kernel:\n  - [1, 1, 1]\n  - [1, 1, 1]\n");
+        assert_eq!(wide, "line 4: bad kernel `kernel[0]`: row has 3 entries, expected 2 (kernels must be square)");
+        assert_eq!(error("kernel: []\n"), "line 3: bad kernel `kernel`: kernel is empty");
+        assert_eq!(error("kernel: [[]]\n"), "line 3: bad kernel `kernel[0]`: kernel is empty");
+        assert_eq!(error("kernel: [1, 2]\n"), "line 3: `kernel[0]` must be a list");
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(spec("kernel: [[1]]\n").unwrap().anchor, (0, 0));
+        assert_eq!(spec("kernel: [[1, 1, 1], [1, 1, 1], [1, 1, 1]]\n").unwrap().anchor, (1, 1));
+        assert_eq!(spec("kernel: [[1, 1], [1, 1]]\nanchor: [1, 0]\n").unwrap().anchor, (1, 0));
+        // Odd kernels can be anchored off center too.
+        assert_eq!(spec("kernel: [[1, 1, 1], [1, 1, 1], [1, 1, 1]]\nanchor: [0, 2]\n").unwrap().anchor, (0, 2));
+
+        let even = "line 3: bad kernel `kernel`: size 2 is even, add `anchor: [x, y]` to choose its center";
+        assert_eq!(error("kernel: [[1, 1], [1, 1]]\n"), even);
+        let errors = [
+            ("anchor: [2, 0]", "line 4: bad kernel `anchor`: (2, 0) lies outside the 2x2 kernel"),
+            ("anchor: [0, 5]", "line 4: bad kernel `anchor`: (0, 5) lies outside the 2x2 kernel"),
+            ("anchor: [-1, 0]", "line 4: `anchor[0]` must be a non-negative integer"),
+            ("anchor: [1]", "line 4: `anchor` must be a pair of kernel indices [x, y]"),
+            ("anchor: 1", "line 4: `anchor` must be a list"),
+        ];
+        for (anchor, message) in errors {
+            assert_eq!(error(&format!("kernel: [[1, 1], [1, 1]]\n{}\n", anchor)), message);
+        }
+    }
+
+    #[test]
+    fn sym_kernels() {
+        let sym = |kernels: &str| {
+            format!("screen: [4, 4]\ntype: sym\n{}fun: return v;\n", kernels).parse::<ProgramSpec>()
+        };
+        let spec = sym("kernelHor: [1, 2, 1]\nkernelVer: [1, 0, 1]\n").unwrap();
+        assert_eq!((spec.kind.kernel_size(), spec.anchor), (3, (1, 1)));
+        let mismatch = sym("kernelHor: [1, 2, 1]\nkernelVer: [1, 1]\n").unwrap_err().to_string();
+        assert_eq!(mismatch, "line 4: bad kernel `kernelVer`: has 2 entries but `kernelHor` has 3");
+        // The anchor of an even `sym` kernel is reported on `kernelHor`.
+        let even = sym("kernelHor: [1, 1]\nkernelVer: [1, 1]\n").unwrap_err().to_string();
+        assert_eq!(even, "line 3: bad kernel `kernelHor`: size 2 is even, add `anchor: [x, y]` to choose its center");
+        assert_eq!(sym("kernelHor: [1, 1]\nkernelVer: [1, 1]\nanchor: [0, 1]\n").unwrap().anchor, (0, 1));
+    }
+
+    fn params(section: &str) -> Result<Params, ProgramError> {
+        format!("{}params: {}\n", STAGE, section).parse::<ProgramSpec>().map(|spec| spec.params)
+    }
+
+    #[test]
+    fn param_values() {
+        let parsed = params("{f: 0.055, k: -2}").unwrap();
+        assert_eq!(parsed.iter().collect::<Vec<_>>(), [("f", 0.055), ("k", -2.)]);
+        for bad in ["{a: .nan}", "{a: .inf}", "{a: -.inf}"] {
+            assert_eq!(params(bad).unwrap_err().to_string(), "line 5: `params.a` must be a finite number", "{}", bad);
+        }
+        assert_eq!(params("{a: high}").unwrap_err().to_string(), "line 5: `params.a` must be a number");
+    }
+
+    #[test]
+    fn param_ranges() {
+        let parsed = params("{f: {value: 0.05, min: 0, max: 0.1}, k: {value: 2, step: 0.5}}").unwrap();
+        assert_eq!(parsed.range(0), ParamRange { min: 0., max: 0.1, step: 0.001 });
+        assert_eq!(parsed.range(1), ParamRange { min: f32::NEG_INFINITY, max: f32::INFINITY, step: 0.5 });
+        let bad_ranges = [
+            ("{a: {value: .nan}}", "`params.a.value` must be a finite number"),
+            ("{a: {value: 1, min: -.inf}}", "`params.a.min` must be a finite number"),
+            ("{a: {value: 1, max: .nan}}", "`params.a.max` must be a finite number"),
+            ("{a: {value: 1, step: .inf}}", "`params.a.step` must be a finite number"),
+            ("{a: {value: 1, step: .nan}}", "`params.a.step` must be a finite number"),
+            ("{a: {value: 1, step: 0}}", "`params.a.step` must be a positive number"),
+            ("{a: {value: 2, max: 1}}", "`params.a.value` must be between `min` and `max`"),
+            ("{a: {value: 1, by: 2}}", "`params.a.by` must be `value`, `min`, `max` or `step`"),
+        ];
+        for (bad, message) in bad_ranges {
+            assert_eq!(params(bad).unwrap_err().to_string(), format!("line 5: {}", message));
+        }
+    }
+
+    #[test]
+    fn bundled_playlist() {
+        let stages = ProgramSpec::playlist(include_str!("../../programs/blur_gol_playlist.yaml")).unwrap();
+        let durations: Vec<_> = stages.iter().map(|stage| stage.duration).collect();
+        assert_eq!(
+            durations,
+            [Some(StageDuration::Steps(12)), Some(StageDuration::Steps(1)), Some(StageDuration::Seconds(20.))]
+        );
+        let kernel_sizes: Vec<_> = stages
+            .iter()
+            .map(|stage| match &stage.kind {
+                ProgramKind::Val { kernel, .. } => kernel.len(),
+                kind => panic!("not a val stage: {:?}", kind),
+            })
+            .collect();
+        assert_eq!(kernel_sizes, [3, 1, 3]);
+        assert!(stages[2].fun.contains("x == 3."));
+        assert!(stages.iter().all(|stage| (stage.width, stage.height) == (1920, 1080)));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(stage_duration("12").unwrap(), Some(StageDuration::Steps(12)));
+        assert_eq!(stage_duration("{steps: 12}").unwrap(), Some(StageDuration::Steps(12)));
+        assert_eq!(stage_duration("{seconds: 2.5}").unwrap(), Some(StageDuration::Seconds(2.5)));
+        assert_eq!(ProgramSpec::playlist(STAGE).unwrap()[0].duration, None);
+        let bad_durations = [
+            "{steps: 1, seconds: 2}", "{seconds: -1}", "{seconds: 0}", "{steps: -1}", "{minutes: 1}", "{}", "soon",
+        ];
+        for bad in bad_durations {
+            assert!(stage_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn documents() {
+        let two = format!("{}---\n{}---\n", STAGE, STAGE.replace("[4, 4]", "[8, 2]"));
+        let stages = ProgramSpec::playlist(&two).unwrap();
+        assert_eq!(stages.iter().map(|stage| (stage.width, stage.height)).collect::<Vec<_>>(), [(4, 4), (8, 2)]);
+        assert!(ProgramSpec::playlist("").is_err());
+        assert!(ProgramSpec::playlist(&format!("{}---\nscreen: [4, 4]\n", STAGE)).is_err());
+    }
+}
@@ -1,9 +1,9 @@
 use std::mem::size_of;
 
-use glium::{program::ComputeShader, uniform, Display, Texture2d, uniforms::UniformBuffer};
+use glium::{backend::Facade, program::ComputeShader, uniform, Texture2d, uniforms::UniformBuffer};
 use yaml_rust::Yaml;
 
-use super::{Program, EdgeSolution};
+use super::{Program, EdgeSolution, Params, ProgramError, WithParams, ProgramKind, ProgramSpec, kind_mismatch, compile_error, shader::{validate, swap_shader_src, val_shader_src, WORK_GROUP_SIZE}};
 
 pub struct ValProgram {
     width: u32,
@@ -11,18 +11,23 @@ pub struct ValProgram {
     convolution_shader: ComputeShader,
     swap_shader: ComputeShader,
     kernel_buf: UniformBuffer<[f32]>,
-    kernel_size: usize
+    kernel_size: usize,
+    anchor: (u32, u32),
+    params: Params,
 }
 
 impl ValProgram {
-    pub fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<F: Facade + ?Sized>(
         width: u32,
         height: u32,
         fun: &str,
+        params: Params,
         kernel: Vec<Vec<f32>>,
-        display: &Display,
+        anchor: (u32, u32),
+        facade: &F,
         edge_solution: EdgeSolution<f32>
-    ) -> Self {
+    ) -> Result<Self, ProgramError> {
         let clamp_src = edge_solution.csample_src();
         let kernel_size = kernel.len();
         let flat_kernel: Vec<f32> = kernel.iter()
@@ -31,26 +36,48 @@ impl ValProgram {
             .collect();
 
         
-        let kernel_buf: UniformBuffer<[f32]> = UniformBuffer::empty_unsized_immutable(display, kernel_size*kernel_size*size_of::<f32>()).unwrap();
+        let kernel_buf: UniformBuffer<[f32]> = UniformBuffer::empty_unsized_immutable(facade, kernel_size*kernel_size*size_of::<f32>()).unwrap();
         kernel_buf.write(&flat_kernel);
 
-        Self {
+        let source = val_shader_src(fun, &params, &clamp_src, kernel_size * kernel_size);
+        let convolution_shader = ComputeShader::from_source(facade, &source)
+            .map_err(|e| compile_error(e, &source, fun))?;
+        let swap_source = swap_shader_src();
+        let swap_shader = ComputeShader::from_source(facade, &swap_source)
+            .map_err(|e| compile_error(e, &swap_source, fun))?;
+
+        Ok(Self {
             width,
             height,
-            convolution_shader: glium::program::ComputeShader::from_source(
-                display,
-                &convolution_shader_src(fun, &clamp_src, kernel_size * kernel_size)
-            )
-            .unwrap(),
-            swap_shader: glium::program::ComputeShader::from_source(display, &SWAP_SHADER_SRC)
-                .unwrap(),
+            convolution_shader,
+            swap_shader,
             kernel_buf,
-            kernel_size
+            kernel_size,
+            anchor,
+            params,
+        })
+    }
+
+    pub fn from_yaml<F: Facade + ?Sized>(doc: &Yaml, facade: &F) -> Result<Self, ProgramError> {
+        let spec = ProgramSpec::from_yaml(doc)?;
+        validate(&spec)?;
+        match spec {
+            ProgramSpec { width, height, fun, anchor, params, kind: ProgramKind::Val { kernel, edge }, .. } => Self::new(
+                width,
+                height,
+                &fun,
+                params,
+                kernel,
+                anchor,
+                facade,
+                edge
+            ),
+            _ => Err(kind_mismatch("`val`")),
         }
     }
 }
 
-impl Program for ValProgram {
+impl Program<Texture2d> for ValProgram {
     fn step(&self, board: &mut Texture2d) {
         let image_unit = board
             .image_unit(glium::uniforms::ImageUnitFormat::RGBA32F)
@@ -58,15 +85,19 @@ impl Program for ValProgram {
             .set_access(glium::uniforms::ImageUnitAccess::ReadWrite);
         
         self.convolution_shader.execute(
-            uniform! { 
-                uWidth: self.width, 
-                uHeight: self.height, 
-                uKernelSize: self.kernel_size as i32, 
-                uKernel: &self.kernel_buf, 
-                uTexture: image_unit
-            }, 
-            self.width/16 + if self.width % 16 > 0 {1} else {0}, 
-            self.height/16 + if self.height % 16 > 0 {1} else {0}, 
+            WithParams {
+                uniforms: uniform! { 
+                    uWidth: self.width, 
+                    uHeight: self.height, 
+                    uKernelSize: self.kernel_size as i32, 
+                    uAnchor: (self.anchor.0 as i32, self.anchor.1 as i32),
+                    uKernel: &self.kernel_buf, 
+                    uTexture: image_unit
+                },
+                params: &self.params,
+            },
+            self.width.div_ceil(WORK_GROUP_SIZE.0), 
+            self.height.div_ceil(WORK_GROUP_SIZE.1), 
             1
         );
 
@@ -76,8 +107,8 @@ impl Program for ValProgram {
             .set_access(glium::uniforms::ImageUnitAccess::ReadWrite);
         self.swap_shader.execute(
             uniform! { uWidth: self.width, uHeight: self.height, uTexture: image_unit},
-            self.width/16 + if self.width % 16 > 0 {1} else {0}, 
-            self.height/16 + if self.height % 16 > 0 {1} else {0}, 
+            self.width.div_ceil(WORK_GROUP_SIZE.0), 
+            self.height.div_ceil(WORK_GROUP_SIZE.1), 
             1,
         );
     }
@@ -86,106 +117,11 @@ impl Program for ValProgram {
         (self.width, self.height)
     }
 
-    fn from_yaml(doc: &Yaml, display: &Display) -> Self {
-        let edge = {
-            if let Some(edge) = doc["edge"].as_str() {
-                if edge == "wrap" {
-                    EdgeSolution::Wrap
-                } else if edge == "clamp" {
-                    EdgeSolution::Clamp
-                } else {
-                    println!("Invalid edge value!");
-                    EdgeSolution::Clamp
-                }
-            } else if let Some(val) = doc["edge"].as_f64().map(|x| x as f32) {
-                EdgeSolution::Value(val)
-            } else {
-                println!("Invalid edge value!");
-                EdgeSolution::Clamp
-            }
-        };
-        Self::new(
-            doc["screen"][0].as_i64().unwrap().try_into().unwrap(),
-            doc["screen"][1].as_i64().unwrap().try_into().unwrap(),
-            doc["fun"].as_str().unwrap(),
-            doc["kernel"]
-                .as_vec()
-                .unwrap()
-                .into_iter()
-                .map(|s| {
-                    s.as_vec()
-                        .expect(&format!(
-                            "Error reading program file: Kernel not an array {:?}",
-                            s
-                        ))
-                        .into_iter()
-                        .map(|yaml| {
-                            yaml.as_f64().expect(&format!(
-                                "Error reading program file: Kernel not a float ({:?})",
-                                s
-                            )) as f32
-                        })
-                        .collect::<Vec<f32>>()
-                })
-                .collect::<Vec<Vec<f32>>>(),
-            display,
-            edge
-        )
+    fn params(&self) -> &Params {
+        &self.params
     }
-}
-
-const SWAP_SHADER_SRC: &'static str = r#"
-#version 430
-
-layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
-
-uniform uint uWidth;
-uniform uint uHeight;
-uniform layout(binding=3, rgba32f) image2D uTexture;
-
 
-void main() {
-    ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
-    if (i.x >= int(uWidth) || i.y >= int(uHeight))
-        return;
-
-    vec4 pixel_sample = imageLoad(uTexture, i);
-    imageStore(uTexture, i, vec4(pixel_sample.g, pixel_sample.r, pixel_sample.b, pixel_sample.a) );
+    fn params_mut(&mut self) -> &mut Params {
+        &mut self.params
+    }
 }
-"#;
-
-fn convolution_shader_src(fun_src: &str, csample_src: &str, kernel_size_sq: usize) -> String {
-    format!(
-        "#version 430
-
-    layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
-    
-    uniform uint uWidth;
-    uniform uint uHeight;
-    uniform int uKernelSize;
-    uniform uKernel{{
-        float kernel[{kernel_size_sq}];
-    }};
-    uniform layout(binding=3, rgba32f) image2D uTexture;
-
-    vec4 csample(ivec2 i) {{
-        {csample_src}
-    }}
-    float fun(float x, float prev) {{
-        {fun_src}
-    }}
-
-    void main() {{
-        ivec2 i = ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y);
-        if (i.x >= int(uWidth) || i.y >= int(uHeight))
-            return;
-
-        float sum = 0;
-        int offset = uKernelSize / 2;
-        for (int k = 0; k < uKernelSize*uKernelSize; ++k)
-            sum += csample(i + ivec2(mod(k, uKernelSize) - offset, k / uKernelSize - offset)).r * kernel[k];
-
-        vec4 pixel_sample = imageLoad(uTexture, i);
-        imageStore(uTexture, i, vec4(pixel_sample.r, fun(sum, pixel_sample.r), pixel_sample.b, pixel_sample.a) );
-    }}" )
-}
\ No newline at end of file
@@ -0,0 +1,393 @@
+//! Recording boards as animations while a simulation runs.
+//!
+//! The output is picked by the path: `.gif` for an animated GIF, `.png` or
+//! `.apng` for an animated PNG, anything else is a directory that gets a
+//! numbered PNG sequence (`000000.png`, `000001.png`, ...), e.g. for
+//! `ffmpeg -i %06d.png`. Animations play at 30 frames per second. Colors are
+//! mapped as in `formats::rgb8`.
+//!
+//! GIF frames are written as they come, APNG frames are kept in memory until
+//! `Recorder::finish` since the frame count goes into the file header.
+//!
+//! `Recorder::stream` writes uncompressed frames to stdout or a named pipe
+//! instead, for piping into a video encoder such as ffmpeg.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{cpu::CpuBoard, formats};
+
+const FPS: u16 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordOptions {
+    pub path: PathBuf,
+    /// Record every `every`th step.
+    pub every: u32,
+    /// Downscale factor, 1 keeps the board size.
+    pub scale: u32,
+}
+
+/// Uncompressed frame formats for `Recorder::stream`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamFormat {
+    /// Bare RGB bytes, the reader has to be told the frame size and rate.
+    Rgb24,
+    /// YUV4MPEG2 with 4:4:4 BT.601 limited range frames and a header giving
+    /// the size and rate.
+    Y4m,
+}
+
+impl FromStr for StreamFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb24" => Ok(StreamFormat::Rgb24),
+            "y4m" => Ok(StreamFormat::Y4m),
+            _ => Err(format!("unknown stream format `{}`, expected `rgb24` or `y4m`", s)),
+        }
+    }
+}
+
+enum Output {
+    Sequence,
+    Gif(gif::Encoder<BufWriter<File>>),
+    Apng(Vec<Vec<u8>>),
+    Stream(StreamFormat, Box<dyn Write>),
+}
+
+pub struct Recorder {
+    options: RecordOptions,
+    output: Output,
+    /// Width and height of the frames.
+    size: (u32, u32),
+    /// Steps since the last recorded frame.
+    steps: u32,
+    frames: u32,
+}
+
+impl Recorder {
+    /// Starts a recording of boards of the given size. The first frame
+    /// should be the board the recording starts from.
+    pub fn new(options: RecordOptions, width: u32, height: u32) -> io::Result<Self> {
+        let (frame_width, frame_height) = (width / options.scale, height / options.scale);
+        if frame_width == 0 || frame_height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "downscaled frames would be empty"));
+        }
+        let output = match extension(&options.path).as_deref() {
+            Some("gif") => {
+                let (w, h) = match (u16::try_from(frame_width), u16::try_from(frame_height)) {
+                    (Ok(w), Ok(h)) => (w, h),
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "frames too large for a GIF")),
+                };
+                let file = BufWriter::new(File::create(&options.path)?);
+                let mut encoder = gif::Encoder::new(file, w, h, &[]).map_err(gif_error)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+                Output::Gif(encoder)
+            }
+            Some("png" | "apng") => {
+                // Fail now rather than after recording.
+                File::create(&options.path)?;
+                Output::Apng(Vec::new())
+            }
+            _ => {
+                fs::create_dir_all(&options.path)?;
+                Output::Sequence
+            }
+        };
+        Ok(Self {
+            options,
+            output,
+            size: (frame_width, frame_height),
+            steps: 0,
+            frames: 0,
+        })
+    }
+
+    /// Starts streaming frames to the file or pipe at `options.path`, or to
+    /// stdout if the path is `-`.
+    pub fn stream(options: RecordOptions, format: StreamFormat, width: u32, height: u32) -> io::Result<Self> {
+        let (frame_width, frame_height) = (width / options.scale, height / options.scale);
+        if frame_width == 0 || frame_height == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "downscaled frames would be empty"));
+        }
+        let mut writer: Box<dyn Write> = if options.path == Path::new("-") {
+            Box::new(BufWriter::new(io::stdout()))
+        } else {
+            // Opening a named pipe for writing waits for its reader, don't truncate it.
+            let file = OpenOptions::new().write(true).create(true).truncate(false).open(&options.path)?;
+            if file.metadata()?.is_file() {
+                file.set_len(0)?;
+            }
+            Box::new(BufWriter::new(file))
+        };
+        if format == StreamFormat::Y4m {
+            writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", frame_width, frame_height, FPS)?;
+        }
+        Ok(Self {
+            options,
+            output: Output::Stream(format, writer),
+            size: (frame_width, frame_height),
+            steps: 0,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.options.path
+    }
+
+    /// Counts a simulation step, returns whether the board after it should be
+    /// passed to `frame`.
+    pub fn step(&mut self) -> bool {
+        self.steps += 1;
+        self.steps >= self.options.every
+    }
+
+    pub fn frame(&mut self, board: &CpuBoard) -> io::Result<()> {
+        self.steps = 0;
+        if formats::scaled_size(board, self.options.scale) != self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "board size changed while recording"));
+        }
+        let data = formats::rgb8(board, self.options.scale);
+        let (width, height) = self.size;
+        match &mut self.output {
+            Output::Sequence => {
+                let path = self.options.path.join(format!("{:06}.png", self.frames));
+                let mut file = BufWriter::new(File::create(path)?);
+                formats::write_rgb8_png(&mut file, width, height, &data)?;
+                file.flush()?;
+            }
+            Output::Gif(encoder) => {
+                let mut frame = gif::Frame::from_rgb_speed(width as u16, height as u16, &data, 10);
+                frame.delay = 100 / FPS;
+                encoder.write_frame(&frame).map_err(gif_error)?;
+            }
+            Output::Apng(frames) => frames.push(data),
+            Output::Stream(StreamFormat::Rgb24, writer) => writer.write_all(&data)?,
+            Output::Stream(StreamFormat::Y4m, writer) => {
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&ycbcr_planes(&data))?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Finishes the file, returning the number of frames recorded.
+    pub fn finish(self) -> io::Result<u32> {
+        match self.output {
+            Output::Sequence => (),
+            Output::Gif(encoder) => {
+                encoder.into_inner()?.flush()?;
+            }
+            Output::Apng(frames) if !frames.is_empty() => {
+                let mut file = BufWriter::new(File::create(&self.options.path)?);
+                let mut encoder = png::Encoder::new(&mut file, self.size.0, self.size.1);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frames.len() as u32, 0).map_err(io::Error::from)?;
+                encoder.set_frame_delay(1, FPS).map_err(io::Error::from)?;
+                let mut writer = encoder.write_header().map_err(io::Error::from)?;
+                for data in &frames {
+                    writer.write_image_data(data).map_err(io::Error::from)?;
+                }
+                writer.finish().map_err(io::Error::from)?;
+                file.flush()?;
+            }
+            Output::Apng(_) => (),
+            Output::Stream(_, mut writer) => writer.flush()?,
+        }
+        Ok(self.frames)
+    }
+}
+
+/// Converts RGB bytes to Y, Cb and Cr planes, BT.601 limited range.
+fn ycbcr_planes(rgb: &[u8]) -> Vec<u8> {
+    let n = rgb.len() / 3;
+    let mut planes = vec![0; n * 3];
+    for (i, pixel) in rgb.chunks_exact(3).enumerate() {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|c| c as f32);
+        let y = 16. + (65.481 * r + 128.553 * g + 24.966 * b) / 255.;
+        let cb = 128. + (-37.797 * r - 74.203 * g + 112. * b) / 255.;
+        let cr = 128. + (112. * r - 93.786 * g - 18.214 * b) / 255.;
+        planes[i] = y.round() as u8;
+        planes[n + i] = cb.round() as u8;
+        planes[2 * n + i] = cr.round() as u8;
+    }
+    planes
+}
+
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_string_lossy().to_lowercase())
+}
+
+fn gif_error(e: gif::EncodingError) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("gpu_convolution_record_{}_{}", std::process::id(), name))
+    }
+
+    /// A 4x2 board in shades of `level`, different for every frame.
+    fn board(level: f32) -> CpuBoard {
+        let mut board = CpuBoard::new(4, 2);
+        for y in 0..2 {
+            for x in 0..4 {
+                board.set(x, y, [level, x as f32 / 3., y as f32, 1.]);
+            }
+        }
+        board
+    }
+
+    /// Records three boards to `name`, returning the file and the frame count.
+    fn record(name: &str, scale: u32) -> (PathBuf, u32) {
+        let path = temp_path(name);
+        let mut recorder = Recorder::new(RecordOptions { path: path.clone(), every: 1, scale }, 4, 2).unwrap();
+        for level in [0., 0.5, 1.] {
+            recorder.frame(&board(level)).unwrap();
+        }
+        (path, recorder.finish().unwrap())
+    }
+
+    #[test]
+    fn gif_frames() {
+        let (path, frames) = record("frames.gif", 1);
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frames, 3);
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(&bytes[..]).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (4, 2));
+        let mut count = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 3);
+            // Black and white survive the palette exactly.
+            let last = frame.buffer.chunks(4).last().unwrap();
+            assert_eq!(last, [[0, 255, 0, 255], [128, 255, 0, 255], [255, 255, 0, 255]][count]);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn apng_frames() {
+        let (path, frames) = record("frames.apng", 2);
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frames, 3);
+
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(info.animation_control().unwrap().num_frames, 3);
+        let mut buf = vec![0; reader.output_buffer_size()];
+        for level in [0, 128, 255] {
+            reader.next_frame(&mut buf).unwrap();
+            let control = reader.info().frame_control().unwrap();
+            assert_eq!((control.delay_num, control.delay_den), (1, FPS));
+            assert_eq!(buf[0], level);
+        }
+    }
+
+    #[test]
+    fn png_sequence() {
+        let (dir, frames) = record("sequence", 1);
+        let mut names: Vec<_> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["000000.png", "000001.png", "000002.png"]);
+        let last = formats::load(&dir.join("000002.png")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(formats::rgb8(&last, 1), formats::rgb8(&board(1.), 1));
+        assert_eq!(frames, 3);
+    }
+
+    #[test]
+    fn frames_must_keep_their_size() {
+        let options = RecordOptions { path: temp_path("empty.gif"), every: 1, scale: 8 };
+        assert!(Recorder::new(options, 4, 2).is_err());
+        let path = temp_path("resized.apng");
+        let mut recorder = Recorder::new(RecordOptions { path: path.clone(), every: 1, scale: 1 }, 4, 2).unwrap();
+        assert!(recorder.frame(&CpuBoard::new(2, 2)).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Streams the start board and `steps` more, recording every `every`th
+    /// step, and returns the bytes written.
+    fn stream(name: &str, format: StreamFormat, every: u32, scale: u32, steps: u32) -> Vec<u8> {
+        let path = temp_path(name);
+        let options = RecordOptions { path: path.clone(), every, scale };
+        let mut recorder = Recorder::stream(options, format, 4, 2).unwrap();
+        recorder.frame(&board(0.)).unwrap();
+        for step in 1..=steps {
+            if recorder.step() {
+                recorder.frame(&board(step as f32 / steps as f32)).unwrap();
+            }
+        }
+        assert_eq!(recorder.finish().unwrap(), 1 + steps / every);
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn y4m_stream() {
+        let bytes = stream("frames.y4m", StreamFormat::Y4m, 2, 1, 4);
+        let header = b"YUV4MPEG2 W4 H2 F30:1 Ip A1:1 C444\n";
+        assert_eq!(&bytes[..header.len()], header);
+        // Steps 0, 2 and 4, each a marker and three 4x2 planes.
+        let frame = b"FRAME\n".len() + 4 * 2 * 3;
+        assert_eq!(bytes.len(), header.len() + 3 * frame);
+        for i in 0..3 {
+            let start = header.len() + i * frame;
+            assert_eq!(&bytes[start..start + 6], b"FRAME\n");
+            let planes = &bytes[start + 6..start + frame];
+            assert_eq!(planes, ycbcr_planes(&formats::rgb8(&board(i as f32 / 2.), 1)));
+        }
+        // A downscaled stream says so in the header.
+        let bytes = stream("small.y4m", StreamFormat::Y4m, 1, 2, 1);
+        let header = b"YUV4MPEG2 W2 H1 F30:1 Ip A1:1 C444\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 2 * (6 + 2 * 3));
+    }
+
+    #[test]
+    fn rgb24_stream() {
+        let bytes = stream("frames.rgb", StreamFormat::Rgb24, 3, 1, 7);
+        // Steps 0, 3 and 6, rows top to bottom without any header.
+        let expected: Vec<u8> = [0., 3. / 7., 6. / 7.].iter().flat_map(|&l| formats::rgb8(&board(l), 1)).collect();
+        assert_eq!(bytes, expected);
+        assert_eq!(&bytes[..3], [0, 0, 255]);
+        assert_eq!(&bytes[4 * 3..4 * 3 + 3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn bt601_limited_range() {
+        let rgb = [[0, 0, 0], [255, 255, 255], [255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        let planes = ycbcr_planes(rgb.as_flattened());
+        assert_eq!(&planes[..5], [16, 235, 81, 145, 41]);
+        assert_eq!(&planes[5..10], [128, 128, 90, 54, 240]);
+        assert_eq!(&planes[10..], [128, 128, 240, 34, 110]);
+    }
+
+    #[test]
+    fn stream_formats() {
+        assert_eq!("rgb24".parse(), Ok(StreamFormat::Rgb24));
+        assert_eq!("y4m".parse(), Ok(StreamFormat::Y4m));
+        assert!("yuv".parse::<StreamFormat>().is_err());
+    }
+}
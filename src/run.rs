@@ -0,0 +1,423 @@
+//! `run` subcommand: steps a program without opening a window and writes the
+//! boards to disk, for long renders on machines without a display.
+
+use std::{error::Error, fs, path::PathBuf, str::FromStr};
+
+use gpu_convolution::{
+    backend::{Backend, CpuBackend, GlBackend},
+    board::{random_board_binary, random_seed, InitialBoard},
+    cpu::CpuBoard,
+    cycle::{CycleAction, CycleDetector},
+    formats::{self, Format},
+    program::ProgramSpec,
+    record::{RecordOptions, Recorder, StreamFormat},
+    stats::StatsWriter,
+    stop::{StopCheck, StopConditions},
+};
+
+const USAGE: &str = "usage: gpu_convolution run <program.yaml> [options]
+  --steps N          steps to run (default `stop: steps:` in the program, or 100)
+  --init INIT        program (its init: section), empty, random, random-binary or an image
+                     (.png, .ppm, .pgm or .pfm, default program)
+  --fit FIT          how an --init image fits the board: stretch, crop or cover (default stretch)
+  --map MAP          board channels to image channels, e.g. rgb=rgb (default) or g=l for
+                     luminance into green
+  --seed N           seed for random fills (default `seed:` in the program, or a random one)
+  --out FILE         where to write the final board, as .pfm, .png or .npy
+                     (default <program>_<last step>.<format>)
+  --every K          also write every Kth board, starting with the initial one
+  --frames DIR       directory for the boards written by --every (default .)
+  --format FORMAT    pfm (32-bit float RGB), png (8-bit, clamped) or npy (float RGBA) for the
+                     boards written by --every and the default --out (default pfm)
+  --record PATH      record an animation: .gif, .png (APNG) or a directory for numbered PNGs
+  --record-every N   record every Nth step (default 1)
+  --record-scale N   shrink recorded frames N times (default 1)
+  --stream FORMAT    write rgb24 or y4m frames to stdout, with the same stride and scale as
+                     --record, e.g. `--stream y4m | ffmpeg -i - out.mp4`
+  --stream-to PATH   write the --stream frames to a file or named pipe instead
+  --stats FILE       write live cells and per channel mean, min, max and variance to a .csv
+                     or .jsonl file
+  --stats-every N    compute statistics every Nth step, starting with the initial board
+                     (default 1)
+  --cycle ACTION     print or reseed when the board stops changing or repeats
+  --cycle-every N    look for repeats every Nth step, only finds periods that are multiples of
+                     N (default 1)
+  --stop-population N
+                     stop once at most N cells are live (red at least 0.5)
+  --stop-mean COND   stop once a channel mean crosses a threshold, e.g. `r>0.8` or `g<0.1`,
+                     can be repeated
+  --stop-static      stop once the board no longer changes
+  --stop-cycle       stop once the board repeats, static or periodic
+  --stop-seconds S   stop after S seconds of wall-clock time
+  --stop-every N     check the board for stop conditions every Nth step (default 1)
+  --param NAME=VALUE set a parameter from the program's `params:`, can be repeated
+  --backend NAME     cpu or gl (default cpu, gl needs a windowing system)
+
+Stop conditions add to the program's `stop:` section. The exit status tells which one ended
+the run: 3 population, 4 mean, 5 static, 6 cycle, 7 time, 0 when all steps ran.";
+
+pub(crate) enum BackendKind {
+    Cpu,
+    Gl,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(BackendKind::Cpu),
+            "gl" => Ok(BackendKind::Gl),
+            _ => Err(format!("unknown backend `{}`", s)),
+        }
+    }
+}
+
+struct RunArgs {
+    program: PathBuf,
+    steps: Option<u32>,
+    init: InitialBoard,
+    seed: Option<u64>,
+    out: Option<PathBuf>,
+    every: Option<u32>,
+    frames: PathBuf,
+    format: Format,
+    record: Option<PathBuf>,
+    record_every: u32,
+    record_scale: u32,
+    stream: Option<StreamFormat>,
+    stream_to: PathBuf,
+    stats: Option<PathBuf>,
+    stats_every: u32,
+    cycle: Option<CycleAction>,
+    cycle_every: u32,
+    /// Stop conditions from the command line, `every` is in `stop_every`.
+    stop: StopConditions,
+    stop_every: Option<u32>,
+    params: Vec<(String, f32)>,
+    backend: BackendKind,
+}
+
+impl RunArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut program = None;
+        let mut run = RunArgs {
+            program: PathBuf::new(),
+            steps: None,
+            init: InitialBoard::Program,
+            seed: None,
+            out: None,
+            every: None,
+            frames: PathBuf::from("."),
+            format: Format::Pfm,
+            record: None,
+            record_every: 1,
+            record_scale: 1,
+            stream: None,
+            stream_to: PathBuf::from("-"),
+            stats: None,
+            stats_every: 1,
+            cycle: None,
+            cycle_every: 1,
+            stop: StopConditions::default(),
+            stop_every: None,
+            params: Vec::new(),
+            backend: BackendKind::Cpu,
+        };
+
+        let (mut fit, mut map) = (None, None);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if program.replace(PathBuf::from(arg)).is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                continue;
+            }
+            match arg.as_str() {
+                "--seed" => {
+                    run.seed = Some(crate::parse_seed(args.next())?);
+                    continue;
+                }
+                "--stop-static" => {
+                    run.stop.static_board = true;
+                    continue;
+                }
+                "--stop-cycle" => {
+                    run.stop.cycle = true;
+                    continue;
+                }
+                _ => (),
+            }
+            let value = args.next().ok_or(format!("`{}` needs a value", arg))?;
+            let number = || match value.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(format!("`{}` must be a positive number, got `{}`", arg, value)),
+            };
+            match arg.as_str() {
+                "--steps" => run.steps = Some(number()?),
+                "--init" => run.init = value.parse().unwrap(),
+                "--fit" => fit = Some(crate::parse_fit(Some(value))?),
+                "--map" => map = Some(crate::parse_map(Some(value))?),
+                "--out" => {
+                    let out = PathBuf::from(value);
+                    Format::from_path(&out).ok_or(format!("`--out` must end in .pfm, .png or .npy, got `{}`", value))?;
+                    run.out = Some(out);
+                }
+                "--every" => run.every = Some(number()?),
+                "--frames" => run.frames = PathBuf::from(value),
+                "--format" => run.format = value.parse()?,
+                "--record" => run.record = Some(PathBuf::from(value)),
+                "--record-every" => run.record_every = number()?,
+                "--record-scale" => run.record_scale = number()?,
+                "--stream" => run.stream = Some(value.parse()?),
+                "--stream-to" => run.stream_to = PathBuf::from(value),
+                "--stats" => run.stats = Some(PathBuf::from(value)),
+                "--stats-every" => run.stats_every = number()?,
+                "--cycle" => {
+                    run.cycle = match value.parse()? {
+                        CycleAction::Pause => return Err("`--cycle pause` only works in the viewer".into()),
+                        action => Some(action),
+                    }
+                }
+                "--cycle-every" => run.cycle_every = number()?,
+                "--stop-population" => {
+                    let population = value.parse();
+                    run.stop.population = Some(population.map_err(|_| {
+                        format!("`{}` must be a non-negative number, got `{}`", arg, value)
+                    })?);
+                }
+                "--stop-mean" => run.stop.mean.push(value.parse()?),
+                "--stop-seconds" => {
+                    run.stop.seconds = match value.parse::<f32>() {
+                        Ok(s) if s.is_finite() && s > 0. => Some(s),
+                        _ => return Err(format!("`{}` must be a positive number, got `{}`", arg, value)),
+                    }
+                }
+                "--stop-every" => run.stop_every = Some(number()?),
+                "--param" => run.params.push(crate::parse_param(Some(value))?),
+                "--backend" => run.backend = value.parse()?,
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        crate::set_image_options(&mut run.init, fit, map)?;
+        run.program = program.ok_or("missing program file")?;
+        Ok(run)
+    }
+
+    /// The program's `stop:` section with the command line options added.
+    fn stop_conditions(&self, spec: &ProgramSpec) -> StopConditions {
+        let mut stop = spec.stop.clone();
+        stop.population = self.stop.population.or(stop.population);
+        stop.mean.extend(&self.stop.mean);
+        stop.static_board |= self.stop.static_board;
+        stop.cycle |= self.stop.cycle;
+        stop.seconds = self.stop.seconds.or(stop.seconds);
+        stop.every = self.stop_every.unwrap_or(stop.every);
+        stop
+    }
+
+    fn name(&self) -> String {
+        self.program
+            .file_stem()
+            .map_or("board".into(), |stem| stem.to_string_lossy().into_owned())
+    }
+}
+
+/// Runs the subcommand with the arguments following `run`, returning the
+/// exit code.
+pub fn main(args: &[String]) -> i32 {
+    let args = match RunArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let spec = crate::read_spec(&args.program.to_string_lossy());
+
+    let result = match args.backend {
+        BackendKind::Cpu => simulate(&CpuBackend::default(), &spec, &args),
+        BackendKind::Gl => GlBackend::headless().and_then(|backend| simulate(&backend, &spec, &args)),
+    };
+    match result {
+        Ok(None) => 0,
+        Ok(Some(code)) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Runs the simulation, returning the exit status of the stop condition that
+/// ended it, if any.
+fn simulate<B: Backend>(backend: &B, spec: &ProgramSpec, args: &RunArgs) -> Result<Option<i32>, Box<dyn Error>> {
+    let mut program = backend.program(spec)?;
+    crate::set_params(program.as_mut(), &args.params)?;
+    let mut seed = args.seed.or(spec.seed).unwrap_or_else(random_seed);
+    if args.init.uses_seed(spec) {
+        eprintln!("seed: {}", seed);
+    }
+    let mut board = backend.upload(&args.init.board(spec, seed)?);
+    let options = |path: &PathBuf| RecordOptions {
+        path: path.clone(),
+        every: args.record_every,
+        scale: args.record_scale,
+    };
+    let in_context = |recorder: &Recorder, e| format!("{}: {}", recorder.path().display(), e);
+    let mut recorders = Vec::new();
+    if let Some(path) = &args.record {
+        let recorder = Recorder::new(options(path), spec.width, spec.height);
+        recorders.push(recorder.map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    if let Some(format) = args.stream {
+        let path = &args.stream_to;
+        let recorder = Recorder::stream(options(path), format, spec.width, spec.height);
+        recorders.push(recorder.map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    if !recorders.is_empty() {
+        let start = backend.download(&board);
+        for recorder in &mut recorders {
+            recorder.frame(&start).map_err(|e| in_context(recorder, e))?;
+        }
+    }
+
+    let name = args.name();
+    let save_frame = |board: &B::Board, step: u32| -> Result<(), Box<dyn Error>> {
+        let path = args.frames.join(format!("{}_{:06}.{}", name, step, args.format.extension()));
+        formats::save(&path, &backend.download(board)).map_err(|e| format!("{}: {}", path.display(), e).into())
+    };
+
+    let mut stats = match &args.stats {
+        Some(path) => Some(StatsWriter::create(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => None,
+    };
+    let mut write_stats = |board: &B::Board, step: u32| -> Result<(), Box<dyn Error>> {
+        if let (Some(writer), Some(path)) = (&mut stats, &args.stats) {
+            if step.is_multiple_of(args.stats_every) {
+                let result = writer.write(step.into(), &backend.stats(board));
+                result.map_err(|e| format!("{}: {}", path.display(), e))?;
+            }
+        }
+        Ok(())
+    };
+
+    let mut cycles = CycleDetector::new(args.cycle_every);
+    let conditions = args.stop_conditions(spec);
+    let steps = args.steps.or(conditions.steps).unwrap_or(100);
+    let watching = conditions.any();
+    let mut stop_check = StopCheck::new(conditions);
+
+    if args.every.is_some() {
+        fs::create_dir_all(&args.frames)?;
+        save_frame(&board, 0)?;
+    }
+    write_stats(&board, 0)?;
+    let mut stopped = stop_check.check(0, backend, &board);
+    let mut step = 0;
+    while stopped.is_none() && step < steps {
+        step += 1;
+        program.step(&mut board);
+        if args.every.is_some_and(|every| step % every == 0) {
+            save_frame(&board, step)?;
+        }
+        write_stats(&board, step)?;
+        let due: Vec<bool> = recorders.iter_mut().map(Recorder::step).collect();
+        if due.contains(&true) {
+            let current = backend.download(&board);
+            for (recorder, _) in recorders.iter_mut().zip(due).filter(|(_, due)| *due) {
+                recorder.frame(&current).map_err(|e| in_context(recorder, e))?;
+            }
+        }
+
+        stopped = stop_check.check(step.into(), backend, &board);
+        if stopped.is_some() {
+            break;
+        }
+        let Some(action) = args.cycle.filter(|_| cycles.due(step.into())) else { continue };
+        let Some(cycle) = cycles.check(step.into(), &backend.download(&board)) else { continue };
+        eprintln!("step {}: {}", step, cycle);
+        if action == CycleAction::Reseed {
+            // Same as the viewer: the start board if it's random, random red otherwise.
+            seed = random_seed();
+            eprintln!("seed: {}", seed);
+            let start = if args.init.uses_seed(spec) {
+                args.init.board(spec, seed)?
+            } else {
+                CpuBoard::from_values(&random_board_binary(spec.width, spec.height, seed))
+            };
+            board = backend.upload(&start);
+            // The stop check's own detector would see a cycle through the old boards.
+            cycles.reset();
+            stop_check.reset();
+        }
+    }
+    for recorder in recorders {
+        let path = recorder.path().to_owned();
+        let frames = recorder.finish().map_err(|e| format!("{}: {}", path.display(), e))?;
+        eprintln!("recorded {} frames to {}", frames, path.display());
+    }
+    match &stopped {
+        Some(stop) => eprintln!("stopped at step {}: {}", step, stop),
+        None if watching => eprintln!("ran {} steps, no stop condition held", step),
+        None => (),
+    }
+
+    let out = args
+        .out
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}_{}.{}", name, step, args.format.extension())));
+    formats::save(&out, &backend.download(&board)).map_err(|e| format!("{}: {}", out.display(), e))?;
+    Ok(stopped.map(|stop| stop.exit_code()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const LIFE: &str = "screen: [8, 8]\ntype: val\nedge: wrap\nkernel: [[1, 1, 1], [1, 0, 1], [1, 1, 1]]\nfun: >\n  \
+                        if ((prev == 1. && (x == 2. || x == 3.)) || (prev == 0. && x == 3.)) return 1.;\n  \
+                        return 0.;\nstop:\n  steps: 4\n";
+
+    /// Exit status of `run` on a small Life board with `options`.
+    fn exit_code(name: &str, options: &[&str]) -> i32 {
+        let dir = env::temp_dir().join(format!("gpu_convolution_run_{}_{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("life.yaml");
+        fs::write(&program, LIFE).unwrap();
+        let out = dir.join("out.pfm");
+        let mut args = vec![program.to_string_lossy().into_owned(), "--out".into(), out.to_string_lossy().into_owned()];
+        args.extend(options.iter().map(|option| option.to_string()));
+        let code = main(&args);
+        assert!(out.exists(), "{}: no board written", name);
+        fs::remove_dir_all(&dir).unwrap();
+        code
+    }
+
+    #[test]
+    fn exit_codes_tell_the_stop_condition() {
+        let random = ["--init", "random-binary", "--seed", "7"];
+        assert_eq!(exit_code("steps", &random), 0);
+        assert_eq!(exit_code("population", &[&random[..], &["--stop-population", "64"]].concat()), 3);
+        assert_eq!(exit_code("mean", &[&random[..], &["--stop-mean", "r<2"]].concat()), 4);
+        assert_eq!(exit_code("static", &["--init", "empty", "--stop-static"]), 5);
+        assert_eq!(exit_code("cycle", &["--init", "empty", "--stop-cycle"]), 6);
+        assert_eq!(exit_code("time", &[&random[..], &["--stop-seconds", "1e-9"]].concat()), 7);
+        // Without a condition holding, the run ends after `stop: steps:`.
+        assert_eq!(exit_code("none", &[&random[..], &["--stop-population", "0", "--stop-static"]].concat()), 0);
+    }
+
+    #[test]
+    fn stop_seconds_must_be_finite() {
+        let parse = |seconds: &str| RunArgs::parse(&["life.yaml".into(), "--stop-seconds".into(), seconds.into()]);
+        assert_eq!(parse("1e30").unwrap().stop.seconds, Some(1e30));
+        for bad in ["0", "-1", "inf", "NaN", "soon"] {
+            let error = parse(bad).err().unwrap();
+            assert_eq!(error, format!("`--stop-seconds` must be a positive number, got `{}`", bad));
+        }
+    }
+}
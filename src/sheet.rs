@@ -0,0 +1,220 @@
+//! Contact sheets: a grid of board images, each labelled below with a few
+//! lines of text in a built-in 3x5 pixel font, e.g. for parameter sweeps.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::formats;
+
+/// Pixels between tiles and around the sheet.
+const GAP: u32 = 4;
+/// Glyphs are drawn `GLYPH_SCALE` times their size, 6x10 pixels.
+const GLYPH_SCALE: u32 = 2;
+const ADVANCE: u32 = 4 * GLYPH_SCALE;
+const LINE_HEIGHT: u32 = 6 * GLYPH_SCALE;
+const BACKGROUND: u8 = 32;
+const TEXT: u8 = 224;
+
+pub struct ContactSheet {
+    columns: u32,
+    tile: (u32, u32),
+    label_lines: u32,
+    width: u32,
+    height: u32,
+    /// RGB, rows top-down.
+    pixels: Vec<u8>,
+}
+
+impl ContactSheet {
+    /// A sheet for `tiles` tiles of `tile_width` x `tile_height` pixels,
+    /// `columns` per row, with room for `label_lines` lines of text under each.
+    pub fn new(tiles: u32, columns: u32, tile_width: u32, tile_height: u32, label_lines: u32) -> Self {
+        let columns = columns.clamp(1, tiles.max(1));
+        let rows = tiles.div_ceil(columns);
+        let width = GAP + columns * (tile_width + GAP);
+        let height = GAP + rows * (tile_height + label_lines * LINE_HEIGHT + GAP);
+        Self {
+            columns,
+            tile: (tile_width, tile_height),
+            label_lines,
+            width,
+            height,
+            pixels: vec![BACKGROUND; (width * height * 3) as usize],
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Column and row of tile `index`.
+    pub fn position(&self, index: u32) -> (u32, u32) {
+        (index % self.columns, index / self.columns)
+    }
+
+    /// Copies tile `index`, RGB rows top-down as from `formats::rgb8`, and
+    /// writes `label` under it. Text wider than the tile is cut off.
+    pub fn place(&mut self, index: u32, tile: &[u8], label: &[String]) {
+        let (column, row) = self.position(index);
+        let (tw, th) = self.tile;
+        let x0 = GAP + column * (tw + GAP);
+        let y0 = GAP + row * (th + self.label_lines * LINE_HEIGHT + GAP);
+        for y in 0..th {
+            let from = (y * tw * 3) as usize;
+            let to = (((y0 + y) * self.width + x0) * 3) as usize;
+            self.pixels[to..to + (tw * 3) as usize].copy_from_slice(&tile[from..from + (tw * 3) as usize]);
+        }
+        for (i, line) in label.iter().take(self.label_lines as usize).enumerate() {
+            let y = y0 + th + GLYPH_SCALE + i as u32 * LINE_HEIGHT;
+            for (j, c) in line.chars().enumerate() {
+                let x = x0 + j as u32 * ADVANCE;
+                if x + 3 * GLYPH_SCALE > x0 + tw {
+                    break;
+                }
+                self.glyph(x, y, c);
+            }
+        }
+    }
+
+    fn glyph(&mut self, x: u32, y: u32, c: char) {
+        for (dy, bits) in glyph(c).iter().enumerate() {
+            for dx in 0..3 {
+                if bits & (0b100 >> dx) == 0 {
+                    continue;
+                }
+                for sy in 0..GLYPH_SCALE {
+                    for sx in 0..GLYPH_SCALE {
+                        let px = x + dx * GLYPH_SCALE + sx;
+                        let py = y + dy as u32 * GLYPH_SCALE + sy;
+                        let i = ((py * self.width + px) * 3) as usize;
+                        self.pixels[i..i + 3].fill(TEXT);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Number of characters that fit on a label line.
+    pub fn label_width(&self) -> usize {
+        ((self.tile.0 + ADVANCE - 3 * GLYPH_SCALE) / ADVANCE) as usize
+    }
+
+    pub fn write_png(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        formats::write_rgb8_png(&mut file, self.width, self.height, &self.pixels)?;
+        file.flush()
+    }
+}
+
+/// Rows of a 3x5 glyph, top first, the high bit being the left pixel. Letters
+/// are all drawn in capitals.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_lowercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        ' ' => [0; 5],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(sheet: &ContactSheet, x: u32, y: u32) -> [u8; 3] {
+        let i = ((y * sheet.width + x) * 3) as usize;
+        [sheet.pixels[i], sheet.pixels[i + 1], sheet.pixels[i + 2]]
+    }
+
+    #[test]
+    fn grid() {
+        let sheet = ContactSheet::new(5, 2, 10, 6, 1);
+        // Two columns, three rows of a tile, a label line and a gap.
+        assert_eq!(sheet.size(), (GAP + 2 * (10 + GAP), GAP + 3 * (6 + LINE_HEIGHT + GAP)));
+        assert_eq!([0, 1, 2, 4].map(|i| sheet.position(i)), [(0, 0), (1, 0), (0, 1), (0, 2)]);
+        // More columns than tiles shrink to one row.
+        assert_eq!(ContactSheet::new(3, 8, 10, 6, 0).size(), (GAP + 3 * (10 + GAP), GAP + 6 + GAP));
+        // Glyphs are 6 pixels wide and 8 apart.
+        assert_eq!(ContactSheet::new(1, 1, 29, 6, 1).label_width(), 3);
+        assert_eq!(ContactSheet::new(1, 1, 30, 6, 1).label_width(), 4);
+    }
+
+    #[test]
+    fn tiles_and_labels() {
+        let mut sheet = ContactSheet::new(4, 2, 16, 2, 1);
+        // Top row red, bottom row blue.
+        let tile: Vec<u8> = [[255, 0, 0]; 16].into_iter().chain([[0, 0, 255]; 16]).flatten().collect();
+        sheet.place(3, &tile, &["1=1".to_string()]);
+        let (x0, y0) = (GAP + 16 + GAP, GAP + 2 + LINE_HEIGHT + GAP);
+        assert_eq!(pixel(&sheet, x0, y0), [255, 0, 0]);
+        assert_eq!(pixel(&sheet, x0 + 15, y0 + 1), [0, 0, 255]);
+        assert_eq!(pixel(&sheet, x0 - 1, y0), [BACKGROUND; 3]);
+        assert_eq!(pixel(&sheet, x0 + 16, y0), [BACKGROUND; 3]);
+        // Other tiles are untouched.
+        assert_eq!(pixel(&sheet, GAP, GAP), [BACKGROUND; 3]);
+
+        // The label starts a glyph pixel under the tile: `1` has the middle
+        // pixel of its top row set, `=` its top row blank.
+        let text_y = y0 + 2 + GLYPH_SCALE;
+        assert_eq!(pixel(&sheet, x0 + GLYPH_SCALE, text_y), [TEXT; 3]);
+        assert_eq!(pixel(&sheet, x0, text_y), [BACKGROUND; 3]);
+        assert_eq!(pixel(&sheet, x0 + ADVANCE, text_y), [BACKGROUND; 3]);
+        assert_eq!(pixel(&sheet, x0 + ADVANCE, text_y + GLYPH_SCALE), [TEXT; 3]);
+        // Only two characters fit under a 16 pixel tile.
+        let third = (x0 + 2 * ADVANCE..x0 + 16).flat_map(|x| (text_y..text_y + 5 * GLYPH_SCALE).map(move |y| (x, y)));
+        assert!(third.into_iter().all(|(x, y)| pixel(&sheet, x, y) == [BACKGROUND; 3]));
+    }
+
+    #[test]
+    fn glyphs() {
+        assert_eq!(glyph('A'), glyph('a'));
+        assert_eq!(glyph(' '), [0; 5]);
+        // Unknown characters are a question mark.
+        assert_eq!(glyph('?'), glyph('%'));
+        assert_ne!(glyph('?'), glyph('7'));
+    }
+}
@@ -0,0 +1,251 @@
+//! Save files holding everything needed to continue a simulation later or
+//! elsewhere.
+//!
+//! A state file starts with the line `GPUCONV-STATE 2`, followed by a line with
+//! the length in bytes of a YAML header, the header itself and then the board
+//! as little-endian `f32` RGBA values, rows bottom to top as in `CpuBoard`.
+//! The header holds the program's YAML source, so the file can be shared on
+//! its own:
+//!
+//! ```yaml
+//! ---
+//! name: gol
+//! width: 1920
+//! height: 1080
+//! step: 1200
+//! speed: 32
+//! seed: "1234"
+//! params: {}
+//! stage: 0
+//! program: "screen: [1920, 1080]\n..."
+//! ```
+//!
+//! Version 1 files, which stored the seed as a YAML integer and had no
+//! `stage`, still load.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+use crate::cpu::CpuBoard;
+
+const MAGIC: &str = "GPUCONV-STATE 2";
+const MAGIC_V1: &str = "GPUCONV-STATE 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    /// Name used for files written from the simulation, normally the program
+    /// file's stem.
+    pub name: String,
+    /// YAML source of the program.
+    pub program: String,
+    pub board: CpuBoard,
+    /// Steps since the board was last filled.
+    pub step: u64,
+    /// Speed setting of the viewer, see the number keys.
+    pub speed: u32,
+    /// Seed of the next random fill.
+    pub seed: u64,
+    /// Values of runtime parameters, by name.
+    pub params: Vec<(String, f32)>,
+    /// Document of a playlist the program is at, 0 for a single program.
+    pub stage: usize,
+}
+
+impl State {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut params = Hash::new();
+        for (name, value) in &self.params {
+            params.insert(Yaml::String(name.clone()), Yaml::Real(format!("{:?}", value)));
+        }
+        let mut header = Hash::new();
+        let mut insert = |key: &str, value| header.insert(Yaml::String(key.to_string()), value);
+        insert("name", Yaml::String(self.name.clone()));
+        insert("width", Yaml::Integer(self.board.width.into()));
+        insert("height", Yaml::Integer(self.board.height.into()));
+        insert("step", Yaml::Integer(self.step as i64));
+        insert("speed", Yaml::Integer(self.speed.into()));
+        // A string, YAML integers are `i64` and the seed can be any `u64`.
+        insert("seed", Yaml::String(self.seed.to_string()));
+        insert("params", Yaml::Hash(params));
+        insert("stage", Yaml::Integer(self.stage as i64));
+        insert("program", Yaml::String(self.program.clone()));
+
+        let mut yaml = String::new();
+        YamlEmitter::new(&mut yaml)
+            .dump(&Yaml::Hash(header))
+            .map_err(|e| invalid(&format!("{:?}", e)))?;
+        yaml.push('\n');
+        write!(w, "{}\n{}\n{}", MAGIC, yaml.len(), yaml)?;
+        for c in &self.board.data {
+            w.write_all(&c.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read(r: &mut impl BufRead) -> io::Result<Self> {
+        let mut magic = vec![0; MAGIC.len() + 1];
+        r.read_exact(&mut magic).map_err(|_| invalid("not a state file"))?;
+        let v1 = match magic.strip_suffix(b"\n") {
+            Some(line) if line == MAGIC.as_bytes() => false,
+            Some(line) if line == MAGIC_V1.as_bytes() => true,
+            Some(line) if line.starts_with(b"GPUCONV-STATE ") => {
+                return Err(invalid("state file from a newer version"));
+            }
+            _ => return Err(invalid("not a state file")),
+        };
+        let mut line = String::new();
+        r.read_line(&mut line)?;
+        let length: usize = line.trim_end().parse().map_err(|_| invalid("bad header length"))?;
+        let mut yaml = vec![0; length];
+        r.read_exact(&mut yaml)?;
+        let yaml = String::from_utf8(yaml).map_err(|_| invalid("header is not UTF-8"))?;
+        let docs = YamlLoader::load_from_str(&yaml).map_err(|e| invalid(&e.to_string()))?;
+        let header = docs.first().ok_or_else(|| invalid("empty header"))?;
+
+        let integer = |key: &str| {
+            header[key]
+                .as_i64()
+                .filter(|n| *n >= 0)
+                .ok_or_else(|| invalid(&format!("missing or bad `{}` in header", key)))
+        };
+        let string = |key: &str| {
+            header[key]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(&format!("missing or bad `{}` in header", key)))
+        };
+        let (width, height) = (integer("width")? as u32, integer("height")? as u32);
+        let mut params = Vec::new();
+        if let Some(hash) = header["params"].as_hash() {
+            for (name, value) in hash {
+                let name = name.as_str().ok_or_else(|| invalid("bad parameter name in header"))?;
+                let value = value
+                    .as_f64()
+                    .or_else(|| value.as_i64().map(|n| n as f64))
+                    .ok_or_else(|| invalid(&format!("bad value of parameter `{}` in header", name)))?;
+                params.push((name.to_string(), value as f32));
+            }
+        }
+
+        let mut board = CpuBoard::new(width, height);
+        let mut bytes = vec![0; board.data.len() * 4];
+        r.read_exact(&mut bytes)?;
+        for (c, b) in board.data.iter_mut().zip(bytes.chunks_exact(4)) {
+            *c = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+        }
+
+        Ok(Self {
+            name: string("name")?,
+            program: string("program")?,
+            board,
+            step: integer("step")? as u64,
+            speed: integer("speed")? as u32,
+            seed: if v1 {
+                // Written `as i64`, seeds above 2^63 come back negative.
+                header["seed"].as_i64().map(|seed| seed as u64)
+            } else {
+                header["seed"].as_str().and_then(|seed| seed.parse().ok())
+            }
+            .ok_or_else(|| invalid("missing or bad `seed` in header"))?,
+            params,
+            stage: if v1 { header["stage"].as_i64().unwrap_or(0) as usize } else { integer("stage")? as usize },
+        })
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(seed: u64) -> State {
+        State {
+            name: "gol".to_string(),
+            program: "screen: [3, 2]\ntype: val\nfun: return x;\n---\nscreen: [3, 2]\n".to_string(),
+            board: CpuBoard {
+                width: 3,
+                height: 2,
+                data: (0..24).map(|i| i as f32 * 0.37 - 2. + 1e-7).collect(),
+            },
+            step: 1200,
+            speed: 32,
+            seed,
+            params: vec![("decay".to_string(), 0.999), ("k".to_string(), -3.)],
+            stage: 1,
+        }
+    }
+
+    #[test]
+    fn write_then_read_gives_the_same_state() {
+        for seed in [0, 1234, 1 << 63, u64::MAX] {
+            let mut bytes = Vec::new();
+            state(seed).write(&mut bytes).unwrap();
+            assert_eq!(State::read(&mut &bytes[..]).unwrap(), state(seed));
+        }
+    }
+
+    #[test]
+    fn truncated_or_foreign_files_are_rejected() {
+        let mut bytes = Vec::new();
+        state(1).write(&mut bytes).unwrap();
+        assert!(State::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        assert!(State::read(&mut &b"GPUCONV-STATE 2\n"[..]).is_err());
+        let newer = State::read(&mut &b"GPUCONV-STATE 3\n"[..]).unwrap_err();
+        assert_eq!(newer.to_string(), "state file from a newer version");
+        assert!(State::read(&mut &b"P6\n3 2\n255\n"[..]).is_err());
+    }
+
+    /// A file as saved by version 1, from before playlists.
+    fn v1_file(seed: i64) -> Vec<u8> {
+        let header = format!(
+            "---\nname: gol\nwidth: 1\nheight: 1\nstep: 7\nspeed: 2\nseed: {}\nparams:\n  k: 0.5\n\
+             program: \"screen: [1, 1]\"\n",
+            seed
+        );
+        let mut bytes = format!("GPUCONV-STATE 1\n{}\n{}", header.len(), header).into_bytes();
+        for c in [0.25f32, 0.5, 0.75, 1.] {
+            bytes.extend(c.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn version_1_files_still_load() {
+        let state = State::read(&mut &v1_file(1234)[..]).unwrap();
+        let expected = State {
+            name: "gol".to_string(),
+            program: "screen: [1, 1]".to_string(),
+            board: CpuBoard { width: 1, height: 1, data: vec![0.25, 0.5, 0.75, 1.] },
+            step: 7,
+            speed: 2,
+            seed: 1234,
+            params: vec![("k".to_string(), 0.5)],
+            stage: 0,
+        };
+        assert_eq!(state, expected);
+        assert_eq!(State::read(&mut &v1_file(-1)[..]).unwrap().seed, u64::MAX);
+
+        // Saving writes the current version.
+        let mut bytes = Vec::new();
+        state.write(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"GPUCONV-STATE 2\n"));
+    }
+}
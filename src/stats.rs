@@ -0,0 +1,308 @@
+//! Statistics of a board: the number of live cells (red at least 0.5, as in
+//! the Life-like programs) and the mean, minimum, maximum and variance of the
+//! red, green and blue channels.
+//!
+//! `Backend::stats` computes them, on the GPU for `GlBackend`. `StatsWriter`
+//! appends them to a CSV or JSON-lines file, picked by extension.
+
+use std::{
+    fmt::{self, Write as _},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::cpu::CpuBoard;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    pub mean: f64,
+    pub min: f32,
+    pub max: f32,
+    /// Population variance.
+    pub variance: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardStats {
+    pub cells: u64,
+    pub live: u64,
+    /// Red, green and blue.
+    pub channels: [ChannelStats; 3],
+}
+
+const CHANNELS: [&str; 3] = ["r", "g", "b"];
+
+impl BoardStats {
+    pub fn of(board: &CpuBoard) -> Self {
+        let mut sums = Sums::default();
+        for pixel in board.data.chunks_exact(4) {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+            let live = if r >= 0.5 { 1. } else { 0. };
+            sums.add([
+                [r, g, b, live],
+                [r * r, g * g, b * b, 1.],
+                [r, g, b, 0.],
+                [r, g, b, 0.],
+            ]);
+        }
+        sums.stats()
+    }
+
+    /// Combines partial results of the reduction shader, four vectors per
+    /// work group: sums with the live count in `w`, sums of squares with the
+    /// cell count in `w`, minimums and maximums.
+    pub(crate) fn from_partials(partials: &[[f32; 4]]) -> Self {
+        let mut sums = Sums::default();
+        for group in partials.chunks_exact(4) {
+            sums.add([group[0], group[1], group[2], group[3]]);
+        }
+        sums.stats()
+    }
+
+    /// One line for printing, e.g. on request in the viewer.
+    pub fn summary(&self) -> String {
+        let mut line = format!("live {}", self.live);
+        for (name, c) in CHANNELS.iter().zip(&self.channels) {
+            write!(
+                line,
+                ", {} mean {:.4} min {:.4} max {:.4} var {:.4}",
+                name, c.mean, c.min, c.max, c.variance
+            )
+            .unwrap();
+        }
+        line
+    }
+}
+
+/// Running totals in `f64`, so large boards don't lose precision.
+struct Sums {
+    sum: [f64; 3],
+    squares: [f64; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+    live: f64,
+    cells: f64,
+}
+
+impl Default for Sums {
+    fn default() -> Self {
+        Self {
+            sum: [0.; 3],
+            squares: [0.; 3],
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+            live: 0.,
+            cells: 0.,
+        }
+    }
+}
+
+impl Sums {
+    fn add(&mut self, [sum, squares, min, max]: [[f32; 4]; 4]) {
+        for c in 0..3 {
+            self.sum[c] += sum[c] as f64;
+            self.squares[c] += squares[c] as f64;
+            self.min[c] = self.min[c].min(min[c]);
+            self.max[c] = self.max[c].max(max[c]);
+        }
+        self.live += sum[3] as f64;
+        self.cells += squares[3] as f64;
+    }
+
+    fn stats(&self) -> BoardStats {
+        let n = self.cells.max(1.);
+        let channels = [0, 1, 2].map(|c| {
+            let mean = self.sum[c] / n;
+            ChannelStats {
+                mean,
+                min: self.min[c],
+                max: self.max[c],
+                variance: (self.squares[c] / n - mean * mean).max(0.),
+            }
+        });
+        BoardStats {
+            cells: self.cells as u64,
+            live: self.live as u64,
+            channels,
+        }
+    }
+}
+
+/// Appends statistics to a `.csv` file (with a header row) or, for any other
+/// extension, a JSON-lines file with one object per line.
+pub struct StatsWriter {
+    file: BufWriter<File>,
+    csv: bool,
+}
+
+impl StatsWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let csv = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+        let mut file = BufWriter::new(File::create(path)?);
+        if csv {
+            let mut header = "step,live".to_string();
+            for name in CHANNELS {
+                write!(header, ",{0}_mean,{0}_min,{0}_max,{0}_variance", name).unwrap();
+            }
+            writeln!(file, "{}", header)?;
+        }
+        Ok(Self { file, csv })
+    }
+
+    pub fn write(&mut self, step: u64, stats: &BoardStats) -> io::Result<()> {
+        let mut line = String::new();
+        if self.csv {
+            write!(line, "{},{}", step, stats.live).unwrap();
+            for c in &stats.channels {
+                write!(line, ",{},{},{},{}", c.mean, c.min, c.max, c.variance).unwrap();
+            }
+        } else {
+            write!(line, "{{\"step\":{},\"live\":{}", step, stats.live).unwrap();
+            for (name, c) in CHANNELS.iter().zip(&stats.channels) {
+                write!(
+                    line,
+                    ",\"{}\":{{\"mean\":{},\"min\":{},\"max\":{},\"variance\":{}}}",
+                    name,
+                    json_number(c.mean),
+                    json_number(c.min),
+                    json_number(c.max),
+                    json_number(c.variance)
+                )
+                .unwrap();
+            }
+            line.push('}');
+        }
+        writeln!(self.file, "{}", line)?;
+        // Flushed every time so the file can be followed while a run goes on.
+        self.file.flush()
+    }
+}
+
+/// JSON has no infinity or NaN, those become `null`.
+fn json_number<T: Into<f64> + fmt::Display + Copy>(x: T) -> String {
+    if x.into().is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::program::shader::WORK_GROUP_SIZE;
+
+    /// Red 0, 0.5, 1 and 0.25, green 1 everywhere and blue 2 in one cell.
+    fn small_board() -> CpuBoard {
+        CpuBoard {
+            width: 2,
+            height: 2,
+            data: vec![0., 1., 0., 1., 0.5, 1., 0., 1., 1., 1., 0., 1., 0.25, 1., 2., 1.],
+        }
+    }
+
+    /// What the stats shader writes for `board`, computed the same way on the
+    /// CPU: per work group, cells outside the board add nothing.
+    fn partials(board: &CpuBoard) -> Vec<[f32; 4]> {
+        let (gw, gh) = WORK_GROUP_SIZE;
+        let mut partials = Vec::new();
+        for group_y in 0..board.height.div_ceil(gh) {
+            for group_x in 0..board.width.div_ceil(gw) {
+                let mut group = [[0.; 4], [0.; 4], [f32::INFINITY; 4], [f32::NEG_INFINITY; 4]];
+                for y in group_y * gh..((group_y + 1) * gh).min(board.height) {
+                    for x in group_x * gw..((group_x + 1) * gw).min(board.width) {
+                        let p = board.get(x, y);
+                        for c in 0..4 {
+                            group[0][c] += if c == 3 { (p[0] >= 0.5) as u8 as f32 } else { p[c] };
+                            group[1][c] += if c == 3 { 1. } else { p[c] * p[c] };
+                            group[2][c] = group[2][c].min(p[c]);
+                            group[3][c] = group[3][c].max(p[c]);
+                        }
+                    }
+                }
+                partials.extend(group);
+            }
+        }
+        partials
+    }
+
+    #[test]
+    fn stats_of_a_known_board() {
+        let stats = BoardStats::of(&small_board());
+        assert_eq!((stats.cells, stats.live), (4, 2));
+        let [r, g, b] = stats.channels;
+        assert_eq!(r, ChannelStats { mean: 0.4375, min: 0., max: 1., variance: 0.13671875 });
+        assert_eq!(g, ChannelStats { mean: 1., min: 1., max: 1., variance: 0. });
+        assert_eq!(b, ChannelStats { mean: 0.5, min: 0., max: 2., variance: 0.75 });
+        assert_eq!(
+            stats.summary(),
+            "live 2, r mean 0.4375 min 0.0000 max 1.0000 var 0.1367, g mean 1.0000 min 1.0000 max 1.0000 var 0.0000, \
+             b mean 0.5000 min 0.0000 max 2.0000 var 0.7500"
+        );
+    }
+
+    #[test]
+    fn partials_give_the_same_stats() {
+        assert_eq!(BoardStats::from_partials(&partials(&small_board())), BoardStats::of(&small_board()));
+
+        // Several work groups, the last row and column of them partly outside the board.
+        let (width, height) = (WORK_GROUP_SIZE.0 * 2 + 3, WORK_GROUP_SIZE.1 + 5);
+        let mut board = CpuBoard::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let v = ((x * 7 + y * 13) % 10) as f32 / 9.;
+                board.set(x, y, [v, 1. - v, v * v - 0.5, 1.]);
+            }
+        }
+        let (cpu, gpu) = (BoardStats::of(&board), BoardStats::from_partials(&partials(&board)));
+        assert_eq!((gpu.cells, gpu.live), (cpu.cells, cpu.live));
+        for (a, b) in cpu.channels.iter().zip(&gpu.channels) {
+            assert_eq!((a.min, a.max), (b.min, b.max));
+            assert!((a.mean - b.mean).abs() < 1e-6 && (a.variance - b.variance).abs() < 1e-6, "{:?} {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn empty_board() {
+        let stats = BoardStats::from_partials(&[]);
+        assert_eq!((stats.cells, stats.live), (0, 0));
+        assert_eq!(stats.channels[0].mean, 0.);
+        assert_eq!((stats.channels[0].min, stats.channels[0].max), (f32::INFINITY, f32::NEG_INFINITY));
+    }
+
+    fn written(extension: &str) -> String {
+        let path = env::temp_dir().join(format!("gpu_convolution_stats_{}.{}", std::process::id(), extension));
+        let mut writer = StatsWriter::create(&path).unwrap();
+        writer.write(0, &BoardStats::of(&small_board())).unwrap();
+        writer.write(10, &BoardStats::from_partials(&[])).unwrap();
+        drop(writer);
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        text
+    }
+
+    #[test]
+    fn csv() {
+        let expected = "step,live,r_mean,r_min,r_max,r_variance,g_mean,g_min,g_max,g_variance,\
+                        b_mean,b_min,b_max,b_variance\n\
+                        0,2,0.4375,0,1,0.13671875,1,1,1,0,0.5,0,2,0.75\n\
+                        10,0,0,inf,-inf,0,0,inf,-inf,0,0,inf,-inf,0\n";
+        assert_eq!(written("CSV"), expected);
+    }
+
+    #[test]
+    fn json_lines() {
+        let expected = "{\"step\":0,\"live\":2,\
+                        \"r\":{\"mean\":0.4375,\"min\":0,\"max\":1,\"variance\":0.13671875},\
+                        \"g\":{\"mean\":1,\"min\":1,\"max\":1,\"variance\":0},\
+                        \"b\":{\"mean\":0.5,\"min\":0,\"max\":2,\"variance\":0.75}}\n\
+                        {\"step\":10,\"live\":0,\
+                        \"r\":{\"mean\":0,\"min\":null,\"max\":null,\"variance\":0},\
+                        \"g\":{\"mean\":0,\"min\":null,\"max\":null,\"variance\":0},\
+                        \"b\":{\"mean\":0,\"min\":null,\"max\":null,\"variance\":0}}\n";
+        assert_eq!(written("jsonl"), expected);
+    }
+}
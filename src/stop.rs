@@ -0,0 +1,442 @@
+//! Conditions that end a headless run before its step count runs out, from
+//! the `stop:` section of a program file or the `--stop-*` options of `run`:
+//!
+//! ```yaml
+//! stop:
+//!   steps: 100000       # run at most this many steps instead of 100
+//!   population: 0       # live cells (red at least 0.5) at or below 0
+//!   mean: r > 0.8       # a channel mean crosses a threshold, or a list of them
+//!   static: true        # the board stopped changing
+//!   cycle: true         # the board repeats, static or not
+//!   seconds: 600        # wall-clock budget
+//!   every: 10           # check the board every 10th step (default 1)
+//! ```
+//!
+//! The first condition that holds ends the run. Board conditions are checked
+//! on the initial board and then every `every` steps, so with `every` above 1
+//! a board repeating with a period that divides it counts as static.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::Instant,
+};
+
+use crate::{
+    backend::Backend,
+    cycle::{Cycle, CycleDetector},
+    program::{spec::Node, ProgramError},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StopConditions {
+    /// Step count when `--steps` isn't given.
+    pub steps: Option<u32>,
+    /// Stop once at most this many cells are live.
+    pub population: Option<u64>,
+    pub mean: Vec<MeanThreshold>,
+    pub static_board: bool,
+    pub cycle: bool,
+    pub seconds: Option<f32>,
+    /// Board conditions are checked every `every`th step.
+    pub every: u32,
+}
+
+impl Default for StopConditions {
+    fn default() -> Self {
+        Self {
+            steps: None,
+            population: None,
+            mean: Vec::new(),
+            static_board: false,
+            cycle: false,
+            seconds: None,
+            every: 1,
+        }
+    }
+}
+
+impl StopConditions {
+    /// Whether any condition besides the step count is set.
+    pub fn any(&self) -> bool {
+        self.population.is_some() || !self.mean.is_empty() || self.static_board || self.cycle || self.seconds.is_some()
+    }
+}
+
+/// A channel mean going above or below a value, written `r > 0.8` or
+/// `g < 0.1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeanThreshold {
+    /// 0, 1 or 2 for red, green or blue.
+    pub channel: usize,
+    pub above: bool,
+    pub value: f32,
+}
+
+impl MeanThreshold {
+    fn holds(&self, mean: f64) -> bool {
+        if self.above {
+            mean > self.value as f64
+        } else {
+            mean < self.value as f64
+        }
+    }
+}
+
+impl FromStr for MeanThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid mean threshold `{}`, expected e.g. `r > 0.8` or `g < 0.1`", s);
+        let (channel, value, above) = match (s.split_once('>'), s.split_once('<')) {
+            (Some((channel, value)), None) => (channel, value, true),
+            (None, Some((channel, value))) => (channel, value, false),
+            _ => return Err(error()),
+        };
+        let channel = match channel.trim() {
+            "r" => 0,
+            "g" => 1,
+            "b" => 2,
+            _ => return Err(error()),
+        };
+        let value = value.trim().parse().map_err(|_| error())?;
+        Ok(Self { channel, above, value })
+    }
+}
+
+impl fmt::Display for MeanThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let channel = ["r", "g", "b"][self.channel];
+        write!(f, "{} {} {}", channel, if self.above { '>' } else { '<' }, self.value)
+    }
+}
+
+/// The condition that ended a run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Population { live: u64 },
+    Mean { threshold: MeanThreshold, mean: f64 },
+    Static { since: u64 },
+    Cycle(Cycle),
+    Time { seconds: f32 },
+}
+
+impl Stop {
+    /// Exit status of `run`, 0 and 1 being taken by a finished run and an
+    /// error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Stop::Population { .. } => 3,
+            Stop::Mean { .. } => 4,
+            Stop::Static { .. } => 5,
+            Stop::Cycle(_) => 6,
+            Stop::Time { .. } => 7,
+        }
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Population { live } => write!(f, "population {}", live),
+            Stop::Mean { threshold, mean } => write!(f, "mean {} (is {:.4})", threshold, mean),
+            Stop::Static { since } => write!(f, "static since step {}", since),
+            Stop::Cycle(cycle) => write!(f, "{}", cycle),
+            Stop::Time { seconds } => write!(f, "time budget of {}s used up", seconds),
+        }
+    }
+}
+
+/// Evaluates `StopConditions` while a simulation steps.
+pub struct StopCheck {
+    conditions: StopConditions,
+    started: Instant,
+    cycles: CycleDetector,
+}
+
+impl StopCheck {
+    /// Starts the clock for `seconds`.
+    pub fn new(conditions: StopConditions) -> Self {
+        Self {
+            cycles: CycleDetector::new(conditions.every),
+            conditions,
+            started: Instant::now(),
+        }
+    }
+
+    /// Forgets the boards seen so far, for when the board was replaced. The
+    /// time budget keeps running.
+    pub fn reset(&mut self) {
+        self.cycles.reset();
+    }
+
+    /// Checks the board after `step`, 0 being the initial board.
+    pub fn check<B: Backend>(&mut self, step: u64, backend: &B, board: &B::Board) -> Option<Stop> {
+        let conditions = &self.conditions;
+        if let Some(seconds) = conditions.seconds {
+            // Not a `Duration`, which can't hold every finite budget.
+            if self.started.elapsed().as_secs_f64() >= seconds.into() {
+                return Some(Stop::Time { seconds });
+            }
+        }
+        if !step.is_multiple_of(conditions.every.into()) {
+            return None;
+        }
+
+        if conditions.population.is_some() || !conditions.mean.is_empty() {
+            let stats = backend.stats(board);
+            if conditions.population.is_some_and(|population| stats.live <= population) {
+                return Some(Stop::Population { live: stats.live });
+            }
+            for threshold in &conditions.mean {
+                let mean = stats.channels[threshold.channel].mean;
+                if threshold.holds(mean) {
+                    return Some(Stop::Mean {
+                        threshold: *threshold,
+                        mean,
+                    });
+                }
+            }
+        }
+
+        if (conditions.static_board || conditions.cycle) && self.cycles.due(step) {
+            let cycle = self.cycles.check(step, &backend.download(board))?;
+            if conditions.cycle {
+                return Some(Stop::Cycle(cycle));
+            }
+            // Checked boards only repeat after `every` steps if they stopped changing.
+            if cycle.period == conditions.every.into() {
+                return Some(Stop::Static { since: cycle.since });
+            }
+        }
+        None
+    }
+}
+
+/// Reads the `stop` section, the default (no conditions) when absent.
+pub(crate) fn parse(node: Node) -> Result<StopConditions, ProgramError> {
+    let mut stop = StopConditions::default();
+    if node.yaml.is_badvalue() {
+        return Ok(stop);
+    }
+    let hash = node.yaml.as_hash().ok_or_else(|| node.wrong_type("a map of stop conditions"))?;
+    for key in hash.keys() {
+        let name = key.as_str().unwrap_or_default();
+        let value = node.get(name);
+        match name {
+            "steps" => stop.steps = Some(value.u32()?),
+            "population" => stop.population = Some(value.u64()?),
+            "mean" => {
+                stop.mean = match value.yaml.as_vec() {
+                    Some(list) => (0..list.len()).map(|i| mean(&value.index(i))).collect::<Result<_, _>>()?,
+                    None => vec![mean(&value)?],
+                }
+            }
+            "static" => stop.static_board = value.bool()?,
+            "cycle" => stop.cycle = value.bool()?,
+            "seconds" => {
+                let seconds = value.f32()?;
+                if !(seconds.is_finite() && seconds > 0.) {
+                    return Err(value.wrong_type("a positive number"));
+                }
+                stop.seconds = Some(seconds);
+            }
+            "every" => stop.every = value.u32()?,
+            _ => {
+                return Err(value.wrong_type(
+                    "a stop condition: `steps`, `population`, `mean`, `static`, `cycle`, `seconds` or `every`",
+                ))
+            }
+        }
+    }
+    Ok(stop)
+}
+
+fn mean(node: &Node) -> Result<MeanThreshold, ProgramError> {
+    node.str()?
+        .parse()
+        .map_err(|_| node.wrong_type("a mean threshold, e.g. `r > 0.8` or `g < 0.1`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::CpuBackend, cpu::CpuBoard, program::ProgramSpec};
+
+    fn board(value: f32) -> CpuBoard {
+        CpuBoard::from_values(&[vec![value, 0.], vec![0., 0.]])
+    }
+
+    fn stop_section(section: &str) -> Result<StopConditions, ProgramError> {
+        let src = format!("screen: [2, 2]\ntype: val\nkernel: [[1]]\nfun: return x;\nstop:\n{}", section);
+        src.parse::<ProgramSpec>().map(|spec| spec.stop)
+    }
+
+    /// Checks `boards` in turn as steps 0, 1, ..., returning the step and
+    /// condition that stopped them.
+    fn run(conditions: StopConditions, boards: &[CpuBoard]) -> Option<(u64, Stop)> {
+        let backend = CpuBackend::default();
+        let mut check = StopCheck::new(conditions);
+        (0..).zip(boards).find_map(|(step, board)| check.check(step, &backend, board).map(|stop| (step, stop)))
+    }
+
+    fn threshold(s: &str) -> MeanThreshold {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_section() {
+        let section = "  steps: 500\n  population: 0\n  mean: r > 0.8\n  static: true\n  cycle: false\n  \
+                       seconds: 1.5\n  every: 10\n";
+        let expected = StopConditions {
+            steps: Some(500),
+            population: Some(0),
+            mean: vec![threshold("r > 0.8")],
+            static_board: true,
+            cycle: false,
+            seconds: Some(1.5),
+            every: 10,
+        };
+        assert_eq!(stop_section(section), Ok(expected));
+        let means = stop_section("  mean: [r > 0.8, 'b < 0.1']\n").unwrap().mean;
+        assert_eq!(means, [threshold("r > 0.8"), threshold("b < 0.1")]);
+        assert!(!stop_section("  steps: 10\n").unwrap().any());
+        assert!(stop_section("  cycle: true\n").unwrap().any());
+
+        let bad_sections = [
+            "  seconds: 0\n", "  seconds: -1\n", "  seconds: .inf\n", "  seconds: .nan\n", "  mean: a > 1\n",
+            "  mean: [r > 1, 2]\n", "  static: 1\n", "  forever: true\n",
+        ];
+        for bad in bad_sections {
+            assert!(matches!(stop_section(bad), Err(ProgramError::WrongType { .. })), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn mean_thresholds() {
+        let above = threshold(" g>0.25 ");
+        assert_eq!(above, MeanThreshold { channel: 1, above: true, value: 0.25 });
+        assert_eq!(above.to_string(), "g > 0.25");
+        assert!(above.holds(0.3) && !above.holds(0.25));
+        let below = threshold("b < 5e-1");
+        assert_eq!(below.to_string(), "b < 0.5");
+        assert!(below.holds(0.) && !below.holds(0.5));
+        for bad in ["r", "r = 1", "a > 1", "r > x", "r > 1 < 2"] {
+            assert!(bad.parse::<MeanThreshold>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn exit_codes() {
+        let stops = [
+            Stop::Population { live: 0 },
+            Stop::Mean { threshold: threshold("r > 0.5"), mean: 0.75 },
+            Stop::Static { since: 3 },
+            Stop::Cycle(Cycle { period: 2, since: 4 }),
+            Stop::Time { seconds: 2. },
+        ];
+        let codes: Vec<i32> = stops.iter().map(Stop::exit_code).collect();
+        assert_eq!(codes, [3, 4, 5, 6, 7]);
+        let messages: Vec<String> = stops.iter().map(Stop::to_string).collect();
+        assert_eq!(
+            messages,
+            [
+                "population 0",
+                "mean r > 0.5 (is 0.7500)",
+                "static since step 3",
+                "repeats every 2 steps since step 4",
+                "time budget of 2s used up",
+            ]
+        );
+    }
+
+    #[test]
+    fn population() {
+        let conditions = StopConditions { population: Some(1), ..StopConditions::default() };
+        let mut two = board(1.);
+        two.set(1, 1, [0.5, 0., 0., 1.]);
+        assert_eq!(run(conditions.clone(), &[two.clone(), board(0.49), two]), Some((1, Stop::Population { live: 0 })));
+        assert_eq!(run(conditions, &[board(1.)]), Some((0, Stop::Population { live: 1 })));
+    }
+
+    #[test]
+    fn means() {
+        let means = vec![threshold("r > 0.2"), threshold("g < 0.5")];
+        let conditions = StopConditions { mean: means, ..StopConditions::default() };
+        let mut green = board(0.);
+        green.set(0, 0, [0., 1., 0., 1.]);
+        green.set(1, 0, [0., 1., 0., 1.]);
+        green.set(0, 1, [0., 1., 0., 1.]);
+        // Red averages 0.25 on `board(1.)`, green 0 on both boards.
+        let found = run(conditions.clone(), &[green.clone(), board(1.)]);
+        assert_eq!(found, Some((1, Stop::Mean { threshold: threshold("r > 0.2"), mean: 0.25 })));
+        green.set(1, 1, [0., 1., 0., 1.]);
+        let conditions = StopConditions { mean: vec![threshold("g < 0.5")], ..conditions };
+        let found = run(conditions, &[green, board(0.)]);
+        assert_eq!(found, Some((1, Stop::Mean { threshold: threshold("g < 0.5"), mean: 0. })));
+    }
+
+    #[test]
+    fn static_and_cycle() {
+        let static_board = StopConditions { static_board: true, ..StopConditions::default() };
+        let settles: Vec<CpuBoard> = [0., 1., 2., 2.].map(board).into();
+        assert_eq!(run(static_board.clone(), &settles), Some((3, Stop::Static { since: 2 })));
+        let blinks: Vec<CpuBoard> = [0., 1., 2., 1., 2.].map(board).into();
+        assert_eq!(run(static_board.clone(), &blinks), None);
+
+        let cycle = StopConditions { cycle: true, ..StopConditions::default() };
+        assert_eq!(run(cycle.clone(), &blinks), Some((3, Stop::Cycle(Cycle { period: 2, since: 1 }))));
+        assert_eq!(run(cycle, &settles), Some((3, Stop::Cycle(Cycle { period: 1, since: 2 }))));
+        // A period 2 board only seen every second step looks static.
+        let every = StopConditions { every: 2, ..static_board };
+        assert_eq!(run(every, &blinks[1..]), Some((2, Stop::Static { since: 0 })));
+    }
+
+    #[test]
+    fn board_conditions_only_every_few_steps() {
+        let conditions = StopConditions { population: Some(0), every: 3, ..StopConditions::default() };
+        let boards: Vec<CpuBoard> = [1., 0., 0., 1., 0., 0., 0.].map(board).into();
+        assert_eq!(run(conditions, &boards), Some((4 + 2, Stop::Population { live: 0 })));
+    }
+
+    #[test]
+    fn first_condition_wins() {
+        let everything = StopConditions {
+            population: Some(0),
+            mean: vec![threshold("r < 0.5")],
+            static_board: true,
+            cycle: true,
+            ..StopConditions::default()
+        };
+        let boards = [board(0.), board(0.)];
+        assert_eq!(run(everything.clone(), &boards), Some((0, Stop::Population { live: 0 })));
+        let no_population = StopConditions { population: None, ..everything.clone() };
+        assert!(matches!(run(no_population, &boards), Some((0, Stop::Mean { .. }))));
+        let board_repeats = StopConditions { population: None, mean: Vec::new(), ..everything.clone() };
+        assert_eq!(run(board_repeats, &boards), Some((1, Stop::Cycle(Cycle { period: 1, since: 0 }))));
+        // The time budget is checked before the board, on every step.
+        let timed = StopConditions { seconds: Some(1e-9), every: 100, ..everything };
+        assert_eq!(run(timed, &[board(1.), board(1.)]), Some((0, Stop::Time { seconds: 1e-9 })));
+    }
+
+    #[test]
+    fn long_time_budgets() {
+        let conditions = stop_section("  seconds: 1e30\n").unwrap();
+        assert_eq!(conditions.seconds, Some(1e30));
+        assert_eq!(run(conditions, &[board(1.), board(0.)]), None);
+        let longest = StopConditions { seconds: Some(f32::MAX), ..StopConditions::default() };
+        assert_eq!(run(longest, &[board(1.)]), None);
+    }
+
+    #[test]
+    fn reset_forgets_boards_before_a_reseed() {
+        let backend = CpuBackend::default();
+        let conditions = StopConditions { static_board: true, ..StopConditions::default() };
+        let mut check = StopCheck::new(conditions);
+        assert_eq!(check.check(0, &backend, &board(1.)), None);
+        check.reset();
+        // The new start board happens to be the old one, it isn't static yet.
+        assert_eq!(check.check(1, &backend, &board(1.)), None);
+        assert_eq!(check.check(2, &backend, &board(1.)), Some(Stop::Static { since: 1 }));
+    }
+}
@@ -0,0 +1,351 @@
+//! `sweep` subcommand: runs a program once for every combination of values
+//! of some of its `params`, or of `float NAME = literal;` constants in its
+//! `fun`, and lays the results out on a labelled contact sheet.
+
+use std::{
+    error::Error,
+    fmt::Write as _,
+    fs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use gpu_convolution::{
+    backend::{Backend, CpuBackend, GlBackend},
+    board::{random_seed, InitialBoard},
+    formats::{self, Format},
+    program::ProgramSpec,
+    sheet::ContactSheet,
+};
+
+use crate::run::BackendKind;
+
+const USAGE: &str = "usage: gpu_convolution sweep <program.yaml> --param NAME=VALUES... [options]
+  --param NAME=VALUES
+                     a parameter from `params:`, or a `float NAME = literal;` constant in `fun`,
+                     and the values to try, as a list
+                     (f=0.02,0.03,0.04) or FROM:TO:COUNT (f=0.02:0.06:5); can be repeated, every
+                     combination is run
+  --steps N          steps per combination (default `stop: steps:` in the program, or 100)
+  --init INIT        as for `run`, every combination starts from the same board
+  --fit FIT, --map MAP, --seed N
+                     as for `run`
+  --out DIR          directory for the boards, sheet.png and index.csv (default <program>_sweep)
+  --format FORMAT    png (default), pfm or npy for the board of each combination
+  --columns N        tiles per row of the contact sheet (default the number of values of the
+                     last --param, or a square grid for a single one)
+  --tile-scale N     shrink tiles N times (default to at most 256 cells wide)
+  --backend NAME     cpu or gl (default cpu, gl needs a windowing system)";
+
+/// Tiles are shrunk to at most this wide unless `--tile-scale` is given.
+const TILE_WIDTH: u32 = 256;
+
+/// A parameter or constant in `fun` and the values it takes.
+struct SweepParam {
+    name: String,
+    values: Vec<f32>,
+}
+
+impl FromStr for SweepParam {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid `--param {}`, expected e.g. `f=0.02,0.03` or `f=0.02:0.06:5`", s);
+        let (name, values) = s.split_once('=').ok_or_else(error)?;
+        let values = match values.split(':').collect::<Vec<_>>()[..] {
+            [from, to, count] => {
+                let (from, to): (f64, f64) = (from.parse().map_err(|_| error())?, to.parse().map_err(|_| error())?);
+                let count: u32 = count.parse().ok().filter(|n| *n > 0).ok_or_else(error)?;
+                let step = if count > 1 { (to - from) / (count - 1) as f64 } else { 0. };
+                // Computed in f64, so that e.g. 0.02 + 0.01 prints as 0.03.
+                (0..count).map(|i| (from + step * i as f64) as f32).collect()
+            }
+            [list] => list.split(',').map(|v| v.trim().parse()).collect::<Result<_, _>>().map_err(|_| error())?,
+            _ => return Err(error()),
+        };
+        Ok(Self {
+            name: name.trim().to_string(),
+            values,
+        })
+    }
+}
+
+struct SweepArgs {
+    program: PathBuf,
+    params: Vec<SweepParam>,
+    steps: Option<u32>,
+    init: InitialBoard,
+    seed: Option<u64>,
+    out: Option<PathBuf>,
+    format: Format,
+    columns: Option<u32>,
+    tile_scale: Option<u32>,
+    backend: BackendKind,
+}
+
+impl SweepArgs {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut program = None;
+        let mut sweep = SweepArgs {
+            program: PathBuf::new(),
+            params: Vec::new(),
+            steps: None,
+            init: InitialBoard::Program,
+            seed: None,
+            out: None,
+            format: Format::Png,
+            columns: None,
+            tile_scale: None,
+            backend: BackendKind::Cpu,
+        };
+
+        let (mut fit, mut map) = (None, None);
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if program.replace(PathBuf::from(arg)).is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                continue;
+            }
+            if arg == "--seed" {
+                sweep.seed = Some(crate::parse_seed(args.next())?);
+                continue;
+            }
+            let value = args.next().ok_or(format!("`{}` needs a value", arg))?;
+            let number = || crate::parse_positive(arg, Some(value));
+            match arg.as_str() {
+                "--param" => {
+                    let param: SweepParam = value.parse()?;
+                    if sweep.params.iter().any(|p| p.name == param.name) {
+                        return Err(format!("`{}` is swept twice", param.name));
+                    }
+                    sweep.params.push(param);
+                }
+                "--steps" => sweep.steps = Some(number()?),
+                "--init" => sweep.init = value.parse().unwrap(),
+                "--fit" => fit = Some(crate::parse_fit(Some(value))?),
+                "--map" => map = Some(crate::parse_map(Some(value))?),
+                "--out" => sweep.out = Some(PathBuf::from(value)),
+                "--format" => sweep.format = value.parse()?,
+                "--columns" => sweep.columns = Some(number()?),
+                "--tile-scale" => sweep.tile_scale = Some(number()?),
+                "--backend" => sweep.backend = value.parse()?,
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+        }
+
+        crate::set_image_options(&mut sweep.init, fit, map)?;
+        sweep.program = program.ok_or("missing program file")?;
+        if sweep.params.is_empty() {
+            return Err("nothing to sweep, add `--param NAME=VALUES`".into());
+        }
+        Ok(sweep)
+    }
+
+    fn name(&self) -> String {
+        self.program
+            .file_stem()
+            .map_or("board".into(), |stem| stem.to_string_lossy().into_owned())
+    }
+
+    /// Values of every combination, the last parameter changing fastest.
+    fn combinations(&self) -> Vec<Vec<f32>> {
+        let mut combinations = vec![Vec::new()];
+        for param in &self.params {
+            combinations = combinations
+                .iter()
+                .flat_map(|head| {
+                    param.values.iter().map(move |v| {
+                        let mut values = head.clone();
+                        values.push(*v);
+                        values
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+}
+
+/// Runs the subcommand with the arguments following `sweep`, returning the
+/// exit code.
+pub fn main(args: &[String]) -> i32 {
+    let args = match SweepArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let spec = crate::read_spec(&args.program.to_string_lossy());
+
+    let result = match args.backend {
+        BackendKind::Cpu => sweep(&CpuBackend::default(), &spec, &args),
+        BackendKind::Gl => GlBackend::headless().and_then(|backend| sweep(&backend, &spec, &args)),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn sweep<B: Backend>(backend: &B, spec: &ProgramSpec, args: &SweepArgs) -> Result<(), Box<dyn Error>> {
+    for param in &args.params {
+        if spec.params.get(&param.name).is_none() && !spec.clone().set_constant(&param.name, 0.) {
+            return Err(format!(
+                "`{}` is neither in `params:` nor a `float {} = <number>;` in `fun`",
+                param.name, param.name
+            )
+            .into());
+        }
+    }
+    let seed = args.seed.or(spec.seed).unwrap_or_else(random_seed);
+    if args.init.uses_seed(spec) {
+        eprintln!("seed: {}", seed);
+    }
+    let start = args.init.board(spec, seed)?;
+    let steps = args.steps.or(spec.stop.steps).unwrap_or(100);
+
+    let name = args.name();
+    let out = args.out.clone().unwrap_or_else(|| PathBuf::from(format!("{}_sweep", name)));
+    fs::create_dir_all(&out).map_err(|e| format!("{}: {}", out.display(), e))?;
+
+    let combinations = args.combinations();
+    let scale = args.tile_scale.unwrap_or(spec.width.div_ceil(TILE_WIDTH));
+    let (tile_width, tile_height) = formats::scaled_size(&start, scale);
+    if tile_width == 0 || tile_height == 0 {
+        return Err("`--tile-scale` would leave empty tiles".into());
+    }
+    let columns = args
+        .columns
+        .unwrap_or(if args.params.len() > 1 { args.params.last().unwrap().values.len() as u32 } else { 0 });
+    // A single parameter goes into a roughly square grid.
+    let columns = if columns > 0 { columns } else { (combinations.len() as f64).sqrt().ceil() as u32 };
+    let labels: Vec<Vec<String>> = combinations
+        .iter()
+        .map(|values| args.params.iter().zip(values).map(|(p, v)| format!("{}={}", p.name, v)).collect())
+        .collect();
+    let mut sheet = ContactSheet::new(combinations.len() as u32, columns, tile_width, tile_height, 1);
+    // Labels that don't fit on one line get a line per parameter.
+    let one_line = labels.iter().all(|lines| lines.join(" ").len() <= sheet.label_width());
+    if !one_line {
+        sheet = ContactSheet::new(combinations.len() as u32, columns, tile_width, tile_height, args.params.len() as u32);
+    }
+
+    let mut index = String::from("tile,file,column,row");
+    for param in &args.params {
+        write!(index, ",{}", param.name).unwrap();
+    }
+    index.push('\n');
+
+    for (i, values) in combinations.iter().enumerate() {
+        let mut spec = spec.clone();
+        for (param, value) in args.params.iter().zip(values) {
+            if !spec.params.set(&param.name, *value) {
+                spec.set_constant(&param.name, *value);
+            }
+        }
+        let label = labels[i].join(" ");
+        eprintln!("{}/{}: {}", i + 1, combinations.len(), label);
+
+        let program = backend.program(&spec)?;
+        let mut board = backend.upload(&start);
+        for _ in 0..steps {
+            program.step(&mut board);
+        }
+        let board = backend.download(&board);
+
+        let file = format!("{}_{:03}.{}", name, i, args.format.extension());
+        let path = out.join(&file);
+        formats::save(&path, &board).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let label = if one_line { vec![label] } else { labels[i].clone() };
+        sheet.place(i as u32, &formats::rgb8(&board, scale), &label);
+
+        let (column, row) = sheet.position(i as u32);
+        write!(index, "{},{},{},{}", i, file, column, row).unwrap();
+        for value in values {
+            write!(index, ",{}", value).unwrap();
+        }
+        index.push('\n');
+    }
+
+    let path = out.join("sheet.png");
+    sheet.write_png(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let path = out.join("index.csv");
+    fs::write(&path, index).map_err(|e| format!("{}: {}", path.display(), e))?;
+    eprintln!("wrote {} boards, sheet.png and index.csv to {}", combinations.len(), out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn values(s: &str) -> Result<Vec<f32>, String> {
+        s.parse::<SweepParam>().map(|param| param.values)
+    }
+
+    #[test]
+    fn parse_values() {
+        let param: SweepParam = " f =0.02, 0.03,0.04".parse().unwrap();
+        assert_eq!((param.name.as_str(), param.values), ("f", vec![0.02, 0.03, 0.04]));
+        assert_eq!(values("f=0.02:0.06:5"), Ok(vec![0.02, 0.03, 0.04, 0.05, 0.06]));
+        assert_eq!(values("f=1:0:3"), Ok(vec![1., 0.5, 0.]));
+        assert_eq!(values("f=7:9:1"), Ok(vec![7.]));
+        assert_eq!(values("f=-1"), Ok(vec![-1.]));
+        for bad in ["f", "f=", "f=1,,2", "f=a", "f=1:2", "f=1:2:0", "f=1:2:-1", "f=1:2:x", "f=1:x:2", "f=1:2:3:4"] {
+            let error = format!("invalid `--param {}`, expected e.g. `f=0.02,0.03` or `f=0.02:0.06:5`", bad);
+            assert_eq!(values(bad), Err(error));
+        }
+    }
+
+    fn args(args: &[&str]) -> Result<SweepArgs, String> {
+        SweepArgs::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn combinations_vary_the_last_param_fastest() {
+        let sweep = args(&["p.yaml", "--param", "a=1,2", "--param", "b=10:30:3"]).unwrap();
+        let expected = [[1., 10.], [1., 20.], [1., 30.], [2., 10.], [2., 20.], [2., 30.]];
+        assert_eq!(sweep.combinations(), expected);
+        assert_eq!(args(&["p.yaml", "--param", "a=1"]).unwrap().combinations(), [[1.]]);
+        assert_eq!(args(&["p.yaml", "--param", "a=1", "--param", "a=2"]).err().unwrap(), "`a` is swept twice");
+        assert_eq!(args(&["p.yaml"]).err().unwrap(), "nothing to sweep, add `--param NAME=VALUES`");
+    }
+
+    #[test]
+    fn boards_sheet_and_index() {
+        let dir = env::temp_dir().join(format!("gpu_convolution_sweep_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("scale.yaml");
+        let src = "screen: [4, 4]\ntype: val\nkernel: [[1]]\nparams: {f: 0.5}\n\
+                   fun: >\n  float g = 1.;\n  return x * f * g;\ninit:\n  - fill: {r: 1}\n";
+        fs::write(&program, src).unwrap();
+        let out = dir.join("out");
+        let sweep = [program.to_str().unwrap(), "--param", "f=0.5,1", "--param", "g=1:3:3", "--steps", "1"];
+        let options = ["--format", "pfm", "--out", out.to_str().unwrap()];
+        let code = main(&[&sweep[..], &options].concat().iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+        assert_eq!(code, 0);
+
+        let index = fs::read_to_string(out.join("index.csv")).unwrap();
+        let expected = "tile,file,column,row,f,g\n\
+                        0,scale_000.pfm,0,0,0.5,1\n1,scale_001.pfm,1,0,0.5,2\n2,scale_002.pfm,2,0,0.5,3\n\
+                        3,scale_003.pfm,0,1,1,1\n4,scale_004.pfm,1,1,1,2\n5,scale_005.pfm,2,1,1,3\n";
+        assert_eq!(index, expected);
+        for (i, value) in [0.5, 1., 1.5, 1., 2., 3.].into_iter().enumerate() {
+            let board = formats::load(&out.join(format!("scale_{:03}.pfm", i))).unwrap();
+            assert_eq!(board.get(2, 1)[0], value, "tile {}", i);
+        }
+        // Three columns of 4x4 tiles, labels too long for one line get two.
+        let sheet = formats::load(&out.join("sheet.png")).unwrap();
+        let expected = ContactSheet::new(6, 3, 4, 4, 2).size();
+        assert_eq!((sheet.width, sheet.height), expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
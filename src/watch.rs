@@ -0,0 +1,53 @@
+//! Polling a file for changes, so the viewer can reload a program while it
+//! runs.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the modification time is looked up.
+const INTERVAL: Duration = Duration::from_millis(250);
+
+pub struct FileWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl FileWatch {
+    /// Starts watching `path` from its current modification time.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            checked: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file was modified since the last change reported, looked
+    /// up at most every `INTERVAL`. A file that can't be read, e.g. while an
+    /// editor replaces it, counts as unchanged.
+    pub fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+        match modified(&self.path) {
+            Some(modified) if self.modified != Some(modified) => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,127 @@
+//! Runs every program in `programs/` on `CpuBackend` from a fixed pseudo-random
+//! board and compares the result with the board stored in `tests/golden/`.
+//!
+//! After an intended change in behaviour, regenerate the goldens with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and review the new files.
+
+use std::{env, fs, path::Path};
+
+use gpu_convolution::{
+    backend::{Backend, CpuBackend},
+    cpu::CpuBoard,
+    formats,
+    program::{ProgramKind, ProgramSpec},
+};
+
+const WIDTH: u32 = 32;
+const HEIGHT: u32 = 24;
+const STEPS: u32 = 16;
+const TOLERANCE: f32 = 1e-4;
+
+/// SplitMix64, so the starting boards don't depend on the `rand` version.
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// Binary red for `val` programs, which are mostly cellular automata. For
+/// `rgb` and `sym` red close to 1 with sparse green spots, the usual start for
+/// reaction-diffusion.
+fn initial_board(spec: &ProgramSpec) -> CpuBoard {
+    let mut rng = SplitMix(0x5eed);
+    let mut board = CpuBoard::new(spec.width, spec.height);
+    for pixel in board.data.chunks_exact_mut(4) {
+        match spec.kind {
+            ProgramKind::Val { .. } => pixel[0] = if rng.next() > 0.5 { 1. } else { 0. },
+            ProgramKind::Rgb { .. } | ProgramKind::Sym { .. } => {
+                pixel[0] = 1. - 0.2 * rng.next();
+                pixel[1] = if rng.next() > 0.9 { 1. } else { 0. };
+            }
+        }
+    }
+    board
+}
+
+fn run(path: &Path) -> CpuBoard {
+    let mut spec: ProgramSpec = fs::read_to_string(path).unwrap().parse().unwrap();
+    spec.width = WIDTH;
+    spec.height = HEIGHT;
+
+    let backend = CpuBackend::default();
+    let program = backend.program(&spec).unwrap();
+    let mut board = backend.upload(&initial_board(&spec));
+    for _ in 0..STEPS {
+        program.step(&mut board);
+    }
+    backend.download(&board)
+}
+
+/// Returns a description of the first mismatching cell, if any. Alpha is not
+/// stored in PFM and not compared.
+fn compare(actual: &CpuBoard, golden: &CpuBoard) -> Option<String> {
+    if (actual.width, actual.height) != (golden.width, golden.height) {
+        return Some(format!(
+            "board is {}x{}, golden is {}x{}",
+            actual.width, actual.height, golden.width, golden.height
+        ));
+    }
+    for y in 0..actual.height {
+        for x in 0..actual.width {
+            let (a, g) = (actual.get(x, y), golden.get(x, y));
+            for c in 0..3 {
+                if (a[c] - g[c]).abs() > TOLERANCE * g[c].abs().max(1.) {
+                    return Some(format!("({}, {}) is {:?}, golden is {:?}", x, y, &a[..3], &g[..3]));
+                }
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn bundled_programs_match_goldens() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let golden_dir = Path::new("tests/golden");
+
+    let mut programs: Vec<_> = fs::read_dir("programs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    let mut failures = Vec::new();
+    for path in &programs {
+        let name = path.file_stem().unwrap().to_string_lossy();
+        let golden_path = golden_dir.join(format!("{}.pfm", name));
+        let board = run(path);
+
+        if update {
+            fs::create_dir_all(golden_dir).unwrap();
+            formats::save(&golden_path, &board).unwrap();
+            continue;
+        }
+        match formats::load(&golden_path) {
+            Ok(golden) => {
+                if let Some(mismatch) = compare(&board, &golden) {
+                    failures.push(format!("{}: {}", name, mismatch));
+                }
+            }
+            Err(e) => failures.push(format!("{}: {}: {}", name, golden_path.display(), e)),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "boards differ from goldens (UPDATE_GOLDEN=1 regenerates them):\n{}",
+        failures.join("\n")
+    );
+}
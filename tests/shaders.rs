@@ -0,0 +1,109 @@
+//! Compares the compute shader generated for every program in `programs/`
+//! with the source stored in `tests/snapshots/`, so changes to shader
+//! generation show up as diffs of those files.
+//!
+//! After an intended change, regenerate them with
+//! `UPDATE_SNAPSHOTS=1 cargo test --test shaders` and review the diff.
+
+use std::{env, fs, path::Path};
+
+use gpu_convolution::program::{
+    shader::{self, stats_shader_src, swap_shader_src},
+    shader_source, ProgramError, ProgramSpec,
+};
+
+fn check(name: &str, source: &str, update: bool, failures: &mut Vec<String>) {
+    let snapshot_dir = Path::new("tests/snapshots");
+    let path = snapshot_dir.join(format!("{}.comp", name));
+    if update {
+        fs::create_dir_all(snapshot_dir).unwrap();
+        fs::write(&path, source).unwrap();
+        return;
+    }
+    match fs::read_to_string(&path) {
+        Ok(snapshot) if snapshot == source => (),
+        Ok(snapshot) => {
+            let line = snapshot
+                .lines()
+                .zip(source.lines())
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| snapshot.lines().count().min(source.lines().count()));
+            failures.push(format!("{}: differs from line {}", name, line + 1));
+        }
+        Err(e) => failures.push(format!("{}: {}: {}", name, path.display(), e)),
+    }
+}
+
+fn programs() -> Vec<std::path::PathBuf> {
+    let mut programs: Vec<_> = fs::read_dir("programs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "yaml"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+    programs
+}
+
+fn validate(src: &str) -> Result<(), ProgramError> {
+    shader::validate(&src.parse().unwrap())
+}
+
+#[test]
+fn generated_shaders_match_snapshots() {
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let programs = programs();
+
+    let mut failures = Vec::new();
+    for path in &programs {
+        let spec: ProgramSpec = fs::read_to_string(path).unwrap().parse().unwrap();
+        let name = path.file_stem().unwrap().to_string_lossy();
+        check(&name, &shader_source(&spec), update, &mut failures);
+    }
+    check("swap", &swap_shader_src(), update, &mut failures);
+    check("stats", &stats_shader_src(), update, &mut failures);
+
+    assert!(
+        failures.is_empty(),
+        "shaders differ from snapshots (UPDATE_SNAPSHOTS=1 regenerates them):\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn bundled_programs_validate() {
+    for path in programs() {
+        for (i, spec) in ProgramSpec::playlist(&fs::read_to_string(&path).unwrap()).unwrap().iter().enumerate() {
+            if let Err(e) = shader::validate(spec) {
+                panic!("{} stage {}: {}", path.display(), i + 1, e);
+            }
+        }
+    }
+}
+
+#[test]
+fn ints_are_converted_like_glsl_does() {
+    validate("screen: [4, 4]\ntype: val\nkernel: [[1]]\nfun: |\n  if (x > 1.)\n    return 1;\n  return 0;\n").unwrap();
+    validate("screen: [4, 4]\ntype: rgb\nkernel: [[1]]\nfun: return ivec3(1);\n").unwrap();
+    validate("screen: [4, 4]\ntype: val\nkernel: [[1]]\nfun: 'return x > 0.5 ? 1 : 0;'\n").unwrap();
+}
+
+#[test]
+fn errors_name_glsl_types_and_lines() {
+    let fun = |fun: &str| format!("screen: [4, 4]\ntype: val\nkernel: [[1]]\nfun: |\n  float a = 1.;\n  {}\n", fun);
+    let cases = [
+        ("return vec2(x);", "`fun` must return float, not vec2"),
+        ("vec3 v = x;\n  return v.x;", "can't assign float to a vec3 variable"),
+        ("return (vec2(x) + vec3(x)).x;", "`+` can't take vec2 and vec3"),
+        ("return y;", "Unknown variable: y"),
+    ];
+    for (body, message) in cases {
+        let expected = ProgramError::Fun { message: message.to_string(), fun_line: 2, line: Some(6) };
+        assert_eq!(validate(&fun(body)), Err(expected), "{}", body);
+    }
+    // A missing `;` shows at the closing brace after `fun`.
+    match validate(&fun("return a")) {
+        Err(ProgramError::Fun { fun_line: 2, line: Some(6), .. }) => (),
+        other => panic!("{:?}", other),
+    }
+}